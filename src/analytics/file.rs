@@ -0,0 +1,151 @@
+use serde_json;
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use analytics::{stamp, AnalyticsConfig, AnalyticsStore, Evaluation, Usage};
+use analytics::mem::MemAnalytics;
+use error::BannerError;
+use flag::FlagPath;
+use util::current_time;
+
+// Lines of the file. The first line holds when tracking started and every
+// other line is a batch of evaluations for a single path.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Line {
+    Since { since: u64 },
+    Batch { path: String, evaluations: Vec<Evaluation> },
+}
+
+// Keeps counts in memory and appends every batch to a file, which is read
+// back and compacted to one entry per bucket when opened
+#[derive(Debug)]
+pub struct FileAnalytics {
+    mem: MemAnalytics,
+    file: Mutex<File>,
+}
+
+fn write_line<W: Write>(out: &mut W, line: &Line) -> Result<(), BannerError> {
+    let json = serde_json::to_string(line)?;
+    writeln!(out, "{}", json)?;
+    Ok(())
+}
+
+impl FileAnalytics {
+    pub fn open<P: AsRef<Path>>(path: P, config: AnalyticsConfig) -> Result<FileAnalytics, BannerError> {
+        let path = path.as_ref();
+        let now = current_time();
+        let mut since = None;
+        let mut batches = vec![];
+
+        if path.exists() {
+            for line in BufReader::new(File::open(path)?).lines() {
+                match serde_json::from_str::<Line>(&line?) {
+                    Ok(Line::Since { since: at }) => since = since.or(Some(at)),
+                    Ok(Line::Batch { path, evaluations }) => batches.push((path, evaluations)),
+
+                    // A partly written last line is lost rather than failing
+                    // to start
+                    Err(_) => warn!("Skipping unreadable line in {}", path.display()),
+                }
+            }
+        }
+
+        let mem = MemAnalytics::since(config, since.unwrap_or(now));
+
+        for &(ref path, ref evaluations) in batches.iter() {
+            mem.add(path, evaluations, now)?;
+        }
+
+        // Write the compacted counts next to the file and swap them in, so
+        // a crash leaves either the old or the new file
+        let mut compact = PathBuf::from(path);
+        compact.set_extension("compact");
+
+        {
+            let mut out = File::create(&compact)?;
+            write_line(&mut out, &Line::Since { since: mem.tracked_since() })?;
+
+            for (path, evaluation) in mem.snapshot()?.into_iter() {
+                write_line(&mut out, &Line::Batch {
+                    path: path,
+                    evaluations: vec![evaluation],
+                })?;
+            }
+
+            out.sync_all()?;
+        }
+
+        fs::rename(&compact, path)?;
+
+        let file = OpenOptions::new().append(true).open(path)?;
+
+        Ok(FileAnalytics {
+            mem: mem,
+            file: Mutex::new(file),
+        })
+    }
+}
+
+impl AnalyticsStore for FileAnalytics {
+    fn record(&self, path: &FlagPath, evaluations: &[Evaluation]) -> Result<(), BannerError> {
+        let now = current_time();
+        let evaluations = stamp(evaluations, now);
+
+        {
+            let mut file = self.file.lock().map_err(|_| BannerError::AnalyticsPoisoned)?;
+            write_line(&mut *file, &Line::Batch {
+                path: path.as_ref().to_string(),
+                evaluations: evaluations.clone(),
+            })?;
+        }
+
+        self.mem.add(path.as_ref(), &evaluations, now)
+    }
+
+    fn usage(&self, path: &FlagPath, key: &str, since: u64) -> Result<Usage, BannerError> {
+        self.mem.usage(path, key, since)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+
+    #[test]
+    fn test_reloads_counts() {
+        let file = env::temp_dir().join(format!("masquerade-analytics-{}.log", current_time()));
+        let config = AnalyticsConfig {
+            bucket: 60,
+            retention: 600,
+        };
+        let path = FlagPath::new("owner", "app", "env");
+        // Not on a bucket boundary, so compaction has to keep the time
+        let at = current_time() - 60;
+        let at = at - at % 60 + 30;
+        let evaluations = vec![Evaluation {
+            key: "f1".to_string(),
+            variation: "true".to_string(),
+            count: 3,
+            at: Some(at),
+        }];
+
+        {
+            let data = FileAnalytics::open(&file, config.clone()).unwrap();
+            data.record(&path, &evaluations).unwrap();
+            data.record(&path, &evaluations).unwrap();
+        }
+
+        let data = FileAnalytics::open(&file, config).unwrap();
+        let _ = fs::remove_file(&file);
+
+        let usage = data.usage(&path, "f1", 0).unwrap();
+        assert_eq!(usage.total, 6);
+        assert_eq!(usage.last_evaluated, Some(at));
+    }
+}
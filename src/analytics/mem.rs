@@ -0,0 +1,216 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::RwLock;
+
+use analytics::{stamp, AnalyticsConfig, AnalyticsStore, Bucket, Evaluation, Usage};
+use error::BannerError;
+use flag::FlagPath;
+use util::current_time;
+
+#[derive(Debug, Default)]
+struct Series {
+    buckets: BTreeMap<u64, BTreeMap<String, u64>>,
+    last: Option<u64>,
+}
+
+// Counts evaluations per flag in fixed time buckets. Buckets older than the
+// retention are dropped as new evaluations come in.
+#[derive(Debug)]
+pub struct MemAnalytics {
+    config: AnalyticsConfig,
+    since: u64,
+    series: RwLock<HashMap<(String, String), Series>>,
+}
+
+impl MemAnalytics {
+    pub fn new(config: AnalyticsConfig) -> MemAnalytics {
+        MemAnalytics::since(config, current_time())
+    }
+
+    pub fn since(config: AnalyticsConfig, since: u64) -> MemAnalytics {
+        MemAnalytics {
+            config: config,
+            since: since,
+            series: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn tracked_since(&self) -> u64 {
+        self.since
+    }
+
+    // Adds evaluations that already have their time set
+    pub fn add(&self, path: &str, evaluations: &[Evaluation], now: u64) -> Result<(), BannerError> {
+        let oldest = now.saturating_sub(self.config.retention);
+        let mut all = self.series.write().map_err(|_| BannerError::AnalyticsPoisoned)?;
+
+        for evaluation in evaluations.iter() {
+            let at = evaluation.at.unwrap_or(now);
+
+            if at < oldest {
+                continue;
+            }
+
+            let series = all.entry((path.to_string(), evaluation.key.clone()))
+                .or_insert(Series::default());
+
+            let count = series
+                .buckets
+                .entry(self.config.bucket_start(at))
+                .or_insert(BTreeMap::new())
+                .entry(evaluation.variation.clone())
+                .or_insert(0);
+            *count = count.saturating_add(evaluation.count);
+
+            series.last = series.last.max(Some(at));
+
+            let expired = series
+                .buckets
+                .keys()
+                .take_while(|&&start| start < self.config.bucket_start(oldest))
+                .cloned()
+                .collect::<Vec<u64>>();
+
+            for start in expired.into_iter() {
+                series.buckets.remove(&start);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Every bucket as an evaluation at the start of the bucket, for writing
+    // out a compact copy of the counts. The bucket holding the last
+    // evaluation keeps its time so that it survives a reload.
+    pub fn snapshot(&self) -> Result<Vec<(String, Evaluation)>, BannerError> {
+        let all = self.series.read().map_err(|_| BannerError::AnalyticsPoisoned)?;
+        let mut snapshot = vec![];
+
+        for (&(ref path, ref key), series) in all.iter() {
+            for (start, counts) in series.buckets.iter() {
+                let at = match series.last {
+                    Some(last) if self.config.bucket_start(last) == *start => last,
+                    _ => *start,
+                };
+
+                for (variation, count) in counts.iter() {
+                    snapshot.push((
+                        path.clone(),
+                        Evaluation {
+                            key: key.clone(),
+                            variation: variation.clone(),
+                            count: *count,
+                            at: Some(at),
+                        },
+                    ));
+                }
+            }
+        }
+
+        Ok(snapshot)
+    }
+}
+
+impl AnalyticsStore for MemAnalytics {
+    fn record(&self, path: &FlagPath, evaluations: &[Evaluation]) -> Result<(), BannerError> {
+        let now = current_time();
+        self.add(path.as_ref(), &stamp(evaluations, now), now)
+    }
+
+    fn usage(&self, path: &FlagPath, key: &str, since: u64) -> Result<Usage, BannerError> {
+        let all = self.series.read().map_err(|_| BannerError::AnalyticsPoisoned)?;
+        let mut usage = Usage {
+            bucket: self.config.bucket,
+            total: 0,
+            variations: BTreeMap::new(),
+            buckets: vec![],
+            last_evaluated: None,
+            tracked_since: self.since,
+        };
+
+        if let Some(series) = all.get(&(path.as_ref().to_string(), key.to_string())) {
+            for (start, counts) in series.buckets.range(self.config.bucket_start(since)..) {
+                for (variation, count) in counts.iter() {
+                    usage.total = usage.total.saturating_add(*count);
+
+                    let total = usage.variations.entry(variation.clone()).or_insert(0);
+                    *total = total.saturating_add(*count);
+                }
+
+                usage.buckets.push(Bucket {
+                    start: *start,
+                    counts: counts.clone(),
+                });
+            }
+
+            usage.last_evaluated = series.last;
+        }
+
+        Ok(usage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> AnalyticsConfig {
+        AnalyticsConfig {
+            bucket: 60,
+            retention: 600,
+        }
+    }
+
+    fn eval(key: &str, variation: &str, count: u64, at: u64) -> Evaluation {
+        Evaluation {
+            key: key.to_string(),
+            variation: variation.to_string(),
+            count: count,
+            at: Some(at),
+        }
+    }
+
+    #[test]
+    fn test_counts_per_bucket_and_variation() {
+        let data = MemAnalytics::since(config(), 0);
+        let path = FlagPath::new("owner", "app", "env");
+
+        let _ = data.add(path.as_ref(), &[eval("f1", "true", 2, 1000), eval("f1", "false", 1, 1010)], 1100);
+        let _ = data.add(path.as_ref(), &[eval("f1", "true", 3, 1090), eval("f2", "true", 1, 1090)], 1100);
+
+        let usage = data.usage(&path, "f1", 0).unwrap();
+
+        assert_eq!(usage.total, 6);
+        assert_eq!(usage.variations.get("true"), Some(&5));
+        assert_eq!(usage.buckets.len(), 2);
+        assert_eq!(usage.buckets[0].start, 960);
+        assert_eq!(usage.last_evaluated, Some(1090));
+    }
+
+    #[test]
+    fn test_drops_expired_evaluations() {
+        let data = MemAnalytics::since(config(), 0);
+        let path = FlagPath::new("owner", "app", "env");
+
+        let _ = data.add(path.as_ref(), &[eval("f1", "true", 1, 100)], 200);
+        let _ = data.add(path.as_ref(), &[eval("f1", "true", 1, 1000), eval("f1", "true", 1, 100)], 1000);
+
+        let usage = data.usage(&path, "f1", 0).unwrap();
+
+        assert_eq!(usage.total, 1);
+        assert_eq!(usage.last_evaluated, Some(1000));
+    }
+
+    #[test]
+    fn test_saturates_counts() {
+        let data = MemAnalytics::since(config(), 0);
+        let path = FlagPath::new("owner", "app", "env");
+
+        let _ = data.add(path.as_ref(), &[eval("f1", "true", u64::max_value(), 1000), eval("f1", "true", 1, 1000)], 1000);
+        let _ = data.add(path.as_ref(), &[eval("f1", "false", 1, 1000)], 1000);
+
+        let usage = data.usage(&path, "f1", 0).unwrap();
+
+        assert_eq!(usage.variations.get("true"), Some(&u64::max_value()));
+        assert_eq!(usage.total, u64::max_value());
+    }
+}
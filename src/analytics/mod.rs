@@ -0,0 +1,88 @@
+use std::collections::BTreeMap;
+
+use error::BannerError;
+use flag::FlagPath;
+use stale::{Evaluations, Tracking};
+use util::{current_time, env_or};
+
+pub mod file;
+pub mod mem;
+
+const VARIATION_OFF: &'static str = "off";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnalyticsConfig {
+    pub bucket: u64,
+    pub retention: u64,
+}
+
+impl AnalyticsConfig {
+    pub fn from_env() -> AnalyticsConfig {
+        AnalyticsConfig {
+            bucket: env_or("ANALYTICS_BUCKET_SECS", 3600u64).max(60),
+            retention: env_or("ANALYTICS_RETENTION_DAYS", 30u64).saturating_mul(24 * 60 * 60),
+        }
+    }
+
+    pub fn bucket_start(&self, at: u64) -> u64 {
+        at - at % self.bucket
+    }
+}
+
+// A number of evaluations of one flag that returned the same variation. SDKs
+// send these in batches and may leave out the time, in which case the time
+// the batch was received is used.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Evaluation {
+    pub key: String,
+    pub variation: String,
+    pub count: u64,
+    #[serde(default)]
+    pub at: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Bucket {
+    pub start: u64,
+    pub counts: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Usage {
+    pub bucket: u64,
+    pub total: u64,
+    pub variations: BTreeMap<String, u64>,
+    pub buckets: Vec<Bucket>,
+    pub last_evaluated: Option<u64>,
+    pub tracked_since: u64,
+}
+
+pub trait AnalyticsStore: Send + Sync {
+    fn record(&self, path: &FlagPath, evaluations: &[Evaluation]) -> Result<(), BannerError>;
+    fn usage(&self, path: &FlagPath, key: &str, since: u64) -> Result<Usage, BannerError>;
+}
+
+// Flags only hold booleans, so these are the only variations they can serve
+pub fn is_variation(name: &str) -> bool {
+    name == "true" || name == "false" || name == VARIATION_OFF
+}
+
+// Fills in missing times and keeps clients from counting into the future
+pub fn stamp(evaluations: &[Evaluation], now: u64) -> Vec<Evaluation> {
+    evaluations
+        .iter()
+        .map(|evaluation| Evaluation {
+            at: Some(evaluation.at.unwrap_or(now).min(now)),
+            ..evaluation.clone()
+        })
+        .collect()
+}
+
+impl Evaluations for Box<AnalyticsStore> {
+    fn tracking(&self, path: &FlagPath, key: &str) -> Option<Tracking> {
+        self.usage(path, key, current_time()).ok().map(|usage| Tracking {
+            since: usage.tracked_since,
+            last_evaluated: usage.last_evaluated,
+        })
+    }
+}
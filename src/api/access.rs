@@ -0,0 +1,124 @@
+use actix_web::HttpRequest;
+
+use api::State;
+use api::error::APIError;
+use api::team::TEAM_PATH;
+use grant::{Grant, Role};
+use user::User;
+
+// Requests act on the caller's own apps unless another owner is named
+pub fn owner<S>(req: &HttpRequest<S>, user: &User) -> String {
+    req.query()
+        .get("owner")
+        .map(|owner| owner.to_string())
+        .unwrap_or(user.uuid.clone())
+}
+
+// Resolves the role of a user on an app, or on a single environment of it
+// when env is given. Admins and owners hold the owner role everywhere. When
+// the app belongs to a team its members start from their team role. Users
+// then get the highest of that and their app wide and environment grants.
+pub fn role_for(
+    state: &State,
+    user: &User,
+    owner: &str,
+    app: &str,
+    env: Option<&str>,
+) -> Result<Option<Role>, APIError> {
+    if user.is_admin() || user.uuid == owner {
+        return Ok(Some(Role::Owner));
+    }
+
+    let mut role = state
+        .teams()
+        .get(&TEAM_PATH.to_string(), owner)
+        .map_err(APIError::read)?
+        .and_then(|team| team.role_of(&user.uuid));
+
+    let path = Grant::store_path(owner, app);
+    let mut keys = vec![Grant::store_key(&user.uuid, None)];
+
+    if let Some(env) = env {
+        keys.push(Grant::store_key(&user.uuid, Some(env)));
+    }
+
+    for key in keys.iter() {
+        let grant = state
+            .grants()
+            .get(&path, key.as_str())
+            .map_err(APIError::read)?;
+
+        role = ::std::cmp::max(role, grant.map(|grant| grant.role));
+    }
+
+    Ok(role)
+}
+
+pub fn require(role: Option<Role>, needed: Role) -> Result<Role, APIError> {
+    match role {
+        Some(role) if role >= needed => Ok(role),
+        _ => Err(APIError::Forbidden),
+    }
+}
+
+// Grants can only be taken away by someone above them, or by an owner
+pub fn outrank(role: Role, other: Role) -> Result<Role, APIError> {
+    if role > other || role == Role::Owner {
+        Ok(role)
+    } else {
+        Err(APIError::Forbidden)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "mem-backend")]
+    use api::state::mem_state;
+    #[cfg(feature = "mem-backend")]
+    use team::Team;
+
+    #[test]
+    fn test_only_higher_roles_remove_grants() {
+        assert!(outrank(Role::EnvAdmin, Role::Editor).is_ok());
+        assert!(outrank(Role::EnvAdmin, Role::EnvAdmin).is_err());
+        assert!(outrank(Role::EnvAdmin, Role::Owner).is_err());
+        assert!(outrank(Role::Owner, Role::Owner).is_ok());
+    }
+
+    #[cfg(feature = "mem-backend")]
+    fn user(uuid: &str) -> User {
+        User::from_session(uuid.to_string(), uuid.to_string(), false)
+    }
+
+    #[test]
+    #[cfg(feature = "mem-backend")]
+    fn test_team_members_get_their_team_role() {
+        let state = mem_state();
+        let mut team = Team::new("platform", "lead");
+        team.set_member("dev", Role::Editor);
+        state.teams().upsert(&TEAM_PATH.to_string(), &team.id, &team).unwrap();
+
+        assert_eq!(role_for(&state, &user("lead"), &team.id, "app", None).unwrap(), Some(Role::Owner));
+        assert_eq!(role_for(&state, &user("dev"), &team.id, "app", Some("prod")).unwrap(), Some(Role::Editor));
+        assert_eq!(role_for(&state, &user("other"), &team.id, "app", None).unwrap(), None);
+        assert!(require(role_for(&state, &user("other"), &team.id, "app", None).unwrap(), Role::Viewer).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "mem-backend")]
+    fn test_grants_raise_team_roles() {
+        let state = mem_state();
+        let mut team = Team::new("platform", "lead");
+        team.set_member("dev", Role::Viewer);
+        state.teams().upsert(&TEAM_PATH.to_string(), &team.id, &team).unwrap();
+
+        let grant = Grant::new("dev".into(), team.id.clone(), "app".into(), Some("staging".into()), Role::EnvAdmin);
+        state.grants().upsert(&grant.path(), &grant.key(), &grant).unwrap();
+
+        assert_eq!(role_for(&state, &user("dev"), &team.id, "app", Some("staging")).unwrap(), Some(Role::EnvAdmin));
+        assert_eq!(role_for(&state, &user("dev"), &team.id, "app", Some("prod")).unwrap(), Some(Role::Viewer));
+        assert_eq!(role_for(&state, &user("dev"), &team.id, "other", None).unwrap(), Some(Role::Viewer));
+    }
+}
@@ -1,7 +1,7 @@
-use http::StatusCode;
-use actix_web::{HttpRequest, HttpResponse, Result};
+use actix_web::{HttpRequest, HttpResponse, ResponseError, Result};
 use actix_web::middleware::{Middleware, Response, Started};
 
+use api::error::APIError;
 use user::User;
 
 #[derive(Debug)]
@@ -13,14 +13,10 @@ impl<S> Middleware<S> for Admin {
             if user.is_admin() {
                 Ok(Started::Done)
             } else {
-                Ok(Started::Response(HttpResponse::new(
-                    StatusCode::FORBIDDEN
-                )))
+                Ok(Started::Response(APIError::Forbidden.error_response()))
             }
         } else {
-            Ok(Started::Response(HttpResponse::new(
-                StatusCode::UNAUTHORIZED
-            )))
+            Ok(Started::Response(APIError::Unauthorized.error_response()))
         }
     }
 
@@ -0,0 +1,129 @@
+use actix_web::*;
+use futures::{future, Future};
+use serde_json;
+
+use std::collections::HashSet;
+
+use analytics::{self, Evaluation};
+use api::State;
+use api::error::{APIError, FieldError};
+use api::flag_req::FlagReq;
+use util::current_time;
+
+const MAX_BATCH: usize = 1000;
+// Enough for a busy SDK's batch interval, and far from overflowing a total
+const MAX_COUNT: u64 = 1_000_000_000;
+const DAY: u64 = 24 * 60 * 60;
+
+#[derive(Deserialize)]
+struct EvaluationBatch {
+    evaluations: Vec<Evaluation>,
+}
+
+#[derive(Serialize)]
+struct BatchResp {
+    recorded: usize,
+    skipped: Vec<String>,
+}
+
+fn validate(batch: &EvaluationBatch) -> Result<(), APIError> {
+    if batch.evaluations.len() > MAX_BATCH {
+        return Err(APIError::invalid("evaluations", "must have at most 1000 entries"));
+    }
+
+    let mut errors = vec![];
+
+    for (i, evaluation) in batch.evaluations.iter().enumerate() {
+        if !analytics::is_variation(&evaluation.variation) {
+            errors.push(FieldError::new(format!("evaluations[{}].variation", i), "must be true, false or off"));
+        }
+
+        if evaluation.count > MAX_COUNT {
+            errors.push(FieldError::new(format!("evaluations[{}].count", i), "must be at most 1000000000"));
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(APIError::InvalidFields(errors))
+    }
+}
+
+// Takes evaluation counts that SDKs summarized since their last batch.
+// Counts for flags that do not exist in the path are skipped.
+pub fn record<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+    let flag_req = match FlagReq::from_req(&req) {
+        Ok(res) => res,
+        Err(err) => return Box::new(future::err(err)),
+    };
+
+    req.json()
+        .from_err()
+        .and_then(move |batch: EvaluationBatch| {
+            validate(&batch)?;
+
+            let flags = state
+                .flags()
+                .get_all(&flag_req.path)
+                .map_err(APIError::read)?;
+
+            let (known, unknown): (Vec<Evaluation>, Vec<Evaluation>) = batch
+                .evaluations
+                .into_iter()
+                .partition(|evaluation| flags.contains_key(&evaluation.key));
+
+            state
+                .analytics()
+                .record(&flag_req.path, &known)
+                .map_err(APIError::write)?;
+
+            let skipped = unknown
+                .into_iter()
+                .map(|evaluation| evaluation.key)
+                .collect::<HashSet<String>>()
+                .into_iter()
+                .collect();
+
+            serde_json::to_string(&BatchResp {
+                recorded: known.len(),
+                skipped: skipped,
+            }).or(Err(APIError::FailedToSerialize))
+                .map(|json| HttpResponse::Accepted().content_type("application/json").body(json))
+        })
+        .responder()
+}
+
+// Counts for a flag over the last `days` days, 7 by default
+pub fn usage<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+    let flag_req = match FlagReq::from_req(&req) {
+        Ok(res) => res,
+        Err(err) => return Box::new(future::err(err)),
+    };
+    let days = req.query()
+        .get("days")
+        .and_then(|days| days.parse::<u64>().ok())
+        .unwrap_or(7);
+
+    Box::new(future::ok(()).and_then(move |_| {
+        let key = flag_req.key.as_ref().ok_or(APIError::FailedToParseParams)?;
+
+        state
+            .flags()
+            .get(&flag_req.path, key)
+            .map_err(APIError::read)?
+            .ok_or(APIError::FailedToFind)?;
+
+        let since = current_time().saturating_sub(days.saturating_mul(DAY));
+        let usage = state
+            .analytics()
+            .usage(&flag_req.path, key, since)
+            .map_err(APIError::read)?;
+
+        Ok(serde_json::to_string(&usage)
+            .or(Err(APIError::FailedToSerialize))
+            .into())
+    }))
+}
@@ -5,11 +5,31 @@ use actix_web::middleware::Logger;
 
 use std::path::Path;
 
+use api::admin;
+use api::analytics;
 use api::auth;
 use api::flag;
+use api::grant;
+use api::history;
+use api::lockout;
+use api::metrics;
+use api::oidc;
 use api::path;
+use api::poll;
+use api::promote;
+use api::report;
+use api::request_id;
+use api::rollout;
+use api::schedule;
+use api::sdk_key;
+use api::session;
+use api::socket;
+use api::status;
 use api::State;
 use api::stream;
+use api::team;
+use api::transfer;
+use api::user;
 
 fn index<'r>(_req: &'r HttpRequest<State>) -> Result<NamedFile> {
     Ok(NamedFile::open(Path::new("www/index.html"))?)
@@ -19,27 +39,227 @@ pub fn api(state: State) -> App<State> {
     App::with_state(state)
         .prefix("/api/v1")
         .middleware(Logger::default())
+        .middleware(request_id::RequestIds)
+        .middleware(metrics::RequestMetrics("api"))
+        .middleware(auth::SdkAuth)
+        .middleware(auth::BearerAuth)
         .middleware(auth::UrlAuth)
         .middleware(auth::BasicAuth)
+        .resource("/teams/", |r| {
+            r.method(Method::GET).a(team::all);
+            r.method(Method::POST).a(team::create)
+        })
+        .resource("/teams/{team}/", |r| {
+            r.method(Method::GET).a(team::read);
+            r.method(Method::DELETE).a(team::delete)
+        })
+        .resource("/teams/{team}/members/", |r| {
+            r.method(Method::POST).a(team::add_member)
+        })
+        .resource("/teams/{team}/members/{user}/", |r| {
+            r.method(Method::DELETE).a(team::remove_member)
+        })
+        .resource("/reports/stale/", |r| r.method(Method::GET).a(report::stale))
         .resource("/{app}/{env}/flag/", |r| {
             r.method(Method::POST).a(flag::create)
         })
         .resource("/{app}/{env}/flag/{key}/", |r| {
+            r.name("flag");
             r.method(Method::GET).a(flag::read);
             r.method(Method::POST).a(flag::update);
             r.method(Method::DELETE).a(flag::delete)
         })
+        .resource("/{app}/{env}/flag/{key}/history/", |r| {
+            r.method(Method::GET).a(history::all)
+        })
+        .resource("/{app}/{env}/flag/{key}/schedules/", |r| {
+            r.method(Method::GET).a(schedule::all);
+            r.method(Method::POST).a(schedule::create)
+        })
+        .resource("/{app}/{env}/schedules/", |r| {
+            r.method(Method::GET).a(schedule::all)
+        })
+        .resource("/{app}/{env}/schedules/{id}/", |r| {
+            r.method(Method::DELETE).a(schedule::cancel)
+        })
+        .resource("/{app}/{env}/flag/{key}/rollouts/", |r| {
+            r.method(Method::POST).a(rollout::create)
+        })
+        .resource("/{app}/{env}/rollouts/{plan}/", |r| {
+            r.method(Method::GET).a(rollout::read)
+        })
+        .resource("/{app}/{env}/rollouts/{plan}/pause/", |r| {
+            r.method(Method::POST).a(rollout::pause)
+        })
+        .resource("/{app}/{env}/rollouts/{plan}/resume/", |r| {
+            r.method(Method::POST).a(rollout::resume)
+        })
+        .resource("/{app}/{env}/rollouts/{plan}/abort/", |r| {
+            r.method(Method::POST).a(rollout::abort)
+        })
+        .resource("/{app}/{env}/history/", |r| {
+            r.method(Method::GET).a(history::all)
+        })
+        .resource("/{app}/{env}/flag/{key}/usage/", |r| {
+            r.method(Method::GET).a(analytics::usage)
+        })
+        .resource("/{app}/{env}/evaluations/", |r| {
+            r.name("evaluations");
+            r.method(Method::POST).a(analytics::record)
+        })
         .resource("/{app}/{env}/flags/", |r| {
+            r.name("flags");
             r.method(Method::GET).a(flag::all)
         })
+        .resource("/{app}/{env}/flags/poll/", |r| {
+            r.name("flags_poll");
+            r.method(Method::GET).a(poll::flag_poll)
+        })
+        .resource("/{app}/{env}/export/", |r| {
+            r.method(Method::GET).a(transfer::export)
+        })
+        .resource("/{app}/{env}/import/", |r| {
+            r.method(Method::POST).a(transfer::import)
+        })
+        .resource("/{app}/{env}/keys/", |r| {
+            r.method(Method::GET).a(sdk_key::all);
+            r.method(Method::POST).a(sdk_key::create)
+        })
+        .resource("/{app}/{env}/keys/{id}/", |r| {
+            r.method(Method::DELETE).a(sdk_key::delete)
+        })
+        .resource("/{app}/{env}/keys/{id}/rotate/", |r| {
+            r.method(Method::POST).a(sdk_key::rotate)
+        })
+        .resource("/{app}/{env}/grants/", |r| {
+            r.method(Method::GET).a(grant::env_all);
+            r.method(Method::POST).a(grant::env_create)
+        })
+        .resource("/{app}/{env}/grants/{user}/", |r| {
+            r.method(Method::DELETE).a(grant::env_delete)
+        })
+        .resource("/{app}/export/", |r| {
+            r.method(Method::GET).a(transfer::export_app)
+        })
+        .resource("/{app}/import/", |r| {
+            r.method(Method::POST).a(transfer::import_app)
+        })
+        .resource("/{app}/promote/", |r| {
+            r.method(Method::POST).a(promote::apply)
+        })
+        .resource("/{app}/promote/preview/", |r| {
+            r.method(Method::POST).a(promote::preview)
+        })
+        .resource("/{app}/grants/", |r| {
+            r.method(Method::GET).a(grant::app_all);
+            r.method(Method::POST).a(grant::app_create)
+        })
+        .resource("/{app}/grants/{user}/", |r| {
+            r.method(Method::DELETE).a(grant::app_delete)
+        })
         .resource("/path/", |r| r.method(Method::POST).a(path::create))
         .resource("/paths/", |r| r.method(Method::GET).a(path::all))
-        .resource("/stream/{app}/{env}/", |r| r.f(stream::flag_stream))
+        .resource("/stream/{app}/{env}/", |r| {
+            r.name("stream");
+            r.f(stream::flag_stream)
+        })
+        .resource("/stream/{app}/{env}/ticket/", |r| {
+            r.method(Method::POST).f(stream::ticket)
+        })
+        .resource("/ws/", |r| r.f(socket::flag_socket))
 }
 
-pub fn frontend(state: State) -> App<State> {
+// Routes for administrators, mounted ahead of the api as they share its prefix
+pub fn admin(state: State) -> App<State> {
+    App::with_state(state)
+        .prefix("/api/v1/admin")
+        .middleware(Logger::default())
+        .middleware(request_id::RequestIds)
+        .middleware(metrics::RequestMetrics("admin"))
+        .middleware(auth::BearerAuth)
+        .middleware(auth::UrlAuth)
+        .middleware(auth::BasicAuth)
+        .middleware(admin::Admin)
+        .resource("/paths/", |r| r.method(Method::GET).a(path::all_admin))
+        .resource("/users/", |r| {
+            r.method(Method::GET).a(user::all);
+            r.method(Method::POST).a(user::create)
+        })
+        .resource("/users/{key}/", |r| {
+            r.method(Method::DELETE).a(user::delete)
+        })
+        .resource("/users/{key}/secret/", |r| {
+            r.method(Method::POST).a(user::reset_secret)
+        })
+        .resource("/users/{key}/admin/", |r| {
+            r.method(Method::POST).a(user::set_admin)
+        })
+        .resource("/users/{key}/disabled/", |r| {
+            r.method(Method::POST).a(user::set_disabled)
+        })
+        .resource("/lockouts/", |r| r.method(Method::GET).a(lockout::all))
+        .resource("/lockouts/{subject}/", |r| {
+            r.method(Method::DELETE).a(lockout::delete)
+        })
+}
+
+// Session routes are mounted ahead of the api so that logging in does not
+// go through the authentication middleware
+pub fn session(state: State) -> App<State> {
+    App::with_state(state)
+        .prefix("/api/v1/session")
+        .middleware(Logger::default())
+        .middleware(request_id::RequestIds)
+        .middleware(metrics::RequestMetrics("session"))
+        .resource("/login/", |r| r.method(Method::POST).a(session::login))
+        .resource("/refresh/", |r| r.method(Method::POST).a(session::refresh))
+        .resource("/logout/", |r| r.method(Method::POST).a(session::logout))
+}
+
+// Browser sign in through an OpenID Connect issuer
+pub fn oidc(state: State) -> App<State> {
+    App::with_state(state)
+        .prefix("/auth")
+        .middleware(Logger::default())
+        .middleware(request_id::RequestIds)
+        .middleware(metrics::RequestMetrics("oidc"))
+        .resource("/login/", |r| r.method(Method::GET).a(oidc::login))
+        .resource("/callback/", |r| r.method(Method::GET).a(oidc::callback))
+        .resource("/logout/", |r| r.method(Method::GET).a(oidc::logout))
+}
+
+// Server status for administrators
+pub fn status(state: State) -> App<State> {
     App::with_state(state)
+        .prefix("/status")
         .middleware(Logger::default())
+        .middleware(request_id::RequestIds)
+        .middleware(metrics::RequestMetrics("status"))
+        .middleware(auth::BearerAuth)
+        .middleware(auth::BasicAuth)
+        .middleware(admin::Admin)
+        .resource("/", |r| r.method(Method::GET).f(status::status))
+}
+
+// Metrics are served on a listener of their own so that they are not exposed
+// next to the public frontend. They are left out of the access log as they
+// are scraped every few seconds.
+pub fn metrics(state: State) -> App<State> {
+    App::with_state(state)
+        .middleware(Logger::default().exclude("/metrics"))
+        .middleware(request_id::RequestIds)
+        .resource("/metrics", |r| r.method(Method::GET).f(metrics::scrape))
+}
+
+// Probes are served next to the frontend as it is mounted at the root. They
+// are left out of the access log as they are called every few seconds.
+pub fn frontend(state: State) -> App<State> {
+    App::with_state(state)
+        .middleware(Logger::default().exclude("/healthz").exclude("/readyz"))
+        .middleware(request_id::RequestIds)
+        .middleware(metrics::RequestMetrics("frontend"))
+        .resource("/healthz", |r| r.method(Method::GET).f(status::health))
+        .resource("/readyz", |r| r.method(Method::GET).f(status::ready))
         .resource("/", |r| r.h(index))
         .resource("/{app}/{env}/", |r| r.h(index))
         .handler(
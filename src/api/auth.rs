@@ -1,22 +1,42 @@
-use actix_web::{HttpRequest, HttpResponse, Result};
+use actix_web::{HttpRequest, HttpResponse, ResponseError, Result};
 use actix_web::middleware::{Middleware, Response, Started};
 use base64::decode;
-use http::{header, StatusCode};
+use http::{header, Method};
 
 use std::str;
 use std::str::FromStr;
 
+use api::access;
 use api::error::APIError;
+use api::lockout;
+use api::sdk_key::SDK_KEY_PATH;
+use api::session::{self, session_token};
+use api::user::USER_PATH;
 use api::State;
 use api::state::UserStore;
+use flag::FlagPath;
+use metrics;
+use sdk_key::{SdkKey, SdkKeyKind};
 use user::User;
 
+// Resources that may be read with an SDK key, and those SDKs may post to.
+// Every other resource requires an authenticated user.
+const SDK_RESOURCES: [&'static str; 4] = ["flag", "flags", "flags_poll", "stream"];
+const SDK_WRITABLE: [&'static str; 1] = ["evaluations"];
+
 #[derive(Debug)]
 pub struct BasicAuth;
 
 #[derive(Debug)]
 pub struct UrlAuth;
 
+#[derive(Debug)]
+pub struct SdkAuth;
+
+#[derive(Debug)]
+pub struct BearerAuth;
+
+#[derive(Deserialize)]
 pub struct AuthReq {
     key: String,
     secret: String,
@@ -29,9 +49,13 @@ impl FromStr for AuthReq {
         let decoded = decode(s).or(Err(APIError::FailedToParseAuth))?;
         let parts = str::from_utf8(&decoded[..])
             .or(Err(APIError::FailedToParseAuth))?
-            .split(":")
+            .splitn(2, ':')
             .collect::<Vec<&str>>();
 
+        if parts.len() != 2 {
+            return Err(APIError::FailedToParseAuth);
+        }
+
         Ok(AuthReq {
             key: parts[0].to_string(),
             secret: parts[1].to_string(),
@@ -40,12 +64,23 @@ impl FromStr for AuthReq {
 }
 
 fn find_user(auth: &AuthReq, store: &Box<UserStore>) -> Option<User> {
-    store.get(&"users".to_string(), auth.key.as_str()).unwrap_or(None)
+    store.get(&USER_PATH.to_string(), auth.key.as_str()).unwrap_or(None)
 }
 
-fn verifiy_auth(auth: &AuthReq, store: &Box<UserStore>) -> Option<User> {
-    find_user(auth, store).and_then(|user| {
-        if user.verify_secret(&auth.secret) {
+pub fn verifiy_auth(auth: &AuthReq, store: &Box<UserStore>) -> Option<User> {
+    find_user(auth, store).and_then(|mut user| {
+        if !user.is_disabled() && user.verify_secret(&auth.secret) {
+
+            // Upgrade hashes made with a legacy salt or a lower iteration
+            // count now that the plain secret is known to be correct
+            if user.needs_rehash() {
+                user.set_secret(&auth.secret);
+
+                if store.upsert(&USER_PATH.to_string(), auth.key.as_str(), &user).is_err() {
+                    error!("Failed to store rehashed credentials for {}", user.uuid);
+                }
+            }
+
             Some(user)
         } else {
             None
@@ -53,14 +88,51 @@ fn verifiy_auth(auth: &AuthReq, store: &Box<UserStore>) -> Option<User> {
     })
 }
 
+// Verifies credentials while tracking failed attempts. Locked out keys and
+// addresses are refused before their secret is checked.
+pub fn authenticate(auth: &AuthReq, req: &HttpRequest<State>) -> Result<User, APIError> {
+    let state = req.state();
+    let subjects = lockout::subjects(&auth.key, req);
+
+    if lockout::is_locked(state, &subjects) {
+        failure("password", APIError::TooManyAttempts.code());
+        return Err(APIError::TooManyAttempts);
+    }
+
+    match verifiy_auth(auth, state.users()) {
+        Some(user) => {
+            lockout::clear_key(state, &auth.key);
+            Ok(user)
+        }
+        None => {
+            failure("password", APIError::Unauthorized.code());
+            lockout::record_failure(state, &subjects);
+            Err(APIError::Unauthorized)
+        }
+    }
+}
+
+fn failure(method: &'static str, reason: &'static str) {
+    metrics::inc(metrics::AUTH_FAILURES, &[("method", method), ("reason", reason)]);
+}
+
+// Rejects a request that failed to authenticate with the given method
+fn reject(method: &'static str, err: APIError) -> Started {
+    failure(method, err.code());
+    Started::Response(err.error_response())
+}
+
+fn is_authenticated<S>(req: &HttpRequest<S>) -> bool {
+    req.extensions().get::<User>().is_some() || req.extensions().get::<SdkKey>().is_some()
+}
+
 fn handle_auth(auth: &AuthReq, req: &HttpRequest<State>) -> Started {
-    if let Some(user) = verifiy_auth(auth, req.state().users()) {
-        req.extensions_mut().insert(user);
-        Started::Done
-    } else {
-        Started::Response(HttpResponse::new(
-            StatusCode::UNAUTHORIZED
-        ))
+    match authenticate(auth, req) {
+        Ok(user) => {
+            req.extensions_mut().insert(user);
+            Started::Done
+        }
+        Err(err) => Started::Response(err.error_response()),
     }
 }
 
@@ -69,13 +141,14 @@ impl Middleware<State> for BasicAuth {
 
         // If the user was already authenticated by some other means,
         // use the already set user
-        if req.extensions().get::<User>().is_some() {
+        if is_authenticated(req) {
             Ok(Started::Done)
         } else {
             let auth_test = req
                 .headers()
                 .get(header::AUTHORIZATION)
                 .and_then(|auth| auth.to_str().ok())
+                .filter(|auth| auth.starts_with("Basic "))
                 .and_then(|auth| auth[6..].parse::<AuthReq>().ok());
 
             if let Some(auth_req) = auth_test {
@@ -83,9 +156,7 @@ impl Middleware<State> for BasicAuth {
             } else {
                 println!("Denied access to due to missing credentials");
 
-                Ok(Started::Response(HttpResponse::new(
-                    StatusCode::UNAUTHORIZED
-                )))
+                Ok(reject("basic", APIError::Unauthorized))
             }
         }
     }
@@ -95,13 +166,46 @@ impl Middleware<State> for BasicAuth {
     }
 }
 
+// Streams opened from a browser authenticate with a ticket for the requested
+// path. Credentials in the url are not accepted for them.
+fn handle_ticket(req: &HttpRequest<State>) -> Started {
+    let token = match req.query().get("ticket") {
+        Some(token) => token.to_string(),
+        None => return Started::Done,
+    };
+
+    let ticket = match session::verify_ticket(req.state(), &token) {
+        Some(ticket) => ticket,
+        None => return reject("ticket", APIError::Unauthorized),
+    };
+
+    let user = session::claimed_user(&ticket.sub, &ticket.key, ticket.admin);
+    let path = {
+        let params = req.match_info();
+
+        match (params.get("app"), params.get("env")) {
+            (Some(app), Some(env)) => FlagPath::make_path(access::owner(req, &user), app, env),
+            _ => return reject("ticket", APIError::Forbidden),
+        }
+    };
+
+    if path == ticket.path {
+        req.extensions_mut().insert(user);
+        Started::Done
+    } else {
+        reject("ticket", APIError::Forbidden)
+    }
+}
+
 impl Middleware<State> for UrlAuth {
     fn start(&self, req: &HttpRequest<State>) -> Result<Started> {
         
         // If the user was already authenticated by some other means,
         // use the already set user
-        if req.extensions().get::<User>().is_some() {
+        if is_authenticated(req) {
             Ok(Started::Done)
+        } else if req.resource().name() == "stream" {
+            Ok(handle_ticket(req))
         } else {
             let auth_test = req.query().get("auth").and_then(|auth| auth.parse::<AuthReq>().ok());
 
@@ -117,3 +221,91 @@ impl Middleware<State> for UrlAuth {
         Ok(Response::Done(resp))
     }
 }
+
+fn sdk_token<S>(req: &HttpRequest<S>) -> Option<(String, bool)> {
+    let header_token = req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|auth| auth.to_str().ok())
+        .map(|auth| auth.trim())
+        .filter(|auth| SdkKey::is_token(auth))
+        .map(|auth| (auth.to_string(), true));
+
+    header_token.or_else(|| {
+        req.query()
+            .get("key")
+            .filter(|key| SdkKey::is_token(key))
+            .map(|key| (key.to_string(), false))
+    })
+}
+
+fn verify_sdk_key(key: &SdkKey, from_header: bool, req: &HttpRequest<State>) -> bool {
+    let params = req.match_info();
+    let name = req.resource().name();
+    let allowed = match *req.method() {
+        Method::GET => SDK_RESOURCES.contains(&name),
+        Method::POST => SDK_WRITABLE.contains(&name),
+        _ => false,
+    };
+    let same_path = match (params.get("app"), params.get("env")) {
+        (Some(app), Some(env)) => key.allows(app, env),
+        _ => false,
+    };
+
+    // Server keys are secrets and must not end up in urls
+    allowed && same_path && (from_header || key.kind == SdkKeyKind::Client)
+}
+
+impl Middleware<State> for SdkAuth {
+    fn start(&self, req: &HttpRequest<State>) -> Result<Started> {
+        if let Some((token, from_header)) = sdk_token(req) {
+            let key = req.state()
+                .keys()
+                .get(&SDK_KEY_PATH.to_string(), SdkKey::hash_token(&token).as_str())
+                .unwrap_or(None);
+
+            match key {
+                Some(key) => if verify_sdk_key(&key, from_header, req) {
+                    req.extensions_mut().insert(key);
+                    Ok(Started::Done)
+                } else {
+                    Ok(reject("sdk_key", APIError::Forbidden))
+                },
+                None => Ok(reject("sdk_key", APIError::Unauthorized)),
+            }
+        } else {
+            Ok(Started::Done)
+        }
+    }
+
+    fn response(&self, _: &HttpRequest<State>, resp: HttpResponse) -> Result<Response> {
+        Ok(Response::Done(resp))
+    }
+}
+
+impl Middleware<State> for BearerAuth {
+    fn start(&self, req: &HttpRequest<State>) -> Result<Started> {
+        if is_authenticated(req) {
+            return Ok(Started::Done);
+        }
+
+        // A valid token that was not revoked identifies the user
+        if let Some(token) = session_token(req) {
+            let user = session::verify(req.state(), &token)
+                .map(|claims| session::claimed_user(&claims.sub, &claims.key, claims.admin));
+
+            match user {
+                Some(user) => {
+                    req.extensions_mut().insert(user);
+                    Ok(Started::Done)
+                }
+                None => Ok(reject("bearer", APIError::Unauthorized)),
+            }
+        } else {
+            Ok(Started::Done)
+        }
+    }
+
+    fn response(&self, _: &HttpRequest<State>, resp: HttpResponse) -> Result<Response> {
+        Ok(Response::Done(resp))
+    }
+}
@@ -1,58 +1,226 @@
 use actix_web::{HttpResponse, ResponseError};
 use actix_web::http::StatusCode;
 use actix_web::error::{JsonPayloadError, PayloadError};
+use serde_json;
 
 use std::error::Error;
 use std::fmt;
 
+use error::BannerError;
+
+pub const PROBLEM_JSON: &'static str = "application/problem+json";
+
+// A single invalid field of a request body
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new<S: Into<String>, T: Into<String>>(field: S, message: T) -> FieldError {
+        FieldError {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum APIError {
     AlreadyExists,
     FailedToAccessParams,
-    FailedToAccessStore,
+    FailedToAccessStore(String),
     FailedToFind,
+    FailedToGenerateToken,
     FailedToParseAuth,
     FailedToParseBody,
     FailedToParseParams,
+    FailedToReachIssuer,
     FailedToSerialize,
-    FailedToWriteToStore,
-    InvalidFlag,
+    FailedToWriteToStore(String),
+    Forbidden,
+    InvalidFields(Vec<FieldError>),
+    MalformedJson(String),
+    MalformedYaml(String),
+    StoreUnavailable(String),
+    TooManyAttempts,
+    TooManyStreams,
     Unauthorized,
 }
 
+// RFC 7807 problem details. The request id is added by the RequestId
+// middleware as errors do not have access to the request.
+#[derive(Serialize)]
+struct Problem<'a> {
+    #[serde(rename = "type")]
+    problem_type: String,
+    title: &'a str,
+    status: u16,
+    code: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    errors: Option<&'a Vec<FieldError>>,
+}
+
 impl APIError {
+    // Maps a failed read from a store, keeping the cause
+    pub fn read(err: BannerError) -> APIError {
+        if err.is_unavailable() {
+            APIError::StoreUnavailable(err.to_string())
+        } else {
+            APIError::FailedToAccessStore(err.to_string())
+        }
+    }
+
+    // Maps a failed write to a store, keeping the cause
+    pub fn write(err: BannerError) -> APIError {
+        if err.is_unavailable() {
+            APIError::StoreUnavailable(err.to_string())
+        } else {
+            APIError::FailedToWriteToStore(err.to_string())
+        }
+    }
+
+    pub fn invalid<S: Into<String>, T: Into<String>>(field: S, message: T) -> APIError {
+        APIError::InvalidFields(vec![FieldError::new(field, message)])
+    }
+
     pub fn status(&self) -> StatusCode {
         match self {
             &APIError::AlreadyExists => StatusCode::CONFLICT,
             &APIError::FailedToAccessParams => StatusCode::BAD_REQUEST,
-            &APIError::FailedToAccessStore => StatusCode::INTERNAL_SERVER_ERROR,
+            &APIError::FailedToAccessStore(_) => StatusCode::INTERNAL_SERVER_ERROR,
             &APIError::FailedToFind => StatusCode::NOT_FOUND,
+            &APIError::FailedToGenerateToken => StatusCode::INTERNAL_SERVER_ERROR,
             &APIError::FailedToParseAuth => StatusCode::BAD_REQUEST,
             &APIError::FailedToParseBody => StatusCode::BAD_REQUEST,
             &APIError::FailedToParseParams => StatusCode::BAD_REQUEST,
+            &APIError::FailedToReachIssuer => StatusCode::BAD_GATEWAY,
             &APIError::FailedToSerialize => StatusCode::INTERNAL_SERVER_ERROR,
-            &APIError::FailedToWriteToStore => StatusCode::INTERNAL_SERVER_ERROR,
-            &APIError::InvalidFlag => StatusCode::BAD_REQUEST,
+            &APIError::FailedToWriteToStore(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            &APIError::Forbidden => StatusCode::FORBIDDEN,
+            &APIError::InvalidFields(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            &APIError::MalformedJson(_) => StatusCode::BAD_REQUEST,
+            &APIError::MalformedYaml(_) => StatusCode::BAD_REQUEST,
+            &APIError::StoreUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            &APIError::TooManyAttempts => StatusCode::TOO_MANY_REQUESTS,
+            &APIError::TooManyStreams => StatusCode::TOO_MANY_REQUESTS,
             &APIError::Unauthorized => StatusCode::UNAUTHORIZED,
         }
     }
+
+    // Stable identifiers that clients can match on
+    pub fn code(&self) -> &'static str {
+        match self {
+            &APIError::AlreadyExists => "already_exists",
+            &APIError::FailedToAccessParams => "invalid_params",
+            &APIError::FailedToAccessStore(_) => "store_read_failed",
+            &APIError::FailedToFind => "not_found",
+            &APIError::FailedToGenerateToken => "token_generation_failed",
+            &APIError::FailedToParseAuth => "invalid_credentials_format",
+            &APIError::FailedToParseBody => "invalid_body",
+            &APIError::FailedToParseParams => "invalid_params",
+            &APIError::FailedToReachIssuer => "issuer_unreachable",
+            &APIError::FailedToSerialize => "serialization_failed",
+            &APIError::FailedToWriteToStore(_) => "store_write_failed",
+            &APIError::Forbidden => "forbidden",
+            &APIError::InvalidFields(_) => "validation_failed",
+            &APIError::MalformedJson(_) => "malformed_json",
+            &APIError::MalformedYaml(_) => "malformed_yaml",
+            &APIError::StoreUnavailable(_) => "store_unavailable",
+            &APIError::TooManyAttempts => "too_many_attempts",
+            &APIError::TooManyStreams => "too_many_streams",
+            &APIError::Unauthorized => "unauthorized",
+        }
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            &APIError::AlreadyExists => "The resource already exists",
+            &APIError::FailedToAccessParams => "The request parameters could not be read",
+            &APIError::FailedToAccessStore(_) => "Failed to read from the store",
+            &APIError::FailedToFind => "The resource could not be found",
+            &APIError::FailedToGenerateToken => "Failed to generate a random token",
+            &APIError::FailedToParseAuth => "The credentials could not be parsed",
+            &APIError::FailedToParseBody => "The request body could not be read",
+            &APIError::FailedToParseParams => "The request parameters are invalid",
+            &APIError::FailedToReachIssuer => "The identity provider could not be reached",
+            &APIError::FailedToSerialize => "Failed to serialize the response",
+            &APIError::FailedToWriteToStore(_) => "Failed to write to the store",
+            &APIError::Forbidden => "Access to the resource is not allowed",
+            &APIError::InvalidFields(_) => "The request body has invalid fields",
+            &APIError::MalformedJson(_) => "The request body is not valid json",
+            &APIError::MalformedYaml(_) => "The request body is not valid yaml",
+            &APIError::StoreUnavailable(_) => "The store is unavailable",
+            &APIError::TooManyAttempts => "Too many failed attempts, try again later",
+            &APIError::TooManyStreams => "Too many open streams",
+            &APIError::Unauthorized => "Authentication is required",
+        }
+    }
+
+    // Details that are safe to show to clients. Store errors are logged but
+    // not returned as they may describe the backend.
+    fn detail(&self) -> Option<&str> {
+        match self {
+            &APIError::MalformedJson(ref detail) | &APIError::MalformedYaml(ref detail) => Some(detail.as_str()),
+            _ => None,
+        }
+    }
+
+    fn backend_cause(&self) -> Option<&str> {
+        match self {
+            &APIError::FailedToAccessStore(ref cause)
+            | &APIError::FailedToWriteToStore(ref cause)
+            | &APIError::StoreUnavailable(ref cause) => Some(cause.as_str()),
+            _ => None,
+        }
+    }
+
+    pub fn problem(&self) -> String {
+        let errors = match self {
+            &APIError::InvalidFields(ref errors) => Some(errors),
+            _ => None,
+        };
+
+        let problem = Problem {
+            problem_type: ["urn:masquerade:error:", self.code()].concat(),
+            title: self.message(),
+            status: self.status().as_u16(),
+            code: self.code(),
+            detail: self.detail(),
+            errors: errors,
+        };
+
+        serde_json::to_string(&problem).unwrap_or_else(|_| "{}".to_string())
+    }
 }
 
 impl Error for APIError {
     fn description(&self) -> &str {
-        ""
+        self.message()
     }
 }
 
 impl fmt::Display for APIError {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        write!(f, "{}", self.description())
+        match self.backend_cause() {
+            Some(cause) => write!(f, "{}: {}", self.message(), cause),
+            None => write!(f, "{}", self.message()),
+        }
     }
 }
 
 impl ResponseError for APIError {
     fn error_response(&self) -> HttpResponse {
-        HttpResponse::new(self.status())
+        if self.status().is_server_error() {
+            error!("{}", self);
+        }
+
+        HttpResponse::build(self.status())
+            .content_type(PROBLEM_JSON)
+            .body(self.problem())
     }
 }
 
@@ -63,7 +231,44 @@ impl From<PayloadError> for APIError {
 }
 
 impl From<JsonPayloadError> for APIError {
-    fn from(_: JsonPayloadError) -> APIError {
-        APIError::FailedToParseBody
+    fn from(err: JsonPayloadError) -> APIError {
+        match err {
+            JsonPayloadError::Deserialize(err) => APIError::MalformedJson(err.to_string()),
+            _ => APIError::FailedToParseBody,
+        }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    #[test]
+    fn test_renders_problem_json() {
+        let problem: Value = serde_json::from_str(&APIError::FailedToFind.problem()).unwrap();
+
+        assert_eq!(problem["status"], 404);
+        assert_eq!(problem["code"], "not_found");
+        assert_eq!(problem["type"], "urn:masquerade:error:not_found");
+        assert!(problem.get("errors").is_none());
+    }
+
+    #[test]
+    fn test_renders_field_errors() {
+        let err = APIError::invalid("key", "must not be empty");
+        let problem: Value = serde_json::from_str(&err.problem()).unwrap();
+
+        assert_eq!(problem["status"], 422);
+        assert_eq!(problem["errors"][0]["field"], "key");
+        assert_eq!(problem["errors"][0]["message"], "must not be empty");
+    }
+
+    #[test]
+    fn test_hides_store_causes_from_clients() {
+        let err = APIError::FailedToAccessStore("connection refused".to_string());
+
+        assert!(!err.problem().contains("connection refused"));
+        assert!(err.to_string().contains("connection refused"));
+    }
+}
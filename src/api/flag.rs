@@ -1,14 +1,33 @@
 use actix_web::*;
-use actix_web::http::StatusCode;
+use actix_web::http::{header, StatusCode};
 use futures::{future, Future, Stream};
 use serde_json;
 
+use ring::digest;
+
+use std::collections::HashMap;
 use std::str;
 
 use api::State;
-use api::error::APIError;
+use api::error::{APIError, FieldError};
 use api::flag_req::FlagReq;
-use flag::Flag;
+use api::history;
+use flag::{Flag, FlagPath, FlagUpdate};
+use grant::Role;
+use history::{HistoryAction, HistoryEntry};
+
+pub fn validate(flag: &Flag) -> Result<(), APIError> {
+    let errors = flag.invalid_fields()
+        .into_iter()
+        .map(|(field, message)| FieldError::new(field, message))
+        .collect::<Vec<FieldError>>();
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(APIError::InvalidFields(errors))
+    }
+}
 
 pub fn read<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
     let state = req.state().clone();
@@ -19,10 +38,11 @@ pub fn read<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse,
 
     Box::new(future::ok(()).and_then(move |_| {
         if let Some(ref key) = flag_req.key {
-            let flag = match state.flags().get(&flag_req.path, key) {
-                Ok(Some(flag)) => Some(flag),
-                _ => None,
-            }.ok_or(APIError::FailedToFind)?;
+            let flag = state
+                .flags()
+                .get(&flag_req.path, key)
+                .map_err(APIError::read)?
+                .ok_or(APIError::FailedToFind)?;
 
             Ok(serde_json::to_string(&flag)
                 .or(Err(APIError::FailedToSerialize))
@@ -35,17 +55,22 @@ pub fn read<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse,
 
 pub fn create<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
     let state = req.state().clone();
-    let flag_req = match FlagReq::from_req(&req) {
+    let flag_req = match FlagReq::from_req(&req)
+        .and_then(|flag_req| flag_req.require(Role::Editor))
+    {
         Ok(res) => res,
         Err(err) => return Box::new(future::err(err)),
     };
 
     req.json()
         .from_err()
-        .and_then(move |flag: Flag| {
-            // Disallow empty string key
-            if flag.key().len() == 0 {
-                Err(APIError::InvalidFlag)?
+        .and_then(move |mut flag: Flag| {
+            validate(&flag)?;
+
+            if flag.meta().source.is_none() {
+                let mut meta = flag.meta().clone();
+                meta.source = Some("api".to_string());
+                flag.set_meta(&meta);
             }
 
             if let Ok(Some(_exists)) = state.flags().get(&flag_req.path, flag.key()) {
@@ -55,36 +80,77 @@ pub fn create<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse
             state
                 .flags()
                 .upsert(&flag_req.path, flag.key(), &flag)
-                .and_then(|_| Ok(HttpResponse::new(StatusCode::CREATED)))
-                .map_err(|_| APIError::FailedToWriteToStore)
+                .map_err(APIError::write)?;
+
+            let entry = HistoryEntry::new(
+                flag.key(),
+                flag_req.actor.as_str(),
+                HistoryAction::Created,
+                Some(flag.clone()),
+            );
+            history::record(&state, &flag_req.path, entry);
+
+            Ok(HttpResponse::new(StatusCode::CREATED))
         })
         .responder()
 }
 
+// Changes a stored flag and records who changed it. Every update goes
+// through here so that streams and history see it, whoever made it.
+pub fn change<F>(
+    state: &State,
+    path: &FlagPath,
+    key: &str,
+    actor: &str,
+    note: Option<String>,
+    f: F,
+) -> Result<Flag, APIError>
+where
+    F: FnOnce(&mut Flag) -> Result<(), APIError>,
+{
+    let mut flag = state
+        .flags()
+        .get(path, key)
+        .map_err(APIError::read)?
+        .ok_or(APIError::FailedToFind)?;
+
+    f(&mut flag)?;
+
+    state
+        .flags()
+        .upsert(path, key, &flag)
+        .map_err(APIError::write)?;
+
+    let entry = HistoryEntry::new(key, actor, HistoryAction::Updated, Some(flag.clone()));
+    history::record(state, path, match note {
+        Some(note) => entry.with_note(note),
+        None => entry,
+    });
+
+    Ok(flag)
+}
+
 pub fn update<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
     let state = req.state().clone();
-    let flag_req = match FlagReq::from_req(&req) {
+    let flag_req = match FlagReq::from_req(&req)
+        .and_then(|flag_req| flag_req.require(Role::Editor))
+    {
         Ok(res) => res,
         Err(err) => return Box::new(future::err(err)),
     };
 
     req.json()
         .from_err()
-        .and_then(move |new_flag: Flag| {
+        .and_then(move |update: FlagUpdate| {
             if let Some(ref key) = flag_req.key {
-                let mut flag = match state.flags().get(&flag_req.path, key) {
-                    Ok(Some(flag)) => Some(flag),
-                    _ => None,
-                }.ok_or(APIError::FailedToFind)?;
-
-                flag.set_value(new_flag.value());
-                flag.toggle(new_flag.is_enabled());
-
-                state
-                    .flags()
-                    .upsert(&flag_req.path, key, &flag)
-                    .and_then(|_| Ok(HttpResponse::new(StatusCode::OK)))
-                    .map_err(|_| APIError::FailedToWriteToStore)
+                change(&state, &flag_req.path, key, &flag_req.actor, None, |flag| {
+                    let new_flag = update.applied_to(flag);
+                    validate(&new_flag)?;
+                    flag.update_from(&new_flag, false);
+                    Ok(())
+                })?;
+
+                Ok(HttpResponse::new(StatusCode::OK))
             } else {
                 Err(APIError::FailedToParseParams)
             }
@@ -94,7 +160,9 @@ pub fn update<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse
 
 pub fn delete<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
     let state = req.state().clone();
-    let flag_req = match FlagReq::from_req(&req) {
+    let flag_req = match FlagReq::from_req(&req)
+        .and_then(|flag_req| flag_req.require(Role::Editor))
+    {
         Ok(res) => res,
         Err(err) => return Box::new(future::err(err)),
     };
@@ -104,12 +172,15 @@ pub fn delete<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse
             let flag = state
                 .flags()
                 .delete(&flag_req.path, key)
-                .map_err(|_| APIError::FailedToWriteToStore)
+                .map_err(APIError::write)
                 .and_then(|res| match res {
                     Some(flag) => Ok(flag),
                     None => Err(APIError::FailedToFind),
                 })?;
 
+            let entry = HistoryEntry::new(key.as_str(), flag_req.actor.as_str(), HistoryAction::Deleted, None);
+            history::record(&state, &flag_req.path, entry);
+
             Ok(serde_json::to_string(&flag)
                 .or(Err(APIError::FailedToSerialize))
                 .into())
@@ -119,28 +190,186 @@ pub fn delete<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse
     }))
 }
 
+// The tag is a digest of the listing itself, so any change to a flag,
+// including its metadata, changes it, and every replica agrees on it
+pub fn etag(body: &str) -> String {
+    let hash = digest::digest(&digest::SHA256, body.as_bytes())
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+
+    format!("\"{}\"", hash)
+}
+
+pub fn if_none_match<S>(req: &HttpRequest<S>) -> Option<String> {
+    req.headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|tags| tags.to_str().ok())
+        .map(|tags| tags.to_string())
+}
+
+fn etag_matches(tags: &str, etag: &str) -> bool {
+    tags.split(",")
+        .map(|tag| tag.trim().trim_left_matches("W/"))
+        .any(|tag| tag == "*" || tag == etag)
+}
+
+pub fn flag_response(
+    flags: &HashMap<String, Flag>,
+    if_none_match: Option<&String>,
+) -> Result<HttpResponse, APIError> {
+    let mut flag_list = flags.values().collect::<Vec<&Flag>>();
+    flag_list
+        .as_mut_slice()
+        .sort_by(|&a, &b| a.key().cmp(b.key()));
+
+    let json = serde_json::to_string(&flag_list).or(Err(APIError::FailedToSerialize))?;
+    let tag = etag(&json);
+
+    if if_none_match.map(|tags| etag_matches(tags, &tag)).unwrap_or(false) {
+        return Ok(HttpResponse::NotModified().header(header::ETAG, tag).finish());
+    }
+
+    Ok(HttpResponse::Ok().header(header::ETAG, tag).body(json))
+}
+
+// Narrows a listing to the flags with every one of the comma separated
+// tags and whose text matches the search
+#[derive(Debug, Default)]
+struct FlagFilter {
+    tags: Vec<String>,
+    q: Option<String>,
+}
+
+impl FlagFilter {
+    fn from_query(query: &HashMap<String, String>) -> FlagFilter {
+        FlagFilter {
+            tags: query
+                .get("tags")
+                .map(|tags| {
+                    tags.split(",")
+                        .map(|tag| tag.trim().to_string())
+                        .filter(|tag| tag.len() > 0)
+                        .collect()
+                })
+                .unwrap_or(vec![]),
+            q: query
+                .get("q")
+                .map(|q| q.trim().to_string())
+                .filter(|q| q.len() > 0),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.tags.is_empty() && self.q.is_none()
+    }
+
+    fn apply(&self, flags: HashMap<String, Flag>) -> HashMap<String, Flag> {
+        flags
+            .into_iter()
+            .filter(|&(_, ref flag)| {
+                flag.has_tags(&self.tags) && self.q.as_ref().map(|q| flag.matches(q)).unwrap_or(true)
+            })
+            .collect()
+    }
+}
+
 pub fn all<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
     let state = req.state().clone();
     let flag_req = match FlagReq::from_req(&req) {
         Ok(res) => res,
         Err(err) => return Box::new(future::err(err)),
     };
+    let if_none_match = if_none_match(req);
+    let filter = FlagFilter::from_query(&req.query());
 
     Box::new(future::ok(()).and_then(move |_| {
-
         state
             .flags()
             .get_all(&flag_req.path)
-            .and_then(|flags| {
-                let mut flag_list = flags.values().collect::<Vec<&Flag>>();
-                flag_list
-                    .as_mut_slice()
-                    .sort_by(|&a, &b| a.key().cmp(b.key()));
-
-                Ok(serde_json::to_string(&flag_list)
-                    .or(Err(APIError::FailedToSerialize))
-                    .into())
+            .map_err(APIError::read)
+            .map(|flags| {
+                if filter.is_empty() {
+                    flags
+                } else {
+                    filter.apply(flags)
+                }
             })
-            .map_err(|_| APIError::FailedToAccessStore)
+            .and_then(|flags| flag_response(&flags, if_none_match.as_ref()))
     }))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use flag::{FlagLink, FlagMeta, FlagValue, LinkKind};
+
+    fn tag_of(flags: Vec<Flag>) -> String {
+        let map = flags.into_iter().map(|flag| (flag.key().to_string(), flag)).collect();
+        let resp = flag_response(&map, None).unwrap();
+
+        resp.headers()[header::ETAG].to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_etag_changes_with_flags() {
+        let f1 = Flag::new("f1", FlagValue::Bool(true), 1, true);
+        let f2 = Flag::new("f1", FlagValue::Bool(true), 2, true);
+        let f3 = Flag::new("f1", FlagValue::Bool(true), 1, false);
+        let mut f4 = f1.clone();
+        f4.set_meta(&FlagMeta { description: "Launch banner".into(), ..FlagMeta::default() });
+
+        assert_eq!(tag_of(vec![f1.clone()]), tag_of(vec![f1.clone()]));
+        assert!(tag_of(vec![f1.clone()]) != tag_of(vec![f2]));
+        assert!(tag_of(vec![f1.clone()]) != tag_of(vec![f3]));
+        assert!(tag_of(vec![f1]) != tag_of(vec![f4]));
+    }
+
+    #[test]
+    fn test_matches_etag_lists() {
+        assert!(etag_matches("\"a\", \"b\"", "\"b\""));
+        assert!(etag_matches("W/\"a\"", "\"a\""));
+        assert!(etag_matches("*", "\"a\""));
+        assert!(!etag_matches("\"a\"", "\"b\""));
+    }
+
+    #[test]
+    fn test_validates_metadata() {
+        let mut f = Flag::new("f1", FlagValue::Bool(true), 1, true);
+        assert!(validate(&f).is_ok());
+
+        f.set_meta(&FlagMeta {
+            tags: vec!["a,b".into()],
+            links: vec![FlagLink { kind: LinkKind::Docs, url: "javascript:alert(1)".into() }],
+            ..FlagMeta::default()
+        });
+
+        match validate(&f) {
+            Err(APIError::InvalidFields(errors)) => {
+                let fields = errors.iter().map(|e| e.field.as_str()).collect::<Vec<&str>>();
+                assert_eq!(fields, vec!["tags", "links[0].url"]);
+            }
+            _ => panic!("Expected invalid fields"),
+        }
+    }
+
+    #[test]
+    fn test_filters_by_tags_and_search() {
+        let mut f1 = Flag::new("f1", FlagValue::Bool(true), 1, true);
+        f1.set_meta(&FlagMeta { tags: vec!["web".into()], ..FlagMeta::default() });
+        let f2 = Flag::new("f2", FlagValue::Bool(true), 1, true);
+
+        let mut flags = HashMap::new();
+        flags.insert("f1".to_string(), f1);
+        flags.insert("f2".to_string(), f2);
+
+        let mut query = HashMap::new();
+        query.insert("tags".to_string(), "web".to_string());
+        assert_eq!(FlagFilter::from_query(&query).apply(flags.clone()).len(), 1);
+
+        query.insert("q".to_string(), "f2".to_string());
+        assert_eq!(FlagFilter::from_query(&query).apply(flags).len(), 0);
+    }
+}
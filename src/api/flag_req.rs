@@ -1,35 +1,64 @@
 use actix_web::{HttpRequest};
 
+use api::access;
 use api::error::APIError;
 use api::State;
 use flag::FlagPath;
+use grant::Role;
+use sdk_key::SdkKey;
 use user::User;
 
 pub struct FlagReq {
     pub path: FlagPath,
     pub key: Option<String>,
+    pub role: Role,
+    pub actor: String,
 }
 
 impl FlagReq {
     pub fn from_req(req: &HttpRequest<State>) -> Result<FlagReq, APIError> {
         let params = req.match_info();
 
-        if let Some(user) = req.extensions().get::<User>() {
+        // Reading the query may write to the extensions, so the user is
+        // cloned rather than borrowed
+        let user = req.extensions().get::<User>().cloned();
+        let sdk_key = req.extensions().get::<SdkKey>().cloned();
+
+        if let Some(user) = user {
             if let (Some(app), Some(env)) = (params.get("app"), params.get("env")) {
+                let owner = access::owner(req, &user);
+                let role = access::role_for(req.state(), &user, &owner, app, Some(env))?;
+
                 Ok(FlagReq {
                     path: FlagPath {
-                        owner: user.uuid.clone(),
+                        owner: owner.clone(),
                         app: app.into(),
                         env: env.into(),
-                        path: FlagPath::make_path(&user.uuid, app, env),
+                        path: FlagPath::make_path(&owner, app, env),
                     },
                     key: params.get("key").map(|s| s.into()),
+                    role: access::require(role, Role::Viewer)?,
+                    actor: user.uuid.clone(),
                 })
             } else {
                 Err(APIError::FailedToParseParams)
             }
+        } else if let Some(sdk_key) = sdk_key {
+            // The key has already been checked against the requested path
+            // and is limited to reading it
+            Ok(FlagReq {
+                path: sdk_key.path,
+                key: params.get("key").map(|s| s.into()),
+                role: Role::Viewer,
+                actor: ["sdk:", sdk_key.id.as_str()].concat(),
+            })
         } else {
             Err(APIError::Unauthorized)
         }
     }
-}
\ No newline at end of file
+
+    pub fn require(self, role: Role) -> Result<FlagReq, APIError> {
+        access::require(Some(self.role), role)?;
+        Ok(self)
+    }
+}
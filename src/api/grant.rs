@@ -0,0 +1,220 @@
+use actix_web::*;
+use actix_web::http::StatusCode;
+use futures::{future, Future, Stream};
+use serde_json;
+
+use api::State;
+use api::access;
+use api::error::APIError;
+use api::flag_req::FlagReq;
+use grant::{Grant, Role};
+use user::User;
+
+#[derive(Deserialize)]
+struct GrantReq {
+    user: String,
+    role: Role,
+}
+
+// The app a grant request targets along with the caller's role on it
+struct AppReq {
+    owner: String,
+    app: String,
+    role: Option<Role>,
+}
+
+impl AppReq {
+    fn from_req(req: &HttpRequest<State>) -> Result<AppReq, APIError> {
+        let user = req.extensions()
+            .get::<User>()
+            .cloned()
+            .ok_or(APIError::Unauthorized)?;
+        let app = req.match_info()
+            .get("app")
+            .map(|app| app.to_string())
+            .ok_or(APIError::FailedToParseParams)?;
+        let owner = access::owner(req, &user);
+        let role = access::role_for(req.state(), &user, &owner, &app, None)?;
+
+        Ok(AppReq {
+            owner: owner,
+            app: app,
+            role: role,
+        })
+    }
+}
+
+fn user_param(req: &HttpRequest<State>) -> Result<String, APIError> {
+    req.match_info()
+        .get("user")
+        .map(|user| user.to_string())
+        .ok_or(APIError::FailedToParseParams)
+}
+
+fn list_grants<F>(state: &State, owner: &str, app: &str, filter: F) -> Result<HttpResponse, APIError>
+where
+    F: Fn(&Grant) -> bool,
+{
+    let mut grants = state
+        .grants()
+        .get_all(&Grant::store_path(owner, app))
+        .map_err(APIError::read)?
+        .into_iter()
+        .map(|(_, grant)| grant)
+        .filter(|grant| filter(grant))
+        .collect::<Vec<Grant>>();
+    grants
+        .as_mut_slice()
+        .sort_by(|a, b| a.user.cmp(&b.user).then(a.env.cmp(&b.env)));
+
+    Ok(serde_json::to_string(&grants)
+        .or(Err(APIError::FailedToSerialize))
+        .into())
+}
+
+fn store_grant(state: &State, grant: &Grant) -> Result<HttpResponse, APIError> {
+    state
+        .grants()
+        .upsert(&grant.path(), grant.key().as_str(), grant)
+        .and_then(|_| Ok(HttpResponse::new(StatusCode::CREATED)))
+        .map_err(APIError::write)
+}
+
+fn remove_grant(state: &State, owner: &str, app: &str, key: &str) -> Result<HttpResponse, APIError> {
+    state
+        .grants()
+        .delete(&Grant::store_path(owner, app), key)
+        .map_err(APIError::write)
+        .and_then(|res| match res {
+            Some(_) => Ok(HttpResponse::new(StatusCode::OK)),
+            None => Err(APIError::FailedToFind),
+        })
+}
+
+pub fn app_all<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+    let app_req = match AppReq::from_req(&req) {
+        Ok(res) => res,
+        Err(err) => return Box::new(future::err(err)),
+    };
+
+    Box::new(future::ok(()).and_then(move |_| {
+        access::require(app_req.role, Role::Owner)?;
+        list_grants(&state, &app_req.owner, &app_req.app, |_| true)
+    }))
+}
+
+pub fn app_create<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+    let app_req = match AppReq::from_req(&req) {
+        Ok(res) => res,
+        Err(err) => return Box::new(future::err(err)),
+    };
+
+    req.json()
+        .from_err()
+        .and_then(move |grant_req: GrantReq| {
+            access::require(app_req.role, Role::Owner)?;
+
+            let grant = Grant::new(grant_req.user, app_req.owner, app_req.app, None, grant_req.role);
+            store_grant(&state, &grant)
+        })
+        .responder()
+}
+
+pub fn app_delete<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+    let app_req = match AppReq::from_req(&req) {
+        Ok(res) => res,
+        Err(err) => return Box::new(future::err(err)),
+    };
+    let user = match user_param(&req) {
+        Ok(user) => user,
+        Err(err) => return Box::new(future::err(err)),
+    };
+
+    Box::new(future::ok(()).and_then(move |_| {
+        access::require(app_req.role, Role::Owner)?;
+        remove_grant(
+            &state,
+            &app_req.owner,
+            &app_req.app,
+            &Grant::store_key(&user, None),
+        )
+    }))
+}
+
+pub fn env_all<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+    let flag_req = match FlagReq::from_req(&req)
+        .and_then(|flag_req| flag_req.require(Role::EnvAdmin))
+    {
+        Ok(res) => res,
+        Err(err) => return Box::new(future::err(err)),
+    };
+
+    Box::new(future::ok(()).and_then(move |_| {
+        let path = flag_req.path;
+        list_grants(&state, &path.owner, &path.app, |grant| grant.applies_to(&path.env))
+    }))
+}
+
+pub fn env_create<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+    let flag_req = match FlagReq::from_req(&req)
+        .and_then(|flag_req| flag_req.require(Role::EnvAdmin))
+    {
+        Ok(res) => res,
+        Err(err) => return Box::new(future::err(err)),
+    };
+
+    req.json()
+        .from_err()
+        .and_then(move |grant_req: GrantReq| {
+            // Owners are only made through app wide grants, and nobody may
+            // hand out more than they hold themselves
+            if grant_req.role == Role::Owner {
+                Err(APIError::invalid("role", "owner can only be granted on the whole app"))?
+            }
+
+            access::require(Some(flag_req.role), grant_req.role)?;
+
+            let path = flag_req.path;
+            let grant = Grant::new(
+                grant_req.user,
+                path.owner,
+                path.app,
+                Some(path.env),
+                grant_req.role,
+            );
+            store_grant(&state, &grant)
+        })
+        .responder()
+}
+
+pub fn env_delete<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+    let flag_req = match FlagReq::from_req(&req)
+        .and_then(|flag_req| flag_req.require(Role::EnvAdmin))
+    {
+        Ok(res) => res,
+        Err(err) => return Box::new(future::err(err)),
+    };
+    let user = match user_param(&req) {
+        Ok(user) => user,
+        Err(err) => return Box::new(future::err(err)),
+    };
+
+    Box::new(future::ok(()).and_then(move |_| {
+        let path = flag_req.path;
+        let key = Grant::store_key(&user, Some(&path.env));
+        let grant = state
+            .grants()
+            .get(&Grant::store_path(&path.owner, &path.app), &key)
+            .map_err(APIError::read)?
+            .ok_or(APIError::FailedToFind)?;
+
+        access::outrank(flag_req.role, grant.role)?;
+        remove_grant(&state, &path.owner, &path.app, &key)
+    }))
+}
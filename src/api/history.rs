@@ -0,0 +1,57 @@
+use actix_web::*;
+use futures::{future, Future};
+use serde_json;
+
+use api::State;
+use api::error::APIError;
+use api::flag_req::FlagReq;
+use flag::FlagPath;
+use history::HistoryEntry;
+
+const DEFAULT_LIMIT: usize = 100;
+
+// Records a change that has already been made. Failing to record it is
+// logged rather than failing the change.
+pub fn record(state: &State, path: &FlagPath, entry: HistoryEntry) {
+    if let Err(err) = state
+        .history()
+        .upsert(&HistoryEntry::store_path(path), &entry.id.clone(), &entry)
+    {
+        error!("Failed to record history of {} in {}: {}", entry.key, path.as_ref(), err);
+    }
+}
+
+fn list(state: &State, flag_req: &FlagReq, limit: usize) -> Result<HttpResponse, APIError> {
+    let mut entries = state
+        .history()
+        .get_all(&HistoryEntry::store_path(&flag_req.path))
+        .map_err(APIError::read)?
+        .into_iter()
+        .map(|(_, entry)| entry)
+        .filter(|entry| flag_req.key.as_ref().map(|key| key == &entry.key).unwrap_or(true))
+        .collect::<Vec<HistoryEntry>>();
+
+    // Newest first
+    entries.sort_by(|a, b| b.id.cmp(&a.id));
+    entries.truncate(limit);
+
+    Ok(serde_json::to_string(&entries)
+        .or(Err(APIError::FailedToSerialize))
+        .into())
+}
+
+// Lists changes to every flag of a path, or to a single flag when the route
+// has a key
+pub fn all<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+    let flag_req = match FlagReq::from_req(&req) {
+        Ok(res) => res,
+        Err(err) => return Box::new(future::err(err)),
+    };
+    let limit = req.query()
+        .get("limit")
+        .and_then(|limit| limit.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_LIMIT);
+
+    Box::new(future::ok(()).and_then(move |_| list(&state, &flag_req, limit)))
+}
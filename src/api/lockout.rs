@@ -0,0 +1,116 @@
+use actix_web::*;
+use actix_web::http::StatusCode;
+use futures::{future, Future};
+use serde_json;
+
+use api::State;
+use api::error::APIError;
+use lockout::{forwarded_ip, Attempts};
+use util::current_time;
+
+pub const LOCKOUT_PATH: &'static str = "lockouts";
+
+// Failed attempts are tracked against the key being guessed and against the
+// address the guesses come from. When a client address header is configured
+// only that header is used, as the peer is then the proxy.
+pub fn subjects(key: &str, req: &HttpRequest<State>) -> Vec<String> {
+    let mut subjects = vec![format!("key:{}", key)];
+
+    let addr = match req.state().lockout_config().ip_header {
+        Some(ref header) => req.headers()
+            .get(header.as_str())
+            .and_then(|value| value.to_str().ok())
+            .and_then(forwarded_ip),
+        None => req.peer_addr().map(|addr| addr.ip()),
+    };
+
+    if let Some(addr) = addr {
+        subjects.push(format!("ip:{}", addr));
+    }
+
+    subjects
+}
+
+fn find(state: &State, subject: &str) -> Option<Attempts> {
+    state
+        .lockouts()
+        .get(&LOCKOUT_PATH.to_string(), subject)
+        .unwrap_or(None)
+}
+
+pub fn is_locked(state: &State, subjects: &[String]) -> bool {
+    let now = current_time();
+
+    subjects
+        .iter()
+        .filter_map(|subject| find(state, subject))
+        .any(|attempts| attempts.is_locked(now))
+}
+
+pub fn record_failure(state: &State, subjects: &[String]) {
+    let now = current_time();
+
+    for subject in subjects {
+        let mut attempts = find(state, subject).unwrap_or(Attempts::new(subject.as_str()));
+        attempts.record_failure(now, state.lockout_config());
+
+        if attempts.is_locked(now) {
+            warn!("Locked out {} after {} failed logins", subject, attempts.failures);
+        }
+
+        if state
+            .lockouts()
+            .upsert(&LOCKOUT_PATH.to_string(), subject, &attempts)
+            .is_err()
+        {
+            error!("Failed to store failed login for {}", subject);
+        }
+    }
+}
+
+// A successful login only clears the key. The address keeps its count so a
+// single valid account can not be used to reset guessing against others.
+pub fn clear_key(state: &State, key: &str) {
+    let subject = format!("key:{}", key);
+
+    if find(state, &subject).is_some() {
+        let _ = state.lockouts().delete(&LOCKOUT_PATH.to_string(), &subject);
+    }
+}
+
+pub fn all<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+
+    Box::new(future::ok(()).and_then(move |_| {
+        let mut attempts = state
+            .lockouts()
+            .get_all(&LOCKOUT_PATH.to_string())
+            .map_err(APIError::read)?
+            .into_iter()
+            .map(|(_, attempts)| attempts)
+            .collect::<Vec<Attempts>>();
+        attempts.as_mut_slice().sort_by(|a, b| a.id.cmp(&b.id));
+
+        Ok(serde_json::to_string(&attempts)
+            .or(Err(APIError::FailedToSerialize))
+            .into())
+    }))
+}
+
+pub fn delete<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+    let subject = match req.match_info().get("subject") {
+        Some(subject) => subject.to_string(),
+        None => return Box::new(future::err(APIError::FailedToParseParams)),
+    };
+
+    Box::new(future::ok(()).and_then(move |_| {
+        state
+            .lockouts()
+            .delete(&LOCKOUT_PATH.to_string(), &subject)
+            .map_err(APIError::write)?
+            .ok_or(APIError::FailedToFind)?;
+
+        Ok(HttpResponse::new(StatusCode::OK))
+    }))
+}
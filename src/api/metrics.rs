@@ -0,0 +1,125 @@
+use actix_web::{HttpRequest, HttpResponse, ResponseError, Result};
+use actix_web::middleware::{Middleware, Response, Started};
+use http::header;
+use ring::constant_time::verify_slices_are_equal;
+
+use std::collections::HashMap;
+use std::env;
+use std::time::Instant;
+
+use api::State;
+use api::error::APIError;
+use metrics;
+
+const CONTENT_TYPE: &'static str = "text/plain; version=0.0.4";
+
+lazy_static! {
+    // Scrapes of the metrics listener are open unless a token is configured.
+    // The metrics name the apps and environments that have open streams, but
+    // not who owns them.
+    static ref METRICS_TOKEN: Option<String> = env::var("METRICS_TOKEN").ok().filter(|token| !token.is_empty());
+}
+
+struct RequestStart(Instant);
+
+// Counts requests and their latency by the route pattern that matched, so
+// that paths with ids in them do not each become a series
+#[derive(Debug)]
+pub struct RequestMetrics(pub &'static str);
+
+impl<S> Middleware<S> for RequestMetrics {
+    fn start(&self, req: &HttpRequest<S>) -> Result<Started> {
+        req.extensions_mut().insert(RequestStart(Instant::now()));
+        Ok(Started::Done)
+    }
+
+    fn response(&self, req: &HttpRequest<S>, resp: HttpResponse) -> Result<Response> {
+        let route = req.resource()
+            .rdef()
+            .map(|rdef| rdef.pattern().to_string())
+            .unwrap_or("unmatched".to_string());
+        let method = req.method().as_str().to_string();
+        let status = resp.status().as_u16().to_string();
+
+        metrics::inc(
+            metrics::HTTP_REQUESTS,
+            &[("app", self.0), ("route", &route), ("method", &method), ("status", &status)],
+        );
+
+        if let Some(start) = req.extensions().get::<RequestStart>() {
+            metrics::observe(
+                metrics::HTTP_DURATION,
+                &[("app", self.0), ("route", &route), ("method", &method)],
+                metrics::seconds(start.0.elapsed()),
+            );
+        }
+
+        Ok(Response::Done(resp))
+    }
+}
+
+fn is_authorized(req: &HttpRequest<State>) -> bool {
+    match *METRICS_TOKEN {
+        Some(ref token) => req.headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| value.starts_with("Bearer "))
+            .map(|value| verify_slices_are_equal(value[7..].as_bytes(), token.as_bytes()).is_ok())
+            .unwrap_or(false),
+        None => true,
+    }
+}
+
+fn gauge(name: &'static str, value: &str, count: usize) -> (Vec<(&'static str, String)>, f64) {
+    (vec![(name, value.to_string())], count as f64)
+}
+
+// Gauges are read from the state when scraped rather than tracked as
+// streams open and close
+fn refresh_gauges(state: &State) {
+    metrics::set_gauges(
+        metrics::CACHE_ENTRIES,
+        vec![
+            gauge("store", "flags", state.flags().stats().cached),
+            gauge("store", "paths", state.paths().stats().cached),
+            gauge("store", "users", state.users().stats().cached),
+            gauge("store", "keys", state.keys().stats().cached),
+            gauge("store", "grants", state.grants().stats().cached),
+            gauge("store", "teams", state.teams().stats().cached),
+            gauge("store", "lockouts", state.lockouts().stats().cached),
+            gauge("store", "history", state.history().stats().cached),
+            gauge("store", "schedules", state.schedules().stats().cached),
+            gauge("store", "revocations", state.revocations().stats().cached),
+        ],
+    );
+
+    // Streams are counted per caller and path. Only the app and env of the
+    // path are kept, as owners are user and team ids.
+    let mut paths: HashMap<String, usize> = HashMap::new();
+
+    for (key, count) in state.streams().into_iter() {
+        let path = key.splitn(2, '/').nth(1).unwrap_or("");
+        let app_env = path.splitn(2, ':').nth(1).unwrap_or("").to_string();
+        *paths.entry(app_env).or_insert(0) += count;
+    }
+
+    metrics::set_gauges(
+        metrics::STREAMS,
+        paths
+            .iter()
+            .map(|(path, count)| gauge("path", path, *count))
+            .collect(),
+    );
+}
+
+pub fn scrape<'r>(req: &'r HttpRequest<State>) -> HttpResponse {
+    if !is_authorized(req) {
+        return APIError::Unauthorized.error_response();
+    }
+
+    refresh_gauges(req.state());
+
+    HttpResponse::Ok()
+        .content_type(CONTENT_TYPE)
+        .body(metrics::render())
+}
@@ -1,39 +1,153 @@
+use actix::{Actor, System};
 use actix_web::*;
+use actix_web::http::StatusCode;
+use serde::Serialize;
+use serde_json;
 
 use std::sync::Arc;
 
+use analytics::AnalyticsStore;
+use api::error::APIError;
 use error::BannerError;
 use flag::{Flag, FlagPath};
+use grant::Grant;
+use history::{HistoryEntry, Retention};
+use lockout::{Attempts, LockoutConfig};
+use revocation::Revocation;
+use schedule::Schedule;
+use sdk_key::SdkKey;
 use store::ThreadedStore;
+use team::Team;
 use user::User;
+use util::env_or;
 
+mod access;
 mod admin;
+mod analytics;
 // mod api;
 mod app;
 mod auth;
 mod error;
 mod flag;
 mod flag_req;
+mod grant;
+mod history;
+mod lockout;
+mod metrics;
+mod oidc;
 // mod frontend;
 mod path;
+mod poll;
+mod promote;
+mod prune;
+mod report;
+mod request_id;
+mod rollout;
+mod schedule;
+mod sdk_key;
+mod session;
+mod socket;
 mod state;
+mod status;
 mod stream;
+mod team;
+mod transfer;
+mod user;
 
 type State = Arc<state::AppState>;
 
-pub fn boot<T, S, U>(flags: T, paths: S, users: U)
+const DEFAULT_METRICS_ADDR: &'static str = "127.0.0.1:9100";
+
+pub fn json_resp<T: Serialize>(status: StatusCode, body: &T) -> Result<HttpResponse, APIError> {
+    serde_json::to_string(body)
+        .or(Err(APIError::FailedToSerialize))
+        .map(|json| {
+            HttpResponse::build(status)
+                .content_type("application/json")
+                .body(json)
+        })
+}
+
+pub fn boot<T, S, U, K, G, M, L, H, C, R>(
+    flags: T,
+    paths: S,
+    users: U,
+    keys: K,
+    grants: G,
+    teams: M,
+    lockouts: L,
+    history: H,
+    schedules: C,
+    revocations: R,
+    analytics: Box<AnalyticsStore>,
+)
 where
     T: ThreadedStore<FlagPath, Flag, Error = BannerError> + 'static,
     S: ThreadedStore<String, FlagPath, Error = BannerError> + 'static,
     U: ThreadedStore<String, User, Error = BannerError> + 'static,
+    K: ThreadedStore<String, SdkKey, Error = BannerError> + 'static,
+    G: ThreadedStore<String, Grant, Error = BannerError> + 'static,
+    M: ThreadedStore<String, Team, Error = BannerError> + 'static,
+    L: ThreadedStore<String, Attempts, Error = BannerError> + 'static,
+    H: ThreadedStore<String, HistoryEntry, Error = BannerError> + 'static,
+    C: ThreadedStore<String, Schedule, Error = BannerError> + 'static,
+    R: ThreadedStore<String, Revocation, Error = BannerError> + 'static,
 {
-    let state = Arc::new(state::AppState::new(flags, paths, users));
+    let state = Arc::new(state::AppState::new(
+        flags,
+        paths,
+        users,
+        keys,
+        grants,
+        teams,
+        lockouts,
+        LockoutConfig::from_env(),
+        history,
+        Retention::from_env(),
+        schedules,
+        revocations,
+        analytics,
+        stream::StreamConfig::from_env(),
+        session::Sessions::from_env(),
+        oidc::Oidc::from_env(),
+    ));
     // HttpServer::new(|| Application::new().resource("/", |r| r.f(index)))
     //     .bind("127.0.0.1:443")
     //     .expect("Can not bind to 127.0.0.1:443")
     //     .run();
-    server::new(move || vec![app::api(state.clone()), app::frontend(state.clone())])
+
+    // The server is started inside a system of our own so that the
+    // scheduler and pruner run next to it
+    let sys = System::new("masquerade");
+    let scheduled = state.clone();
+    let pruned = state.clone();
+    let metrics_state = state.clone();
+
+    server::new(move || vec![
+            app::session(state.clone()),
+            app::admin(state.clone()),
+            app::oidc(state.clone()),
+            app::api(state.clone()),
+            app::status(state.clone()),
+            app::frontend(state.clone()),
+        ])
         .bind("0.0.0.0:8088")
         .expect("Can not bind to 0.0.0.0:8088")
-        .run();
+        .start();
+
+    // Metrics listen on the loopback unless told otherwise, for a scraper
+    // running next to the server
+    let metrics_addr = env_or("METRICS_ADDR", DEFAULT_METRICS_ADDR.to_string());
+
+    server::new(move || app::metrics(metrics_state.clone()))
+        .bind(metrics_addr.as_str())
+        .expect("Can not bind the metrics listener")
+        .start();
+
+    if schedule::Scheduler::is_enabled() {
+        schedule::Scheduler::from_env(scheduled).start();
+    }
+
+    prune::Pruner::from_env(pruned).start();
+    sys.run();
 }
@@ -0,0 +1,440 @@
+use actix_web::*;
+use actix_web::http::header;
+use base64::{encode_config, URL_SAFE_NO_PAD};
+use futures::{future, Future};
+use ring::rand::{SecureRandom, SystemRandom};
+use uuid::Uuid;
+
+use std::env;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use api::State;
+use api::error::APIError;
+use api::session::{self, session_token, SESSION_COOKIE};
+use api::user::USER_PATH;
+use oidc::{validate_id_token, IdToken, JwkSet, OidcError, Validation};
+use user::User;
+use util::current_time;
+
+// Holds the state and nonce of a login in progress
+const AUTH_COOKIE: &'static str = "oidc_auth";
+const AUTH_COOKIE_AGE: u64 = 600;
+const ISSUER_TIMEOUT: u64 = 10;
+
+#[derive(Debug, Clone)]
+pub struct OidcConfig {
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub redirect_uri: String,
+    pub scopes: String,
+    pub user_claim: String,
+    pub auto_provision: bool,
+}
+
+impl OidcConfig {
+    // Login through an issuer is only enabled when one is configured. Any
+    // issuer that serves a discovery document works, including local mock
+    // issuers served over plain http.
+    pub fn from_env() -> Option<OidcConfig> {
+        let issuer = env::var("OIDC_ISSUER").ok()?;
+
+        Some(OidcConfig {
+            issuer: issuer.trim_right_matches('/').to_string(),
+            client_id: env::var("OIDC_CLIENT_ID").unwrap_or(String::new()),
+            client_secret: env::var("OIDC_CLIENT_SECRET").unwrap_or(String::new()),
+            redirect_uri: env::var("OIDC_REDIRECT_URI")
+                .unwrap_or("http://localhost:8088/auth/callback/".to_string()),
+            scopes: env::var("OIDC_SCOPES").unwrap_or("openid email profile".to_string()),
+            user_claim: env::var("OIDC_USER_CLAIM").unwrap_or("email".to_string()),
+            auto_provision: env::var("OIDC_AUTO_PROVISION")
+                .map(|val| val == "true" || val == "1")
+                .unwrap_or(false),
+        })
+    }
+
+    fn secure_cookies(&self) -> bool {
+        self.redirect_uri.starts_with("https://")
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Discovery {
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+    #[serde(default)]
+    id_token_signing_alg_values_supported: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct TokenResp {
+    id_token: String,
+}
+
+#[derive(Serialize)]
+struct TokenReq<'a> {
+    grant_type: &'a str,
+    code: &'a str,
+    redirect_uri: &'a str,
+    client_id: &'a str,
+    client_secret: &'a str,
+}
+
+// The discovery document and signing keys of the issuer are fetched on first
+// use. Keys are fetched again when a token is signed with an unknown key, as
+// happens after the issuer rotates them.
+pub struct Oidc {
+    config: OidcConfig,
+    discovery: RwLock<Option<Discovery>>,
+    keys: RwLock<Option<JwkSet>>,
+}
+
+impl Oidc {
+    pub fn new(config: OidcConfig) -> Oidc {
+        Oidc {
+            config: config,
+            discovery: RwLock::new(None),
+            keys: RwLock::new(None),
+        }
+    }
+
+    pub fn from_env() -> Option<Oidc> {
+        OidcConfig::from_env().map(Oidc::new)
+    }
+
+    fn cached_discovery(&self) -> Option<Discovery> {
+        self.discovery.read().ok().and_then(|disc| disc.clone())
+    }
+
+    fn cached_keys(&self) -> Option<JwkSet> {
+        self.keys.read().ok().and_then(|keys| keys.clone())
+    }
+}
+
+fn random_token() -> Result<String, APIError> {
+    let mut bytes = [0u8; 24];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .or(Err(APIError::FailedToGenerateToken))?;
+
+    Ok(encode_config(&bytes, URL_SAFE_NO_PAD))
+}
+
+fn encode_param(val: &str) -> String {
+    val.bytes()
+        .map(|b| match b {
+            b'A'...b'Z' | b'a'...b'z' | b'0'...b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn cookie(name: &str, val: &str, max_age: Option<u64>, secure: bool) -> String {
+    // SameSite=Lax keeps the cookies off cross site requests other than top
+    // level navigation, which the issuer's redirect back to us relies on
+    let mut cookie = [name, "=", val, "; Path=/; HttpOnly; SameSite=Lax"].concat();
+
+    if let Some(age) = max_age {
+        cookie.push_str(&format!("; Max-Age={}", age));
+    }
+
+    if secure {
+        cookie.push_str("; Secure");
+    }
+
+    cookie
+}
+
+fn oidc(state: &State) -> Result<&Oidc, APIError> {
+    state.oidc().ok_or(APIError::FailedToFind)
+}
+
+fn fetch_json<T>(url: &str) -> Box<Future<Item = T, Error = APIError>>
+where
+    T: ::serde::de::DeserializeOwned + 'static,
+{
+    match client::get(url).timeout(Duration::from_secs(ISSUER_TIMEOUT)).finish() {
+        Ok(req) => Box::new(
+            req.send()
+                .map_err(|_| APIError::FailedToReachIssuer)
+                .and_then(|resp| resp.json::<T>().map_err(|_| APIError::FailedToReachIssuer)),
+        ),
+        Err(_) => Box::new(future::err(APIError::FailedToReachIssuer)),
+    }
+}
+
+fn discovery(state: State) -> Box<Future<Item = Discovery, Error = APIError>> {
+    let issuer = match oidc(&state) {
+        Ok(oidc) => match oidc.cached_discovery() {
+            Some(disc) => return Box::new(future::ok(disc)),
+            None => oidc.config.issuer.clone(),
+        },
+        Err(err) => return Box::new(future::err(err)),
+    };
+
+    let url = [issuer.as_str(), "/.well-known/openid-configuration"].concat();
+
+    Box::new(fetch_json::<Discovery>(&url).and_then(move |disc| {
+        if let Ok(mut cached) = oidc(&state)?.discovery.write() {
+            *cached = Some(disc.clone());
+        }
+
+        Ok(disc)
+    }))
+}
+
+fn keys(state: State, jwks_uri: String, refresh: bool) -> Box<Future<Item = JwkSet, Error = APIError>> {
+    if !refresh {
+        if let Some(keys) = oidc(&state).ok().and_then(|oidc| oidc.cached_keys()) {
+            return Box::new(future::ok(keys));
+        }
+    }
+
+    Box::new(fetch_json::<JwkSet>(&jwks_uri).and_then(move |keys| {
+        if let Ok(mut cached) = oidc(&state)?.keys.write() {
+            *cached = Some(keys.clone());
+        }
+
+        Ok(keys)
+    }))
+}
+
+fn validate(state: &State, id_token: &str, keys: &JwkSet, nonce: &str) -> Result<IdToken, OidcError> {
+    let oidc = oidc(state).or(Err(OidcError::UnknownKey))?;
+    let config = &oidc.config;
+
+    // The client secret only verifies tokens of issuers that say they sign
+    // ID tokens with it
+    let hs256 = oidc
+        .cached_discovery()
+        .map(|disc| disc.id_token_signing_alg_values_supported.iter().any(|alg| alg == "HS256"))
+        .unwrap_or(false);
+
+    validate_id_token(
+        id_token,
+        keys,
+        &Validation {
+            issuer: &config.issuer,
+            client_id: &config.client_id,
+            client_secret: Some(config.client_secret.as_str()).filter(|secret| hs256 && !secret.is_empty()),
+            nonce: nonce,
+            now: current_time(),
+        },
+    )
+}
+
+type IdFuture = Box<Future<Item = IdToken, Error = APIError>>;
+
+fn verify_id_token(state: State, jwks_uri: String, id_token: String, nonce: String) -> IdFuture {
+    Box::new(
+        keys(state.clone(), jwks_uri.clone(), false).and_then(move |cached| {
+            match validate(&state, &id_token, &cached, &nonce) {
+                Ok(id) => Box::new(future::ok(id)) as IdFuture,
+                Err(OidcError::UnknownKey) => Box::new(
+                    keys(state.clone(), jwks_uri, true).and_then(move |fresh| {
+                        validate(&state, &id_token, &fresh, &nonce).or(Err(APIError::Unauthorized))
+                    }),
+                ) as IdFuture,
+                Err(err) => {
+                    warn!("Rejected ID token: {:?}", err);
+                    Box::new(future::err(APIError::Unauthorized)) as IdFuture
+                }
+            }
+        }),
+    )
+}
+
+// Finds the user named by the configured claim, creating it when automatic
+// provisioning is enabled. Provisioned users get a random secret that is
+// never shown, they can only sign in through the issuer until it is reset.
+fn find_or_provision(state: &State, id: &IdToken) -> Result<User, APIError> {
+    let config = &oidc(state)?.config;
+    let key = id.claim(&config.user_claim).ok_or(APIError::Unauthorized)?;
+
+    // An unverified email address could belong to someone else
+    let unverified = id.claim("email_verified").is_some() && !id.is_true("email_verified");
+
+    if config.user_claim == "email" && unverified {
+        return Err(APIError::Forbidden);
+    }
+
+    let existing = state
+        .users()
+        .get(&USER_PATH.to_string(), &key)
+        .map_err(APIError::read)?;
+
+    match existing {
+        Some(user) => if user.is_disabled() {
+            Err(APIError::Forbidden)
+        } else {
+            Ok(user)
+        },
+        None if config.auto_provision => {
+            let user = User::new(Uuid::new_v4().to_string(), key, User::generate_secret(), false);
+
+            state
+                .users()
+                .upsert(&USER_PATH.to_string(), user.key.as_str(), &user)
+                .map_err(APIError::write)?;
+
+            info!("Provisioned user {} from issuer subject {}", user.uuid, id.sub);
+            Ok(user)
+        }
+        None => Err(APIError::Forbidden),
+    }
+}
+
+pub fn login<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+
+    Box::new(discovery(state.clone()).and_then(move |disc| {
+        let config = &oidc(&state)?.config;
+        let auth_state = random_token()?;
+        let nonce = random_token()?;
+
+        let params = [
+            ("response_type", "code"),
+            ("client_id", config.client_id.as_str()),
+            ("redirect_uri", config.redirect_uri.as_str()),
+            ("scope", config.scopes.as_str()),
+            ("state", auth_state.as_str()),
+            ("nonce", nonce.as_str()),
+        ].iter()
+            .map(|&(name, val)| [name, "=", &encode_param(val)].concat())
+            .collect::<Vec<String>>()
+            .join("&");
+
+        let sep = if disc.authorization_endpoint.contains('?') { "&" } else { "?" };
+
+        Ok(HttpResponse::Found()
+            .header(header::LOCATION, [disc.authorization_endpoint.as_str(), sep, &params].concat())
+            .header(
+                header::SET_COOKIE,
+                cookie(
+                    AUTH_COOKIE,
+                    &[auth_state.as_str(), ":", nonce.as_str()].concat(),
+                    Some(AUTH_COOKIE_AGE),
+                    config.secure_cookies(),
+                ),
+            )
+            .finish())
+    }))
+}
+
+pub fn callback<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+    let code = req.query().get("code").map(|code| code.to_string());
+    let returned_state = req.query().get("state").map(|val| val.to_string());
+    let pending = req.cookie(AUTH_COOKIE).map(|cookie| cookie.value().to_string());
+
+    // The state must match the one set on this browser when the login
+    // started, otherwise the response may have been forged
+    let (code, nonce) = match (code, returned_state, pending) {
+        (Some(code), Some(returned), Some(pending)) => {
+            let mut parts = pending.splitn(2, ':');
+
+            match (parts.next(), parts.next()) {
+                (Some(expected), Some(nonce)) if expected == returned => (code, nonce.to_string()),
+                _ => return Box::new(future::err(APIError::Unauthorized)),
+            }
+        }
+        _ => return Box::new(future::err(APIError::Unauthorized)),
+    };
+
+    let exchange_state = state.clone();
+
+    Box::new(
+        discovery(state.clone())
+            .and_then(move |disc| {
+                let req = {
+                    let config = &oidc(&exchange_state)?.config;
+
+                    client::post(disc.token_endpoint.as_str())
+                        .timeout(Duration::from_secs(ISSUER_TIMEOUT))
+                        .form(TokenReq {
+                            grant_type: "authorization_code",
+                            code: &code,
+                            redirect_uri: &config.redirect_uri,
+                            client_id: &config.client_id,
+                            client_secret: &config.client_secret,
+                        })
+                        .map_err(|_| APIError::FailedToReachIssuer)?
+                };
+
+                Ok((req, disc.jwks_uri))
+            })
+            .and_then(|(req, jwks_uri)| {
+                req.send()
+                    .map_err(|_| APIError::FailedToReachIssuer)
+                    .and_then(|resp| {
+                        resp.json::<TokenResp>()
+                            .map_err(|_| APIError::FailedToReachIssuer)
+                    })
+                    .map(move |tokens| (tokens.id_token, jwks_uri))
+            })
+            .and_then({
+                let state = state.clone();
+                move |(id_token, jwks_uri)| verify_id_token(state, jwks_uri, id_token, nonce)
+            })
+            .and_then(move |id| {
+                let user = find_or_provision(&state, &id)?;
+                let (token, _) = state.sessions().issue(&user)?;
+                let secure = oidc(&state)?.config.secure_cookies();
+
+                Ok(HttpResponse::Found()
+                    .header(header::LOCATION, "/")
+                    .header(header::SET_COOKIE, cookie(SESSION_COOKIE, &token, None, secure))
+                    .header(header::SET_COOKIE, cookie(AUTH_COOKIE, "", Some(0), secure))
+                    .finish())
+            }),
+    )
+}
+
+pub fn logout<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+    let token = session_token(req);
+
+    Box::new(future::ok(()).and_then(move |_| {
+        if let Some(claims) = token.and_then(|token| session::verify(&state, &token)) {
+            session::revoke(&state, &claims)?;
+        }
+
+        let secure = oidc(&state)?.config.secure_cookies();
+
+        Ok(HttpResponse::Found()
+            .header(header::LOCATION, "/")
+            .header(header::SET_COOKIE, cookie(SESSION_COOKIE, "", Some(0), secure))
+            .finish())
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encodes_params() {
+        assert_eq!(encode_param("openid email"), "openid%20email");
+        assert_eq!(
+            encode_param("http://localhost/cb?a=b"),
+            "http%3A%2F%2Flocalhost%2Fcb%3Fa%3Db"
+        );
+        assert_eq!(encode_param("a-b_c.d~"), "a-b_c.d~");
+    }
+
+    #[test]
+    fn test_builds_cookies() {
+        assert_eq!(
+            cookie("session", "token", None, false),
+            "session=token; Path=/; HttpOnly; SameSite=Lax"
+        );
+        assert_eq!(
+            cookie("session", "", Some(0), true),
+            "session=; Path=/; HttpOnly; SameSite=Lax; Max-Age=0; Secure"
+        );
+    }
+}
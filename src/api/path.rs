@@ -6,8 +6,10 @@ use serde_json;
 use std::str;
 
 use api::State;
+use api::access;
 use api::error::APIError;
 use flag::FlagPath;
+use grant::Role;
 use user::User;
 
 const PATH_KEY: &'static str = "paths";
@@ -16,21 +18,25 @@ const PATH_KEY: &'static str = "paths";
 struct FlagPathReq {
     pub app: String,
     pub env: String,
+    pub owner: Option<String>,
 }
 
 pub fn create<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
     let state = req.state().clone();
-    let ext = req.extensions();
-    let u = ext.get::<User>();
+    let user = req.extensions().get::<User>().cloned();
 
-    if let Some(u) = ext.get::<User>() {
-        let user = u.clone();
-        
+    if let Some(user) = user {
         req.json()
             .from_err()
             .and_then(move |f_path_req: FlagPathReq| {
                 let path = PATH_KEY.to_string();
-                let f_path = FlagPath::new(user.uuid.clone(), f_path_req.app, f_path_req.env);
+                let owner = f_path_req.owner.unwrap_or(user.uuid.clone());
+
+                // Only owners of an app may add environments to it
+                let role = access::role_for(&state, &user, &owner, &f_path_req.app, None)?;
+                access::require(role, Role::Owner)?;
+
+                let f_path = FlagPath::new(owner, f_path_req.app, f_path_req.env);
 
                 if let Ok(Some(_exists)) = state.paths().get(&path, f_path.as_ref()) {
                     Err(APIError::AlreadyExists)?
@@ -40,18 +46,51 @@ pub fn create<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse
                     .paths()
                     .upsert(&path, f_path.as_ref(), &f_path)
                     .and_then(|_| Ok(HttpResponse::new(StatusCode::CREATED)))
-                    .map_err(|_| APIError::FailedToWriteToStore)
+                    .map_err(APIError::write)
             })
             .responder()
     } else {
         Box::new(future::err(APIError::Unauthorized))
     }
-
-    
 }
 
+// Lists the paths the caller holds any role on
 pub fn all<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
     let state = req.state().clone();
+    let user = match req.extensions().get::<User>().cloned() {
+        Some(user) => user,
+        None => return Box::new(future::err(APIError::Unauthorized)),
+    };
+
+    Box::new(future::ok(()).and_then(move |_| {
+        Ok(serde_json::to_string(&visible(&state, &user)?)
+            .or(Err(APIError::FailedToSerialize))
+            .into())
+    }))
+}
+
+pub fn visible(state: &State, user: &User) -> Result<Vec<FlagPath>, APIError> {
+    let paths = state
+        .paths()
+        .get_all(&PATH_KEY.to_string())
+        .map_err(APIError::read)?;
+
+    let mut visible = vec![];
+
+    for f_path in paths.into_iter().map(|(_, f_path)| f_path) {
+        let role = access::role_for(state, user, &f_path.owner, &f_path.app, Some(&f_path.env))?;
+
+        if role.is_some() {
+            visible.push(f_path);
+        }
+    }
+
+    Ok(visible)
+}
+
+// Lists every path regardless of roles, for admins only
+pub fn all_admin<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
 
     Box::new(future::ok(()).and_then(move |_| {
         state
@@ -64,6 +103,6 @@ pub fn all<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, E
                         .into(),
                 )
             })
-            .map_err(|_| APIError::FailedToAccessStore)
+            .map_err(APIError::read)
     }))
 }
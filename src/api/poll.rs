@@ -0,0 +1,152 @@
+use actix_web::{HttpRequest, HttpResponse};
+use actix_web::http::StatusCode;
+use futures::{future, task, Async, Future, Poll};
+use tokio::timer::Delay;
+use uuid::Uuid;
+
+use std::cmp;
+use std::time::{Duration, Instant};
+
+use api::State;
+use api::error::APIError;
+use api::flag::{flag_response, if_none_match};
+use api::flag_req::FlagReq;
+use flag::FlagPath;
+
+const DEFAULT_TIMEOUT: u64 = 30;
+
+// Holds a flag list request open until the path no longer matches the
+// client's ETag or the timeout expires, in which case 304 is returned
+struct FlagPoll {
+    id: String,
+    path: FlagPath,
+    state: State,
+    if_none_match: Option<String>,
+    timeout: Delay,
+    subbed: bool,
+}
+
+impl Future for FlagPoll {
+    type Item = HttpResponse;
+    type Error = APIError;
+
+    fn poll(&mut self) -> Poll<HttpResponse, APIError> {
+        if !self.subbed {
+            self.subbed =
+                self.state
+                    .flags()
+                    .sub(self.id.as_str(), &self.path, Some(task::current()));
+        }
+
+        let flags = self.state
+            .flags()
+            .get_all(&self.path)
+            .map_err(APIError::read)?;
+        let resp = flag_response(&flags, self.if_none_match.as_ref())?;
+
+        if resp.status() != StatusCode::NOT_MODIFIED {
+            return Ok(Async::Ready(resp));
+        }
+
+        match self.timeout.poll() {
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            _ => Ok(Async::Ready(resp)),
+        }
+    }
+}
+
+impl Drop for FlagPoll {
+    fn drop(&mut self) {
+        self.state.flags().unsub(self.id.as_str(), &self.path);
+    }
+}
+
+pub fn flag_poll<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let flag_req = match FlagReq::from_req(&req) {
+        Ok(res) => res,
+        Err(err) => return Box::new(future::err(err)),
+    };
+
+    let max = req.state().stream_config().max_poll;
+    let timeout = req.query()
+        .get("timeout")
+        .and_then(|secs| secs.parse::<u64>().ok())
+        .map(|secs| cmp::min(Duration::from_secs(secs), max))
+        .unwrap_or(cmp::min(Duration::from_secs(DEFAULT_TIMEOUT), max));
+
+    Box::new(FlagPoll {
+        id: Uuid::new_v4().to_string(),
+        path: flag_req.path,
+        state: req.state().clone(),
+        if_none_match: if_none_match(req),
+        timeout: Delay::new(Instant::now() + timeout),
+        subbed: false,
+    })
+}
+
+#[cfg(all(test, feature = "mem-backend"))]
+mod tests {
+    use super::*;
+
+    use actix_web::http::header;
+    use tokio::runtime::current_thread::Runtime;
+
+    use std::thread;
+
+    use api::state::mem_state;
+    use flag::{Flag, FlagValue};
+
+    fn poll(state: &State, path: &FlagPath, tag: Option<String>, secs: u64) -> FlagPoll {
+        FlagPoll {
+            id: Uuid::new_v4().to_string(),
+            path: path.clone(),
+            state: state.clone(),
+            if_none_match: tag,
+            timeout: Delay::new(Instant::now() + Duration::from_secs(secs)),
+            subbed: false,
+        }
+    }
+
+    fn current_tag(state: &State, path: &FlagPath) -> String {
+        let flags = state.flags().get_all(path).unwrap();
+        let resp = flag_response(&flags, None).unwrap();
+
+        resp.headers()[header::ETAG].to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_not_modified_until_timeout() {
+        let state = mem_state();
+        let path = FlagPath::new("owner", "app", "env");
+        let _ = state.flags().upsert(&path, "f1", &Flag::new("f1", FlagValue::Bool(true), 1, true));
+        let tag = current_tag(&state, &path);
+
+        let resp = Runtime::new().unwrap().block_on(poll(&state, &path, Some(tag), 0)).unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+
+        let resp = Runtime::new().unwrap().block_on(poll(&state, &path, Some("\"stale\"".into()), 5)).unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn test_wakes_on_change() {
+        let state = mem_state();
+        let path = FlagPath::new("owner", "app", "env");
+        let _ = state.flags().upsert(&path, "f1", &Flag::new("f1", FlagValue::Bool(true), 1, true));
+        let tag = current_tag(&state, &path);
+
+        let writer = state.clone();
+        let written = path.clone();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            let _ = writer.flags().upsert(&written, "f2", &Flag::new("f2", FlagValue::Bool(true), 1, true));
+        });
+
+        let started = Instant::now();
+        let resp = Runtime::new().unwrap().block_on(poll(&state, &path, Some(tag), 10)).unwrap();
+        let _ = handle.join();
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,304 @@
+use actix_web::*;
+use futures::Future;
+use serde_json;
+
+use std::collections::HashMap;
+
+use api::State;
+use api::access;
+use api::error::{APIError, FieldError};
+use api::history;
+use flag::{Flag, FlagPath};
+use grant::Role;
+use history::{HistoryAction, HistoryEntry};
+use user::User;
+
+const PATH_KEY: &'static str = "paths";
+const PROMOTE_SOURCE: &'static str = "promote";
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PromoteMode {
+    // Creates missing flags and brings existing ones in line with the source
+    Overwrite,
+    // Only creates flags that the target does not have yet
+    Missing,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PromoteReq {
+    pub from: String,
+    pub to: String,
+    #[serde(default)]
+    pub keys: Option<Vec<String>>,
+    pub mode: PromoteMode,
+    #[serde(default)]
+    pub keep_enabled: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Create,
+    Update,
+    Unchanged,
+    Skip,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PromoteChange {
+    pub key: String,
+    pub change: ChangeKind,
+    pub before: Option<Flag>,
+    pub after: Option<Flag>,
+}
+
+#[derive(Debug, Serialize)]
+struct PromoteResp<'a> {
+    from: &'a str,
+    to: &'a str,
+    applied: bool,
+    changes: &'a [PromoteChange],
+}
+
+// The flag the target would end up with
+fn promoted(source: &Flag, target: Option<&Flag>, keep_enabled: bool) -> Flag {
+    match target {
+        Some(target) => {
+            let mut flag = target.clone();
+            flag.update_from(source, keep_enabled);
+            flag
+        }
+        None => Flag::copy_of(source, PROMOTE_SOURCE),
+    }
+}
+
+// Works out what promoting would change without writing anything
+pub fn plan(
+    source: &HashMap<String, Flag>,
+    target: &HashMap<String, Flag>,
+    req: &PromoteReq,
+) -> Result<Vec<PromoteChange>, APIError> {
+    let keys = match req.keys {
+        Some(ref keys) => {
+            let errors = keys.iter()
+                .filter(|key| !source.contains_key(key.as_str()))
+                .map(|key| FieldError::new("keys", format!("{} does not exist in {}", key, req.from)))
+                .collect::<Vec<FieldError>>();
+
+            if !errors.is_empty() {
+                return Err(APIError::InvalidFields(errors));
+            }
+
+            keys.clone()
+        }
+        None => source.keys().cloned().collect(),
+    };
+
+    let mut changes = keys.iter()
+        .map(|key| {
+            let flag = &source[key];
+            let before = target.get(key);
+
+            let (change, after) = match (before, req.mode) {
+                (None, _) => (ChangeKind::Create, Some(promoted(flag, None, req.keep_enabled))),
+                (Some(_), PromoteMode::Missing) => (ChangeKind::Skip, None),
+                (Some(existing), PromoteMode::Overwrite) => {
+                    let after = promoted(flag, Some(existing), req.keep_enabled);
+
+                    // Timestamps change on any update, so only the fields
+                    // that were copied are compared
+                    if after.same_as(existing) {
+                        (ChangeKind::Unchanged, None)
+                    } else {
+                        (ChangeKind::Update, Some(after))
+                    }
+                }
+            };
+
+            PromoteChange {
+                key: key.clone(),
+                change: change,
+                before: before.cloned(),
+                after: after,
+            }
+        })
+        .collect::<Vec<PromoteChange>>();
+
+    changes.sort_by(|a, b| a.key.cmp(&b.key));
+    changes.dedup_by(|a, b| a.key == b.key);
+
+    Ok(changes)
+}
+
+// The caller needs to be able to read the source and edit the target, and
+// both environments have to exist
+fn paths(state: &State, req: &HttpRequest<State>, promote: &PromoteReq) -> Result<(FlagPath, FlagPath, String), APIError> {
+    let user = req.extensions()
+        .get::<User>()
+        .cloned()
+        .ok_or(APIError::Unauthorized)?;
+    let app = req.match_info()
+        .get("app")
+        .map(|app| app.to_string())
+        .ok_or(APIError::FailedToParseParams)?;
+    let owner = access::owner(req, &user);
+
+    if promote.from == promote.to {
+        return Err(APIError::invalid("to", "must differ from the source environment"));
+    }
+
+    access::require(access::role_for(state, &user, &owner, &app, Some(&promote.from))?, Role::Viewer)?;
+    access::require(access::role_for(state, &user, &owner, &app, Some(&promote.to))?, Role::Editor)?;
+
+    let from = FlagPath::new(owner.as_str(), app.as_str(), promote.from.as_str());
+    let to = FlagPath::new(owner.as_str(), app.as_str(), promote.to.as_str());
+
+    for path in [&from, &to].iter() {
+        state
+            .paths()
+            .get(&PATH_KEY.to_string(), path.as_ref())
+            .map_err(APIError::read)?
+            .ok_or(APIError::FailedToFind)?;
+    }
+
+    Ok((from, to, user.uuid))
+}
+
+fn respond(promote: &PromoteReq, applied: bool, changes: &[PromoteChange]) -> Result<HttpResponse, APIError> {
+    serde_json::to_string(&PromoteResp {
+        from: &promote.from,
+        to: &promote.to,
+        applied: applied,
+        changes: changes,
+    }).or(Err(APIError::FailedToSerialize))
+        .map(|json| HttpResponse::Ok().content_type("application/json").body(json))
+}
+
+fn promote(req: &HttpRequest<State>, apply: bool) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+    let http_req = req.clone();
+
+    req.json()
+        .from_err()
+        .and_then(move |body: PromoteReq| {
+            let (from, to, actor) = paths(&state, &http_req, &body)?;
+            let source = state.flags().get_all(&from).map_err(APIError::read)?;
+            let target = state.flags().get_all(&to).map_err(APIError::read)?;
+            let changes = plan(&source, &target, &body)?;
+
+            if apply {
+                let note = ["promoted from ", body.from.as_str()].concat();
+
+                for change in changes.iter() {
+                    if let Some(ref flag) = change.after {
+                        state
+                            .flags()
+                            .upsert(&to, &change.key, flag)
+                            .map_err(APIError::write)?;
+
+                        let entry = HistoryEntry::new(
+                            change.key.as_str(),
+                            actor.as_str(),
+                            HistoryAction::Promoted,
+                            Some(flag.clone()),
+                        );
+                        history::record(&state, &to, entry.with_note(note.as_str()));
+                    }
+                }
+            }
+
+            respond(&body, apply, &changes)
+        })
+        .responder()
+}
+
+// Shows the changes a promotion would make
+pub fn preview<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    promote(req, false)
+}
+
+pub fn apply<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    promote(req, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use flag::FlagValue;
+
+    fn flags(list: Vec<Flag>) -> HashMap<String, Flag> {
+        list.into_iter()
+            .map(|flag| (flag.key().to_string(), flag))
+            .collect()
+    }
+
+    fn req(mode: PromoteMode, keep_enabled: bool) -> PromoteReq {
+        PromoteReq {
+            from: "staging".to_string(),
+            to: "prod".to_string(),
+            keys: None,
+            mode: mode,
+            keep_enabled: keep_enabled,
+        }
+    }
+
+    #[test]
+    fn test_plans_creates_and_updates() {
+        let source = flags(vec![
+            Flag::new("f1", FlagValue::Bool(true), 3, true),
+            Flag::new("f2", FlagValue::Bool(true), 1, true),
+            Flag::new("f3", FlagValue::Bool(true), 1, true),
+        ]);
+        let target = flags(vec![
+            Flag::new("f2", FlagValue::Bool(false), 1, false),
+            Flag::new("f3", FlagValue::Bool(true), 1, true),
+        ]);
+
+        let changes = plan(&source, &target, &req(PromoteMode::Overwrite, false)).unwrap();
+        let kinds = changes.iter().map(|c| c.change).collect::<Vec<ChangeKind>>();
+
+        assert_eq!(kinds, vec![ChangeKind::Create, ChangeKind::Update, ChangeKind::Unchanged]);
+        assert_eq!(changes[0].after.as_ref().unwrap().version(), 1);
+        assert_eq!(changes[1].after.as_ref().unwrap().version(), 2);
+        assert!(changes[1].after.as_ref().unwrap().is_enabled());
+    }
+
+    #[test]
+    fn test_keeps_target_enabled_state() {
+        let source = flags(vec![Flag::new("f1", FlagValue::Bool(true), 1, true)]);
+        let target = flags(vec![Flag::new("f1", FlagValue::Bool(false), 1, false)]);
+
+        let changes = plan(&source, &target, &req(PromoteMode::Overwrite, true)).unwrap();
+        let after = changes[0].after.as_ref().unwrap();
+
+        assert_eq!(after.value(), &FlagValue::Bool(true));
+        assert!(!after.is_enabled());
+    }
+
+    #[test]
+    fn test_only_creates_missing_flags() {
+        let source = flags(vec![
+            Flag::new("f1", FlagValue::Bool(true), 1, true),
+            Flag::new("f2", FlagValue::Bool(true), 1, true),
+        ]);
+        let target = flags(vec![Flag::new("f1", FlagValue::Bool(false), 1, false)]);
+
+        let mut promote = req(PromoteMode::Missing, false);
+        promote.keys = Some(vec!["f1".to_string(), "f2".to_string()]);
+
+        let changes = plan(&source, &target, &promote).unwrap();
+        let kinds = changes.iter().map(|c| c.change).collect::<Vec<ChangeKind>>();
+
+        assert_eq!(kinds, vec![ChangeKind::Skip, ChangeKind::Create]);
+    }
+
+    #[test]
+    fn test_rejects_unknown_keys() {
+        let mut promote = req(PromoteMode::Missing, false);
+        promote.keys = Some(vec!["missing".to_string()]);
+
+        assert!(plan(&HashMap::new(), &HashMap::new(), &promote).is_err());
+    }
+}
@@ -0,0 +1,91 @@
+use actix::{Actor, AsyncContext, Context};
+
+use std::time::Duration;
+
+use api::State;
+use history;
+use revocation;
+use util::{current_time, env_or};
+
+const DEFAULT_INTERVAL_SECS: u64 = 300;
+const PATH_KEY: &'static str = "paths";
+
+// Prunes every store that keeps entries past their use
+pub fn run(state: &State, now: u64) {
+    match revocation::prune(&**state.revocations(), now) {
+        Ok(pruned) => if pruned > 0 {
+            info!("Removed {} expired revocations", pruned);
+        },
+        Err(err) => error!("Failed to prune revocations: {}", err),
+    }
+
+    let paths = match state.paths().get_all(&PATH_KEY.to_string()) {
+        Ok(paths) => paths,
+        Err(err) => {
+            error!("Failed to read paths to prune history: {}", err);
+            return;
+        }
+    };
+
+    for (_, path) in paths.iter() {
+        match history::prune(&**state.history(), path, state.history_retention(), now) {
+            Ok(pruned) => if pruned > 0 {
+                info!("Removed {} history entries of {}", pruned, path.as_ref());
+            },
+            Err(err) => error!("Failed to prune history of {}: {}", path.as_ref(), err),
+        }
+    }
+}
+
+// Removes what has outlived its use from the stores on an interval, so that
+// requests never have to. Every replica runs one; removing the same entries
+// twice is harmless.
+pub struct Pruner {
+    state: State,
+    interval: Duration,
+}
+
+impl Pruner {
+    pub fn from_env(state: State) -> Pruner {
+        Pruner {
+            state: state,
+            interval: Duration::from_secs(env_or("PRUNE_INTERVAL_SECS", DEFAULT_INTERVAL_SECS).max(1)),
+        }
+    }
+}
+
+impl Actor for Pruner {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(self.interval, |act, _ctx| run(&act.state, current_time()));
+    }
+}
+
+#[cfg(all(test, feature = "mem-backend"))]
+mod tests {
+    use super::*;
+
+    use api::state::mem_state;
+    use flag::FlagPath;
+    use history::{HistoryAction, HistoryEntry};
+
+    #[test]
+    fn test_prunes_history_of_every_path() {
+        let state = mem_state();
+        let path = FlagPath::new("owner", "app", "env");
+        let store_path = HistoryEntry::store_path(&path);
+        let max = state.history_retention().max_entries;
+
+        let _ = state.paths().upsert(&PATH_KEY.to_string(), path.as_ref(), &path);
+
+        for _ in 0..max + 2 {
+            let entry = HistoryEntry::new("f1", "user", HistoryAction::Updated, None);
+            let _ = state.history().upsert(&store_path, &entry.id.clone(), &entry);
+        }
+
+        run(&state, current_time());
+
+        assert_eq!(state.history().get_all(&store_path).unwrap().len(), max);
+    }
+}
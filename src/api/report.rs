@@ -0,0 +1,45 @@
+use actix_web::{HttpRequest, HttpResponse};
+use futures::{future, Future};
+use serde_json;
+
+use api::State;
+use api::error::APIError;
+use api::path;
+use stale::{self, StaleConfig};
+use user::User;
+use util::current_time;
+
+// Lists stale flags across every path the caller can see, optionally
+// limited to one app. Thresholds may be overridden in the query.
+pub fn stale<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+    let user = match req.extensions().get::<User>().cloned() {
+        Some(user) => user,
+        None => return Box::new(future::err(APIError::Unauthorized)),
+    };
+    let query = req.query().clone();
+
+    Box::new(future::ok(()).and_then(move |_| {
+        let config = StaleConfig::from_env().with_overrides(&query);
+        let now = current_time();
+        let mut groups = vec![];
+
+        for f_path in path::visible(&state, &user)?.iter() {
+            if query.get("app").map(|app| app != &f_path.app).unwrap_or(false) {
+                continue;
+            }
+
+            let flags = state.flags().get_all(f_path).map_err(APIError::read)?;
+
+            if let Some(group) = stale::group(f_path, &flags, state.analytics(), now, &config) {
+                groups.push(group);
+            }
+        }
+
+        groups.sort_by(|a, b| (&a.owner, &a.app, &a.env).cmp(&(&b.owner, &b.app, &b.env)));
+
+        Ok(serde_json::to_string(&groups)
+            .or(Err(APIError::FailedToSerialize))
+            .into())
+    }))
+}
@@ -0,0 +1,104 @@
+use actix_web::{HttpRequest, HttpResponse, Result};
+use actix_web::Body;
+use actix_web::middleware::{Middleware, Response, Started};
+use http::header::{HeaderValue, CONTENT_TYPE};
+use serde_json;
+use serde_json::Value;
+use uuid::Uuid;
+
+use api::error::PROBLEM_JSON;
+
+pub const REQUEST_ID_HEADER: &'static str = "x-request-id";
+
+// The id of the current request, taken from the caller when it sent a
+// reasonable one so that requests can be followed through proxies
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+fn is_valid(id: &str) -> bool {
+    id.len() > 0 && id.len() <= 64
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn request_id<S>(req: &HttpRequest<S>) -> String {
+    req.extensions()
+        .get::<RequestId>()
+        .map(|id| id.0.clone())
+        .unwrap_or_default()
+}
+
+// Problem responses are built without access to the request, so the id is
+// added to their body on the way out
+fn with_request_id(body: &[u8], id: &str) -> Option<Vec<u8>> {
+    let mut problem = serde_json::from_slice::<Value>(body).ok()?;
+    problem
+        .as_object_mut()?
+        .insert("request_id".to_string(), Value::from(id));
+
+    serde_json::to_vec(&problem).ok()
+}
+
+#[derive(Debug)]
+pub struct RequestIds;
+
+impl<S> Middleware<S> for RequestIds {
+    fn start(&self, req: &HttpRequest<S>) -> Result<Started> {
+        let id = req.headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|id| id.to_str().ok())
+            .filter(|id| is_valid(id))
+            .map(|id| id.to_string())
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+        req.extensions_mut().insert(RequestId(id));
+        Ok(Started::Done)
+    }
+
+    fn response(&self, req: &HttpRequest<S>, mut resp: HttpResponse) -> Result<Response> {
+        let id = request_id(req);
+
+        let is_problem = resp.headers()
+            .get(CONTENT_TYPE)
+            .map(|content_type| content_type == PROBLEM_JSON)
+            .unwrap_or(false);
+
+        if is_problem {
+            let body = match resp.body() {
+                &Body::Binary(ref body) => with_request_id(body.as_ref(), &id),
+                _ => None,
+            };
+
+            if let Some(body) = body {
+                resp.set_body(body);
+            }
+        }
+
+        if let Ok(value) = HeaderValue::from_str(&id) {
+            resp.headers_mut().insert(REQUEST_ID_HEADER, value);
+        }
+
+        Ok(Response::Done(resp))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accepts_only_reasonable_ids() {
+        assert!(is_valid("5f1c2a-req_1"));
+        assert!(!is_valid(""));
+        assert!(!is_valid("has space"));
+        assert!(!is_valid(&"a".repeat(65)));
+    }
+
+    #[test]
+    fn test_adds_request_id_to_problems() {
+        let body = with_request_id(br#"{"code":"not_found"}"#, "req-1").unwrap();
+        let problem: Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(problem["request_id"], "req-1");
+        assert_eq!(problem["code"], "not_found");
+    }
+}
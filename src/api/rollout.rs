@@ -0,0 +1,410 @@
+use actix_web::*;
+use actix_web::http::StatusCode;
+use futures::{future, Future};
+
+use api::{json_resp, State};
+use api::error::{APIError, FieldError};
+use api::flag_req::FlagReq;
+use flag::FlagPath;
+use grant::Role;
+use schedule::{self, Schedule, ScheduleStatus, ScheduledOp};
+use util::current_time;
+
+// The changes to make and how many seconds apart. The first step is made
+// at the start, or straight away when none is given.
+#[derive(Debug, Deserialize)]
+pub struct RolloutReq {
+    pub steps: Vec<ScheduledOp>,
+    pub every: u64,
+    #[serde(default)]
+    pub start: Option<u64>,
+}
+
+const MAX_STEPS: usize = 100;
+const MAX_EVERY_SECS: u64 = 365 * 24 * 60 * 60;
+
+fn validate(body: &RolloutReq, now: u64) -> Result<(), APIError> {
+    let mut errors = vec![];
+
+    if body.steps.is_empty() {
+        errors.push(FieldError::new("steps", "must have at least one step"));
+    } else if body.steps.len() > MAX_STEPS {
+        errors.push(FieldError::new("steps", format!("must have at most {} steps", MAX_STEPS)));
+    }
+
+    for (i, step) in body.steps.iter().enumerate() {
+        for (field, message) in step.invalid_fields().into_iter() {
+            errors.push(FieldError::new(format!("steps[{}].{}", i, field), message));
+        }
+    }
+
+    if body.every == 0 {
+        errors.push(FieldError::new("every", "must be at least one second"));
+    } else if body.every > MAX_EVERY_SECS {
+        errors.push(FieldError::new("every", "must be at most a year"));
+    }
+
+    if body.start.map(|start| start <= now).unwrap_or(false) {
+        errors.push(FieldError::new("start", "must be in the future"));
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(APIError::InvalidFields(errors))
+    }
+}
+
+// Stores every step of a plan, removing the ones already stored when one
+// fails so that a plan is never left half written
+pub fn create<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+    let flag_req = match FlagReq::from_req(&req)
+        .and_then(|flag_req| flag_req.require(Role::Editor))
+    {
+        Ok(res) => res,
+        Err(err) => return Box::new(future::err(err)),
+    };
+
+    req.json()
+        .from_err()
+        .and_then(move |body: RolloutReq| {
+            let key = flag_req.key.clone().ok_or(APIError::FailedToParseParams)?;
+            let now = current_time();
+
+            validate(&body, now)?;
+
+            state
+                .flags()
+                .get(&flag_req.path, &key)
+                .map_err(APIError::read)?
+                .ok_or(APIError::FailedToFind)?;
+
+            let start = body.start.unwrap_or(now);
+            let steps = schedule::rollout(&flag_req.path, key.as_str(), &body.steps, start, body.every, flag_req.actor.as_str())
+                .ok_or_else(|| APIError::invalid("start", "puts the last step too far in the future"))?;
+
+            for (i, step) in steps.iter().enumerate() {
+                if let Err(err) = state.schedules().upsert(&Schedule::store_path(), &step.id, step) {
+                    for done in steps[..i].iter() {
+                        if state.schedules().delete(&Schedule::store_path(), &done.id).is_err() {
+                            error!("Failed to remove rollout step {}", done.id);
+                        }
+                    }
+
+                    return Err(APIError::write(err));
+                }
+            }
+
+            json_resp(StatusCode::CREATED, &steps)
+        })
+        .responder()
+}
+
+fn steps(state: &State, path: &FlagPath, plan: &str) -> Result<Vec<Schedule>, APIError> {
+    let mut steps = state
+        .schedules()
+        .get_all(&Schedule::store_path())
+        .map_err(APIError::read)?
+        .into_iter()
+        .map(|(_, scheduled)| scheduled)
+        .filter(|scheduled| scheduled.in_plan(plan) && scheduled.path.as_ref() == path.as_ref())
+        .collect::<Vec<Schedule>>();
+
+    if steps.is_empty() {
+        return Err(APIError::FailedToFind);
+    }
+
+    steps.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(steps)
+}
+
+pub fn read<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+    let flag_req = match FlagReq::from_req(&req) {
+        Ok(res) => res,
+        Err(err) => return Box::new(future::err(err)),
+    };
+    let plan = req.match_info().get("plan").unwrap_or("").to_string();
+
+    Box::new(future::ok(()).and_then(move |_| {
+        json_resp(StatusCode::OK, &steps(&state, &flag_req.path, &plan)?)
+    }))
+}
+
+// Moves the steps of a plan that are in the given state on to another. A
+// step that the scheduler has claimed is being applied, so the plan is left
+// alone until it is done.
+fn move_steps<F>(state: &State, path: &FlagPath, plan: &str, from: &[ScheduleStatus], message: &'static str, now: u64, f: F) -> Result<Vec<Schedule>, APIError>
+where
+    F: Fn(&mut Schedule, u64),
+{
+    let mut steps = steps(state, path, plan)?;
+
+    if !steps.iter().any(|step| from.contains(&step.status)) {
+        return Err(APIError::invalid("status", message));
+    }
+
+    if steps.iter().any(|step| from.contains(&step.status) && step.is_claimed(now)) {
+        return Err(APIError::invalid("status", "a step of the rollout is being applied, try again shortly"));
+    }
+
+    for step in steps.iter_mut().filter(|step| from.contains(&step.status)) {
+        f(step, now);
+
+        state
+            .schedules()
+            .upsert(&Schedule::store_path(), &step.id.clone(), step)
+            .map_err(APIError::write)?;
+    }
+
+    Ok(steps)
+}
+
+fn transition<F>(req: &HttpRequest<State>, from: &'static [ScheduleStatus], message: &'static str, f: F) -> Box<Future<Item = HttpResponse, Error = APIError>>
+where
+    F: Fn(&mut Schedule, u64) + 'static,
+{
+    let state = req.state().clone();
+    let flag_req = match FlagReq::from_req(&req)
+        .and_then(|flag_req| flag_req.require(Role::Editor))
+    {
+        Ok(res) => res,
+        Err(err) => return Box::new(future::err(err)),
+    };
+    let plan = req.match_info().get("plan").unwrap_or("").to_string();
+
+    Box::new(future::ok(()).and_then(move |_| {
+        let steps = move_steps(&state, &flag_req.path, &plan, from, message, current_time(), f)?;
+        json_resp(StatusCode::OK, &steps)
+    }))
+}
+
+fn pause_step(step: &mut Schedule, _now: u64) {
+    step.finish(ScheduleStatus::Paused, None)
+}
+
+// The remaining steps are pushed back by as long as the plan was paused, so
+// they keep their spacing
+fn resume_step(step: &mut Schedule, now: u64) {
+    step.at = step.at.saturating_add(now.saturating_sub(step.updated));
+    step.finish(ScheduleStatus::Pending, None)
+}
+
+pub fn pause<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    transition(req, &[ScheduleStatus::Pending], "the rollout has no pending steps", pause_step)
+}
+
+pub fn resume<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    transition(req, &[ScheduleStatus::Paused], "the rollout is not paused", resume_step)
+}
+
+pub fn abort<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    transition(
+        req,
+        &[ScheduleStatus::Pending, ScheduleStatus::Paused],
+        "the rollout has already finished",
+        |step, _| step.finish(ScheduleStatus::Cancelled, None),
+    )
+}
+
+// Cancels what is left of a plan after one of its steps failed
+pub fn halt(state: &State, plan: &str) {
+    let remaining = match state.schedules().get_all(&Schedule::store_path()) {
+        Ok(schedules) => schedules
+            .into_iter()
+            .map(|(_, scheduled)| scheduled)
+            .filter(|scheduled| {
+                scheduled.in_plan(plan)
+                    && (scheduled.status == ScheduleStatus::Pending || scheduled.status == ScheduleStatus::Paused)
+            })
+            .collect::<Vec<Schedule>>(),
+        Err(err) => {
+            error!("Failed to read the steps of rollout {}: {}", plan, err);
+            return;
+        }
+    };
+
+    for mut step in remaining.into_iter() {
+        step.finish(ScheduleStatus::Cancelled, Some("an earlier step failed".to_string()));
+
+        if let Err(err) = state.schedules().upsert(&Schedule::store_path(), &step.id.clone(), &step) {
+            error!("Failed to halt rollout step {}: {}", step.id, err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "mem-backend")]
+    use api::schedule::run_due;
+    #[cfg(feature = "mem-backend")]
+    use api::state::mem_state;
+    use flag::FlagValue;
+    #[cfg(feature = "mem-backend")]
+    use flag::Flag;
+    #[cfg(feature = "mem-backend")]
+    use history::HistoryEntry;
+
+    #[test]
+    fn test_validates_plans() {
+        let mut body = RolloutReq {
+            steps: vec![ScheduledOp::SetValue { value: FlagValue::Bool(true) }],
+            every: 1800,
+            start: None,
+        };
+        assert!(validate(&body, 100).is_ok());
+
+        body.steps = [1, 5, 25, 100, 101].iter().map(|&percentage| ScheduledOp::SetPercentage { percentage: percentage }).collect();
+
+        match validate(&body, 100) {
+            Err(APIError::InvalidFields(errors)) => assert_eq!(errors[0].field, "steps[4].percentage"),
+            _ => panic!("Expected invalid fields"),
+        }
+
+        body.steps.pop();
+        assert!(validate(&body, 100).is_ok());
+
+        body.every = 0;
+        body.start = Some(50);
+
+        match validate(&body, 100) {
+            Err(APIError::InvalidFields(errors)) => {
+                let fields = errors.iter().map(|e| e.field.as_str()).collect::<Vec<&str>>();
+                assert_eq!(fields, vec!["every", "start"]);
+            }
+            _ => panic!("Expected invalid fields"),
+        }
+
+        body.steps = vec![ScheduledOp::Toggle { enabled: true }; MAX_STEPS + 1];
+        body.every = MAX_EVERY_SECS + 1;
+        body.start = None;
+
+        match validate(&body, 100) {
+            Err(APIError::InvalidFields(errors)) => {
+                let fields = errors.iter().map(|e| e.field.as_str()).collect::<Vec<&str>>();
+                assert_eq!(fields, vec!["steps", "every"]);
+            }
+            _ => panic!("Expected invalid fields"),
+        }
+    }
+
+    #[cfg(feature = "mem-backend")]
+    fn store_plan(state: &State, path: &FlagPath, start: u64) -> String {
+        let ops = vec![
+            ScheduledOp::Toggle { enabled: false },
+            ScheduledOp::Toggle { enabled: true },
+        ];
+        let steps = schedule::rollout(path, "f1", &ops, start, 100, "user").unwrap();
+
+        for step in steps.iter() {
+            let _ = state.schedules().upsert(&Schedule::store_path(), &step.id, step);
+        }
+
+        steps[0].rollout.as_ref().unwrap().plan.clone()
+    }
+
+    #[cfg(feature = "mem-backend")]
+    fn pause_steps(state: &State, path: &FlagPath, plan: &str, now: u64) -> Result<Vec<Schedule>, APIError> {
+        move_steps(state, path, plan, &[ScheduleStatus::Pending], "not pending", now, pause_step)
+    }
+
+    #[cfg(feature = "mem-backend")]
+    #[test]
+    fn test_paused_plans_resume_later() {
+        let state = mem_state();
+        let path = FlagPath::new("owner", "app", "env");
+        let _ = state.flags().upsert(&path, "f1", &Flag::new("f1", FlagValue::Bool(true), 1, true));
+        let now = current_time();
+        let plan = store_plan(&state, &path, now);
+
+        let paused = pause_steps(&state, &path, &plan, now).unwrap();
+        assert!(paused.iter().all(|step| step.status == ScheduleStatus::Paused));
+        assert!(pause_steps(&state, &path, &plan, now).is_err());
+        assert_eq!(run_due(&state, "scheduler", 3600, now + 1000), 0);
+
+        let resumed = move_steps(&state, &path, &plan, &[ScheduleStatus::Paused], "not paused", now + 500, resume_step).unwrap();
+
+        // Pushed back by how long each step was paused, keeping the spacing
+        for (step, before) in resumed.iter().zip(paused.iter()) {
+            assert_eq!(step.status, ScheduleStatus::Pending);
+            assert_eq!(step.at, before.at + (now + 500 - before.updated));
+        }
+
+        // Only the first step is due until the shifted second one comes round
+        assert_eq!(run_due(&state, "scheduler", 3600, resumed[0].at), 1);
+        assert!(!state.flags().get(&path, "f1").unwrap().unwrap().is_enabled());
+        assert_eq!(run_due(&state, "scheduler", 3600, resumed[1].at), 1);
+        assert!(state.flags().get(&path, "f1").unwrap().unwrap().is_enabled());
+    }
+
+    #[cfg(feature = "mem-backend")]
+    #[test]
+    fn test_percentage_plans_advance_step_by_step() {
+        let state = mem_state();
+        let path = FlagPath::new("owner", "app", "env");
+        let flag = Flag::new("f1", FlagValue::Bool(true), 1, true).with_percentage(0);
+        let _ = state.flags().upsert(&path, "f1", &flag);
+        let now = current_time();
+
+        let ops = [1, 5, 25, 100]
+            .iter()
+            .map(|&percentage| ScheduledOp::SetPercentage { percentage: percentage })
+            .collect::<Vec<ScheduledOp>>();
+        let plan = schedule::rollout(&path, "f1", &ops, now, 1800, "user").unwrap();
+
+        for step in plan.iter() {
+            let _ = state.schedules().upsert(&Schedule::store_path(), &step.id, step);
+        }
+
+        for (i, step) in plan.iter().enumerate() {
+            assert_eq!(run_due(&state, "scheduler", 3600, step.at), 1);
+
+            let stored = state.flags().get(&path, "f1").unwrap().unwrap();
+            assert_eq!(stored.percentage(), [1, 5, 25, 100][i]);
+            assert_eq!(stored.version(), i as u64 + 2);
+        }
+
+        let history = state.history().get_all(&HistoryEntry::store_path(&path)).unwrap();
+        assert_eq!(history.len(), 4);
+    }
+
+    #[cfg(feature = "mem-backend")]
+    #[test]
+    fn test_failed_steps_halt_the_plan() {
+        let state = mem_state();
+        let path = FlagPath::new("owner", "app", "env");
+        let now = current_time();
+
+        // The flag does not exist, so the first step fails
+        let plan = store_plan(&state, &path, now);
+        assert_eq!(run_due(&state, "scheduler", 3600, now), 1);
+
+        let stored = steps(&state, &path, &plan).unwrap();
+        assert_eq!(stored[0].status, ScheduleStatus::Failed);
+        assert_eq!(stored[1].status, ScheduleStatus::Cancelled);
+        assert!(pause_steps(&state, &path, &plan, now).is_err());
+        assert_eq!(run_due(&state, "scheduler", 3600, now + 1000), 0);
+    }
+
+    #[cfg(feature = "mem-backend")]
+    #[test]
+    fn test_leaves_plans_alone_while_a_step_is_claimed() {
+        let state = mem_state();
+        let path = FlagPath::new("owner", "app", "env");
+        let now = current_time();
+        let plan = store_plan(&state, &path, now);
+
+        let mut first = steps(&state, &path, &plan).unwrap().remove(0);
+        first.claim("scheduler", now + 60);
+        let _ = state.schedules().upsert(&Schedule::store_path(), &first.id.clone(), &first);
+
+        assert!(pause_steps(&state, &path, &plan, now).is_err());
+        assert!(steps(&state, &path, &plan).unwrap().iter().all(|step| step.status == ScheduleStatus::Pending));
+
+        // Once the claim runs out the plan can be moved again
+        assert!(pause_steps(&state, &path, &plan, now + 60).is_ok());
+    }
+}
@@ -0,0 +1,349 @@
+use actix::{Actor, AsyncContext, Context};
+use actix_web::*;
+use actix_web::http::StatusCode;
+use futures::{future, Future};
+use uuid::Uuid;
+
+use std::collections::HashSet;
+use std::time::Duration;
+
+use api::{json_resp, State};
+use api::error::{APIError, FieldError};
+use api::flag;
+use api::flag_req::FlagReq;
+use api::rollout;
+use grant::Role;
+use schedule::{Schedule, ScheduleStatus, ScheduledOp};
+use util::{current_time, env_or};
+
+const DEFAULT_INTERVAL_SECS: u64 = 15;
+const MIN_INTERVAL_SECS: u64 = 1;
+// How long a scheduler has to apply a schedule it claimed
+const CLAIM_SECS: u64 = 60;
+
+#[derive(Debug, Deserialize)]
+pub struct ScheduleReq {
+    pub at: u64,
+    pub op: ScheduledOp,
+}
+
+// Schedules a change to a flag that exists now. The time is given in
+// seconds since the epoch.
+pub fn create<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+    let flag_req = match FlagReq::from_req(&req)
+        .and_then(|flag_req| flag_req.require(Role::Editor))
+    {
+        Ok(res) => res,
+        Err(err) => return Box::new(future::err(err)),
+    };
+
+    req.json()
+        .from_err()
+        .and_then(move |body: ScheduleReq| {
+            let key = flag_req.key.clone().ok_or(APIError::FailedToParseParams)?;
+            let scheduled = Schedule::new(flag_req.path.clone(), key.as_str(), body.at, body.op, flag_req.actor.as_str());
+
+            let errors = scheduled
+                .invalid_fields(current_time())
+                .into_iter()
+                .map(|(field, message)| FieldError::new(field, message))
+                .collect::<Vec<FieldError>>();
+
+            if !errors.is_empty() {
+                return Err(APIError::InvalidFields(errors));
+            }
+
+            state
+                .flags()
+                .get(&flag_req.path, &key)
+                .map_err(APIError::read)?
+                .ok_or(APIError::FailedToFind)?;
+
+            state
+                .schedules()
+                .upsert(&Schedule::store_path(), &scheduled.id, &scheduled)
+                .map_err(APIError::write)?;
+
+            json_resp(StatusCode::CREATED, &scheduled)
+        })
+        .responder()
+}
+
+fn list(state: &State, flag_req: &FlagReq, pending: bool) -> Result<HttpResponse, APIError> {
+    let mut schedules = state
+        .schedules()
+        .get_all(&Schedule::store_path())
+        .map_err(APIError::read)?
+        .into_iter()
+        .map(|(_, scheduled)| scheduled)
+        .filter(|scheduled| scheduled.path.as_ref() == flag_req.path.as_ref())
+        .filter(|scheduled| flag_req.key.as_ref().map(|key| key == &scheduled.key).unwrap_or(true))
+        .filter(|scheduled| !pending || scheduled.status == ScheduleStatus::Pending)
+        .collect::<Vec<Schedule>>();
+
+    // Soonest first
+    schedules.sort_by(|a, b| a.id.cmp(&b.id));
+
+    json_resp(StatusCode::OK, &schedules)
+}
+
+// Lists the schedules of every flag of a path, or of a single flag when the
+// route has a key. `?status=pending` leaves out the finished ones.
+pub fn all<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+    let flag_req = match FlagReq::from_req(&req) {
+        Ok(res) => res,
+        Err(err) => return Box::new(future::err(err)),
+    };
+    let pending = req.query()
+        .get("status")
+        .map(|status| status == "pending")
+        .unwrap_or(false);
+
+    Box::new(future::ok(()).and_then(move |_| list(&state, &flag_req, pending)))
+}
+
+// Cancelled schedules are kept, until pruned with the other finished ones, so
+// that they still show up in listings
+pub fn cancel<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+    let flag_req = match FlagReq::from_req(&req)
+        .and_then(|flag_req| flag_req.require(Role::Editor))
+    {
+        Ok(res) => res,
+        Err(err) => return Box::new(future::err(err)),
+    };
+    let id = match req.match_info().get("id") {
+        Some(id) => id.to_string(),
+        None => return Box::new(future::err(APIError::FailedToParseParams)),
+    };
+
+    Box::new(future::ok(()).and_then(move |_| {
+        let mut scheduled = state
+            .schedules()
+            .get(&Schedule::store_path(), &id)
+            .map_err(APIError::read)?
+            .filter(|scheduled| scheduled.path.as_ref() == flag_req.path.as_ref())
+            .ok_or(APIError::FailedToFind)?;
+
+        if scheduled.status != ScheduleStatus::Pending {
+            return Err(APIError::invalid("status", "only pending schedules can be cancelled"));
+        }
+
+        if scheduled.is_claimed(current_time()) {
+            return Err(APIError::invalid("status", "the schedule is being applied"));
+        }
+
+        scheduled.finish(ScheduleStatus::Cancelled, None);
+
+        state
+            .schedules()
+            .upsert(&Schedule::store_path(), &id, &scheduled)
+            .map_err(APIError::write)?;
+
+        json_resp(StatusCode::OK, &scheduled)
+    }))
+}
+
+// Makes a due change through the same path as an update from the api
+fn run(state: &State, scheduled: &mut Schedule) {
+    let note = scheduled.note();
+    let op = scheduled.clone();
+
+    let result = flag::change(state, &op.path, &op.key, &op.actor, Some(note), |flag| {
+        op.apply_to(flag);
+        Ok(())
+    });
+
+    match result {
+        Ok(_) => scheduled.finish(ScheduleStatus::Applied, None),
+        Err(err) => {
+            warn!("Failed to apply schedule {} to {}: {}", scheduled.id, scheduled.key, err);
+            scheduled.finish(ScheduleStatus::Failed, Some(err.to_string()));
+        }
+    }
+}
+
+// Re-reads a due schedule and claims it, returning it only while this
+// scheduler still holds the claim once written. The store has no conditional
+// writes, so this is not a lock between schedulers: it keeps a rollout from
+// moving a step that is being applied, and lets a restarted scheduler take
+// over what it left behind once the claim runs out.
+fn claim(state: &State, id: &str, owner: &str, now: u64) -> Option<Schedule> {
+    let path = Schedule::store_path();
+
+    let mut scheduled = match state.schedules().get(&path, id) {
+        Ok(Some(scheduled)) => scheduled,
+        Ok(None) => return None,
+        Err(err) => {
+            error!("Failed to read schedule {}: {}", id, err);
+            return None;
+        }
+    };
+
+    if !scheduled.is_due(now) {
+        return None;
+    }
+
+    scheduled.claim(owner, now.saturating_add(CLAIM_SECS));
+
+    if let Err(err) = state.schedules().upsert(&path, id, &scheduled) {
+        error!("Failed to claim schedule {}: {}", id, err);
+        return None;
+    }
+
+    match state.schedules().get(&path, id) {
+        Ok(Some(claimed)) => {
+            if claimed.status == ScheduleStatus::Pending && claimed.is_claimed_by(owner) {
+                Some(claimed)
+            } else {
+                None
+            }
+        }
+        Ok(None) => None,
+        Err(err) => {
+            error!("Failed to read schedule {}: {}", id, err);
+            None
+        }
+    }
+}
+
+// Applies every due schedule in the order they were due, returning how many
+// were applied or failed. A failed rollout step halts the rest of its plan.
+// Schedules finished longer ago than the retention are removed.
+pub fn run_due(state: &State, owner: &str, retention: u64, now: u64) -> usize {
+    let path = Schedule::store_path();
+
+    let schedules = match state.schedules().get_all(&path) {
+        Ok(schedules) => schedules
+            .into_iter()
+            .map(|(_, scheduled)| scheduled)
+            .collect::<Vec<Schedule>>(),
+        Err(err) => {
+            error!("Failed to read schedules: {}", err);
+            return 0;
+        }
+    };
+
+    for expired in schedules.iter().filter(|scheduled| scheduled.is_expired(now, retention)) {
+        if let Err(err) = state.schedules().delete(&path, &expired.id) {
+            error!("Failed to remove finished schedule {}: {}", expired.id, err);
+        }
+    }
+
+    let mut due = schedules
+        .into_iter()
+        .filter(|scheduled| scheduled.is_due(now))
+        .collect::<Vec<Schedule>>();
+
+    due.sort_by(|a, b| a.id.cmp(&b.id));
+
+    let mut halted = HashSet::new();
+    let mut ran = 0;
+
+    for candidate in due.iter() {
+        if candidate.rollout.as_ref().map(|step| halted.contains(&step.plan)).unwrap_or(false) {
+            continue;
+        }
+
+        let mut scheduled = match claim(state, &candidate.id, owner, now) {
+            Some(scheduled) => scheduled,
+            None => continue,
+        };
+
+        run(state, &mut scheduled);
+        ran += 1;
+
+        if scheduled.status == ScheduleStatus::Failed {
+            if let Some(ref step) = scheduled.rollout {
+                halted.insert(step.plan.clone());
+            }
+        }
+
+        if let Err(err) = state.schedules().upsert(&path, &scheduled.id, &scheduled) {
+            error!("Failed to store the outcome of schedule {}: {}", scheduled.id, err);
+        }
+    }
+
+    for plan in halted.iter() {
+        rollout::halt(state, plan);
+    }
+
+    ran
+}
+
+// Checks for due schedules on an interval. Only one replica may run it, as
+// two schedulers can both apply a schedule they read as due at the same
+// time. Every other replica is started with SCHEDULER_ENABLED=false.
+pub struct Scheduler {
+    state: State,
+    id: String,
+    interval: Duration,
+    retention: u64,
+}
+
+impl Scheduler {
+    pub fn is_enabled() -> bool {
+        env_or("SCHEDULER_ENABLED", true)
+    }
+
+    pub fn from_env(state: State) -> Scheduler {
+        Scheduler {
+            state: state,
+            id: Uuid::new_v4().to_string(),
+            interval: Duration::from_secs(env_or("SCHEDULER_INTERVAL_SECS", DEFAULT_INTERVAL_SECS).max(MIN_INTERVAL_SECS)),
+            retention: env_or("SCHEDULE_RETENTION_DAYS", 30u64).saturating_mul(24 * 60 * 60),
+        }
+    }
+}
+
+impl Actor for Scheduler {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(self.interval, |act, _ctx| {
+            let applied = run_due(&act.state, &act.id, act.retention, current_time());
+
+            if applied > 0 {
+                info!("Ran {} scheduled flag changes", applied);
+            }
+        });
+    }
+}
+
+#[cfg(all(test, feature = "mem-backend"))]
+mod tests {
+    use super::*;
+
+    use api::state::mem_state;
+    use flag::{Flag, FlagPath, FlagValue};
+
+    #[test]
+    fn test_runs_due_schedules_once_and_prunes_finished_ones() {
+        let state = mem_state();
+        let path = FlagPath::new("owner", "app", "env");
+        let _ = state.flags().upsert(&path, "f1", &Flag::new("f1", FlagValue::Bool(true), 1, true));
+
+        let now = current_time();
+        let due = Schedule::new(path.clone(), "f1", 100, ScheduledOp::Toggle { enabled: false }, "user");
+        let mut claimed = Schedule::new(path.clone(), "f1", 100, ScheduledOp::Toggle { enabled: true }, "user");
+        claimed.claim("other", now + 60);
+        let mut old = Schedule::new(path.clone(), "f1", 10, ScheduledOp::Toggle { enabled: true }, "user");
+        old.finish(ScheduleStatus::Applied, None);
+        old.updated = 0;
+
+        for scheduled in vec![&due, &claimed, &old].into_iter() {
+            let _ = state.schedules().upsert(&Schedule::store_path(), &scheduled.id, scheduled);
+        }
+
+        assert_eq!(run_due(&state, "scheduler", 3600, now), 1);
+        assert_eq!(run_due(&state, "scheduler", 3600, now), 0);
+
+        let stored = state.schedules().get_all(&Schedule::store_path()).unwrap();
+        assert_eq!(stored[&due.id].status, ScheduleStatus::Applied);
+        assert_eq!(stored[&claimed.id].status, ScheduleStatus::Pending);
+        assert!(!stored.contains_key(&old.id));
+        assert!(!state.flags().get(&path, "f1").unwrap().unwrap().is_enabled());
+    }
+}
@@ -0,0 +1,167 @@
+use actix_web::*;
+use actix_web::http::StatusCode;
+use futures::{future, Future, Stream};
+use serde_json;
+
+use api::State;
+use api::error::APIError;
+use api::flag_req::FlagReq;
+use flag::FlagPath;
+use grant::Role;
+use sdk_key::{SdkKey, SdkKeyKind};
+
+pub const SDK_KEY_PATH: &'static str = "keys";
+
+#[derive(Deserialize)]
+struct SdkKeyReq {
+    kind: SdkKeyKind,
+}
+
+#[derive(Serialize)]
+struct SdkKeyView<'a> {
+    id: &'a str,
+    kind: SdkKeyKind,
+    app: &'a str,
+    env: &'a str,
+    hint: &'a str,
+    created: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token: Option<&'a str>,
+}
+
+impl<'a> SdkKeyView<'a> {
+    fn new(key: &'a SdkKey, token: Option<&'a str>) -> SdkKeyView<'a> {
+        SdkKeyView {
+            id: key.id.as_str(),
+            kind: key.kind,
+            app: key.path.app.as_str(),
+            env: key.path.env.as_str(),
+            hint: key.hint.as_str(),
+            created: key.created,
+            token: token,
+        }
+    }
+}
+
+fn keys_for(state: &State, path: &FlagPath) -> Result<Vec<SdkKey>, APIError> {
+    state
+        .keys()
+        .get_all(&SDK_KEY_PATH.to_string())
+        .map(|keys| {
+            keys.into_iter()
+                .map(|(_, key)| key)
+                .filter(|key| key.path.path == path.path)
+                .collect()
+        })
+        .map_err(APIError::read)
+}
+
+fn find_key(state: &State, path: &FlagPath, id: &str) -> Result<SdkKey, APIError> {
+    keys_for(state, path)?
+        .into_iter()
+        .find(|key| key.id == id)
+        .ok_or(APIError::FailedToFind)
+}
+
+fn issue_key(state: &State, kind: SdkKeyKind, path: FlagPath) -> Result<HttpResponse, APIError> {
+    let (key, token) = SdkKey::generate(kind, path);
+
+    state
+        .keys()
+        .upsert(&SDK_KEY_PATH.to_string(), key.hash.as_str(), &key)
+        .map_err(APIError::write)?;
+
+    // The token is only ever returned here
+    serde_json::to_string(&SdkKeyView::new(&key, Some(token.as_str())))
+        .or(Err(APIError::FailedToSerialize))
+        .map(|json| HttpResponse::build(StatusCode::CREATED).body(json))
+}
+
+pub fn create<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+    let flag_req = match FlagReq::from_req(&req)
+        .and_then(|flag_req| flag_req.require(Role::EnvAdmin))
+    {
+        Ok(res) => res,
+        Err(err) => return Box::new(future::err(err)),
+    };
+
+    req.json()
+        .from_err()
+        .and_then(move |key_req: SdkKeyReq| issue_key(&state, key_req.kind, flag_req.path))
+        .responder()
+}
+
+pub fn all<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+    let flag_req = match FlagReq::from_req(&req)
+        .and_then(|flag_req| flag_req.require(Role::EnvAdmin))
+    {
+        Ok(res) => res,
+        Err(err) => return Box::new(future::err(err)),
+    };
+
+    Box::new(future::ok(()).and_then(move |_| {
+        let mut keys = keys_for(&state, &flag_req.path)?;
+        keys.as_mut_slice()
+            .sort_by(|a, b| a.created.cmp(&b.created));
+
+        let views = keys.iter()
+            .map(|key| SdkKeyView::new(key, None))
+            .collect::<Vec<SdkKeyView>>();
+
+        Ok(serde_json::to_string(&views)
+            .or(Err(APIError::FailedToSerialize))
+            .into())
+    }))
+}
+
+pub fn rotate<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+    let flag_req = match FlagReq::from_req(&req)
+        .and_then(|flag_req| flag_req.require(Role::EnvAdmin))
+    {
+        Ok(res) => res,
+        Err(err) => return Box::new(future::err(err)),
+    };
+    let id = match req.match_info().get("id") {
+        Some(id) => id.to_string(),
+        None => return Box::new(future::err(APIError::FailedToParseParams)),
+    };
+
+    Box::new(future::ok(()).and_then(move |_| {
+        let old = find_key(&state, &flag_req.path, &id)?;
+        let resp = issue_key(&state, old.kind, flag_req.path)?;
+
+        state
+            .keys()
+            .delete(&SDK_KEY_PATH.to_string(), old.hash.as_str())
+            .map_err(APIError::write)?;
+
+        Ok(resp)
+    }))
+}
+
+pub fn delete<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+    let flag_req = match FlagReq::from_req(&req)
+        .and_then(|flag_req| flag_req.require(Role::EnvAdmin))
+    {
+        Ok(res) => res,
+        Err(err) => return Box::new(future::err(err)),
+    };
+    let id = match req.match_info().get("id") {
+        Some(id) => id.to_string(),
+        None => return Box::new(future::err(APIError::FailedToParseParams)),
+    };
+
+    Box::new(future::ok(()).and_then(move |_| {
+        let key = find_key(&state, &flag_req.path, &id)?;
+
+        state
+            .keys()
+            .delete(&SDK_KEY_PATH.to_string(), key.hash.as_str())
+            .and_then(|_| Ok(HttpResponse::new(StatusCode::OK)))
+            .map_err(APIError::write)
+    }))
+}
@@ -0,0 +1,466 @@
+use actix_web::*;
+use actix_web::http::{header, StatusCode};
+use base64::{decode_config, encode_config, URL_SAFE_NO_PAD};
+use futures::{future, Future};
+use ring::{digest, hmac};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+use uuid::Uuid;
+
+use std::env;
+use std::time::Duration;
+
+use api::State;
+use api::auth::{authenticate, AuthReq};
+use api::error::APIError;
+use api::user::USER_PATH;
+use flag::FlagPath;
+use revocation::{Revocation, RevocationCache};
+use user::User;
+use util::{current_millis, current_time, env_or};
+
+const SECRET_VAR: &'static str = "SESSION_SECRET";
+const DEFAULT_TTL: u64 = 3600;
+const DEFAULT_REVOCATION_CACHE_SECS: u64 = 5;
+const BEARER: &'static str = "Bearer ";
+
+// Browsers that signed in through an identity provider hold their token in
+// this cookie instead of sending it in a header
+pub const SESSION_COOKIE: &'static str = "session";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    pub key: String,
+    pub admin: bool,
+    // Issue times are in milliseconds so that a token issued in the same
+    // second that a user's sessions were ended can be told apart
+    #[serde(default)]
+    pub iat: u64,
+    pub exp: u64,
+    pub jti: String,
+}
+
+// Grants a single user access to the stream of one path for a short time. The
+// ticket is put in the stream url as EventSource can not send headers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StreamTicket {
+    pub sub: String,
+    pub key: String,
+    pub admin: bool,
+    pub path: String,
+    pub iat: u64,
+    pub exp: u64,
+}
+
+#[derive(Serialize)]
+struct SessionResp<'a> {
+    token: &'a str,
+    expires: u64,
+}
+
+// Issues and verifies signed bearer tokens without reading the user store.
+// Revoked tokens, and the sessions of users that were removed, disabled,
+// promoted, demoted or had their secret reset, are kept in the revocation
+// store until they would have expired anyway. Every replica checks them
+// through a cache that is read again every few seconds.
+pub struct Sessions {
+    key: hmac::SigningKey,
+    ticket_key: hmac::SigningKey,
+    ttl: Duration,
+    revoked: RevocationCache,
+}
+
+impl Sessions {
+    pub fn new(secret: &[u8], ttl: Duration) -> Sessions {
+        let key = hmac::SigningKey::new(&digest::SHA256, secret);
+
+        // Tickets are signed with a key derived from the session secret so
+        // that a ticket can not be used as a session token or the other way
+        let ticket_secret = hmac::sign(&key, b"stream-ticket");
+
+        Sessions {
+            key: key,
+            ticket_key: hmac::SigningKey::new(&digest::SHA256, ticket_secret.as_ref()),
+            ttl: ttl,
+            revoked: RevocationCache::new(Duration::from_secs(DEFAULT_REVOCATION_CACHE_SECS)),
+        }
+    }
+
+    // Without a configured secret tokens are signed with a random key, which
+    // means they do not survive restarts and are not shared across replicas
+    pub fn from_env() -> Sessions {
+        let secret = env::var(SECRET_VAR)
+            .ok()
+            .map(|secret| secret.into_bytes())
+            .unwrap_or_else(|| {
+                warn!("{} is not set, using a random session secret", SECRET_VAR);
+
+                let mut secret = vec![0u8; 32];
+                SystemRandom::new()
+                    .fill(&mut secret)
+                    .expect("Failed to generate a session secret");
+                secret
+            });
+        let mut sessions = Sessions::new(&secret, Duration::from_secs(env_or("SESSION_TTL_SECS", DEFAULT_TTL)));
+        sessions.revoked = RevocationCache::new(Duration::from_secs(env_or(
+            "REVOCATION_CACHE_SECS",
+            DEFAULT_REVOCATION_CACHE_SECS,
+        )));
+
+        sessions
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    pub fn revoked(&self) -> &RevocationCache {
+        &self.revoked
+    }
+
+    pub fn issue(&self, user: &User) -> Result<(String, Claims), APIError> {
+        let now = current_time();
+        let claims = Claims {
+            sub: user.uuid.clone(),
+            key: user.key.clone(),
+            admin: user.is_admin(),
+            iat: current_millis(),
+            exp: now + self.ttl.as_secs(),
+            jti: Uuid::new_v4().to_string(),
+        };
+
+        Ok((sign(&self.key, &claims)?, claims))
+    }
+
+    // Checks the signature and expiry of a token. Revoked tokens are only
+    // rejected by `verify`, which also checks the revocations.
+    pub fn verify(&self, token: &str) -> Option<Claims> {
+        let claims = verify_signed::<Claims>(&self.key, token)?;
+
+        if claims.exp <= current_time() {
+            None
+        } else {
+            Some(claims)
+        }
+    }
+
+    pub fn issue_ticket(
+        &self,
+        user: &User,
+        path: &FlagPath,
+        ttl: Duration,
+    ) -> Result<(String, StreamTicket), APIError> {
+        let now = current_time();
+        let ticket = StreamTicket {
+            sub: user.uuid.clone(),
+            key: user.key.clone(),
+            admin: user.is_admin(),
+            path: path.path.clone(),
+            iat: current_millis(),
+            exp: now + ttl.as_secs(),
+        };
+
+        Ok((sign(&self.ticket_key, &ticket)?, ticket))
+    }
+
+    pub fn verify_ticket(&self, token: &str) -> Option<StreamTicket> {
+        let ticket = verify_signed::<StreamTicket>(&self.ticket_key, token)?;
+
+        if ticket.exp <= current_time() {
+            None
+        } else {
+            Some(ticket)
+        }
+    }
+}
+
+// Verifies a token and checks that neither it nor the sessions of its user
+// were revoked. Tokens are refused when the revocations can not be read.
+pub fn verify(state: &State, token: &str) -> Option<Claims> {
+    state.sessions().verify(token).filter(|claims| {
+        !state
+            .sessions()
+            .revoked()
+            .is_revoked(&**state.revocations(), Some(&claims.jti), &claims.sub, claims.iat)
+    })
+}
+
+pub fn verify_ticket(state: &State, token: &str) -> Option<StreamTicket> {
+    state.sessions().verify_ticket(token).filter(|ticket| {
+        !state
+            .sessions()
+            .revoked()
+            .is_revoked(&**state.revocations(), None, &ticket.sub, ticket.iat)
+    })
+}
+
+fn store_revocation(state: &State, revocation: Revocation) -> Result<(), APIError> {
+    state
+        .revocations()
+        .upsert(&Revocation::store_path(), &revocation.jti, &revocation)
+        .map_err(APIError::write)?;
+    state.sessions().revoked().insert(revocation);
+
+    Ok(())
+}
+
+// Expired revocations are removed by the pruner rather than here
+pub fn revoke(state: &State, claims: &Claims) -> Result<(), APIError> {
+    store_revocation(state, Revocation::new(claims.jti.as_str(), claims.exp))
+}
+
+// Ends every session and stream ticket issued to a user so far. The user has
+// to be stored afterwards so that refreshing a session refuses it too.
+pub fn end_sessions(state: &State, user: &mut User) -> Result<(), APIError> {
+    let since = user.end_sessions();
+    let exp = current_time() + state.sessions().ttl().as_secs();
+
+    store_revocation(state, Revocation::user(&user.uuid, since, exp))
+}
+
+// The user a verified session or ticket was issued to. The store is not read,
+// changes to the user end their sessions instead.
+pub fn claimed_user(sub: &str, key: &str, admin: bool) -> User {
+    User::from_session(sub.to_string(), key.to_string(), admin)
+}
+
+// Tokens are the base64 encoded json payload followed by its signature
+fn sign<T: Serialize>(key: &hmac::SigningKey, payload: &T) -> Result<String, APIError> {
+    let payload = serde_json::to_vec(payload).or(Err(APIError::FailedToSerialize))?;
+    let encoded = encode_config(&payload, URL_SAFE_NO_PAD);
+    let signature = hmac::sign(key, encoded.as_bytes());
+
+    Ok([
+        encoded.as_str(),
+        ".",
+        encode_config(signature.as_ref(), URL_SAFE_NO_PAD).as_str(),
+    ].concat())
+}
+
+fn verify_signed<T: DeserializeOwned>(key: &hmac::SigningKey, token: &str) -> Option<T> {
+    let mut parts = token.splitn(2, '.');
+
+    let (payload, signature) = match (parts.next(), parts.next()) {
+        (Some(payload), Some(signature)) => (payload, signature),
+        _ => return None,
+    };
+
+    let signature = decode_config(signature, URL_SAFE_NO_PAD).ok()?;
+    hmac::verify_with_own_key(key, payload.as_bytes(), &signature).ok()?;
+
+    decode_config(payload, URL_SAFE_NO_PAD)
+        .ok()
+        .and_then(|json| serde_json::from_slice::<T>(&json).ok())
+}
+
+pub fn bearer_token<S>(req: &HttpRequest<S>) -> Option<String> {
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|auth| auth.to_str().ok())
+        .filter(|auth| auth.starts_with(BEARER))
+        .map(|auth| auth[BEARER.len()..].trim().to_string())
+}
+
+// The stored user a session was issued to, as long as they still exist, are
+// not disabled, have the admin flag the session carries and have not had
+// their sessions ended since it was issued. Only read on refresh.
+pub fn session_user(state: &State, uuid: &str, key: &str, admin: bool, iat: u64) -> Option<User> {
+    state
+        .users()
+        .get(&USER_PATH.to_string(), key)
+        .unwrap_or(None)
+        .filter(|user| {
+            user.uuid == uuid && !user.is_disabled() && user.is_admin() == admin && user.accepts_session(iat)
+        })
+}
+
+pub fn session_token<S>(req: &HttpRequest<S>) -> Option<String> {
+    bearer_token(req).or_else(|| req.cookie(SESSION_COOKIE).map(|cookie| cookie.value().to_string()))
+}
+
+fn session_resp(token: &str, claims: &Claims) -> Result<HttpResponse, APIError> {
+    serde_json::to_string(&SessionResp {
+        token: token,
+        expires: claims.exp,
+    }).or(Err(APIError::FailedToSerialize))
+        .map(|json| HttpResponse::Ok().content_type("application/json").body(json))
+}
+
+pub fn login<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+    let http_req = req.clone();
+
+    req.json()
+        .from_err()
+        .and_then(move |auth: AuthReq| {
+            let user = authenticate(&auth, &http_req)?;
+            let (token, claims) = state.sessions().issue(&user)?;
+
+            session_resp(&token, &claims)
+        })
+        .responder()
+}
+
+pub fn refresh<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+    let token = bearer_token(req);
+
+    Box::new(future::ok(()).and_then(move |_| {
+        let claims = token
+            .and_then(|token| verify(&state, &token))
+            .ok_or(APIError::Unauthorized)?;
+
+        // Users that were removed, disabled or had their admin flag changed
+        // since the token was issued have to log in again
+        let user = session_user(&state, &claims.sub, &claims.key, claims.admin, claims.iat).ok_or(APIError::Unauthorized)?;
+
+        revoke(&state, &claims)?;
+        let (token, new_claims) = state.sessions().issue(&user)?;
+
+        session_resp(&token, &new_claims)
+    }))
+}
+
+pub fn logout<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+    let token = bearer_token(req);
+
+    Box::new(future::ok(()).and_then(move |_| {
+        let claims = token
+            .and_then(|token| verify(&state, &token))
+            .ok_or(APIError::Unauthorized)?;
+
+        revoke(&state, &claims)?;
+
+        Ok(HttpResponse::new(StatusCode::NO_CONTENT))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "mem-backend")]
+    use api::state::mem_state;
+
+    fn sessions(ttl: u64) -> Sessions {
+        Sessions::new(b"test-secret", Duration::from_secs(ttl))
+    }
+
+    fn user() -> User {
+        User::from_session("user-id".to_string(), "key".to_string(), true)
+    }
+
+    #[test]
+    fn test_verifies_issued_tokens() {
+        let s = sessions(60);
+        let (token, claims) = s.issue(&user()).unwrap();
+        let verified = s.verify(&token).unwrap();
+
+        assert_eq!(verified.sub, "user-id");
+        assert_eq!(verified.jti, claims.jti);
+        assert!(verified.admin);
+    }
+
+    #[test]
+    fn test_rejects_tampered_tokens() {
+        let s = sessions(60);
+        let (token, _) = s.issue(&user()).unwrap();
+        let other = sessions(60);
+
+        assert!(other.verify(&token).is_none());
+        assert!(s.verify(&token[1..]).is_none());
+        assert!(s.verify("not-a-token").is_none());
+    }
+
+    #[test]
+    fn test_rejects_expired_tokens() {
+        let s = sessions(0);
+        let (token, _) = s.issue(&user()).unwrap();
+
+        assert!(s.verify(&token).is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "mem-backend")]
+    fn test_rejects_revoked_tokens() {
+        let state = mem_state();
+        let (token, claims) = state.sessions().issue(&user()).unwrap();
+        assert!(verify(&state, &token).is_some());
+
+        revoke(&state, &claims).unwrap();
+
+        assert!(verify(&state, &token).is_none());
+        assert!(state.revocations().get(&Revocation::store_path(), &claims.jti).unwrap().is_some());
+    }
+
+    #[test]
+    #[cfg(feature = "mem-backend")]
+    fn test_rejects_sessions_of_users_whose_sessions_ended() {
+        let state = mem_state();
+        let mut stored = User::new("user-id".to_string(), "key".to_string(), "secret".to_string(), true);
+        let (token, _) = state.sessions().issue(&stored).unwrap();
+        let path = "user-id:app:env".parse::<FlagPath>().unwrap();
+        let (ticket, _) = state.sessions().issue_ticket(&stored, &path, Duration::from_secs(60)).unwrap();
+        assert!(verify(&state, &token).is_some());
+        assert!(verify_ticket(&state, &ticket).is_some());
+
+        ::std::thread::sleep(Duration::from_millis(2));
+        end_sessions(&state, &mut stored).unwrap();
+
+        assert!(verify(&state, &token).is_none());
+        assert!(verify_ticket(&state, &ticket).is_none());
+        assert!(!stored.accepts_session(0));
+
+        let (token, _) = state.sessions().issue(&stored).unwrap();
+        assert!(verify(&state, &token).is_some());
+    }
+
+    #[test]
+    #[cfg(feature = "mem-backend")]
+    fn test_finds_only_unchanged_session_users() {
+        let state = mem_state();
+        let mut stored = User::new("user-id".to_string(), "key".to_string(), "secret".to_string(), true);
+        state.users().upsert(&USER_PATH.to_string(), "key", &stored).unwrap();
+
+        let (_, claims) = state.sessions().issue(&stored).unwrap();
+        let iat = claims.iat;
+
+        assert!(session_user(&state, "user-id", "key", true, iat).is_some());
+        assert!(session_user(&state, "user-id", "key", false, iat).is_none());
+        assert!(session_user(&state, "other-id", "key", true, iat).is_none());
+        assert!(session_user(&state, "user-id", "missing", true, iat).is_none());
+
+        // Sessions ended a moment after the token was issued reject it
+        ::std::thread::sleep(Duration::from_millis(2));
+        stored.end_sessions();
+        state.users().upsert(&USER_PATH.to_string(), "key", &stored).unwrap();
+
+        assert!(session_user(&state, "user-id", "key", true, iat).is_none());
+        assert!(session_user(&state, "user-id", "key", true, current_millis()).is_some());
+
+        stored.set_disabled(true);
+        state.users().upsert(&USER_PATH.to_string(), "key", &stored).unwrap();
+
+        assert!(session_user(&state, "user-id", "key", true, current_millis()).is_none());
+    }
+
+    #[test]
+    fn test_keeps_tickets_and_tokens_apart() {
+        let s = sessions(60);
+        let path = "user-id:app:env".parse::<FlagPath>().unwrap();
+        let (ticket, _) = s.issue_ticket(&user(), &path, Duration::from_secs(60)).unwrap();
+        let (token, _) = s.issue(&user()).unwrap();
+
+        assert_eq!(s.verify_ticket(&ticket).unwrap().path, "user-id:app:env");
+        assert!(s.verify(&ticket).is_none());
+        assert!(s.verify_ticket(&token).is_none());
+        assert!(s.issue_ticket(&user(), &path, Duration::from_secs(0))
+            .map(|(ticket, _)| s.verify_ticket(&ticket).is_none())
+            .unwrap());
+    }
+
+}
@@ -0,0 +1,203 @@
+use actix::{Actor, ActorContext, AsyncContext, SpawnHandle, StreamHandler};
+use actix_web::{ws, Error, HttpRequest, HttpResponse};
+use futures::Stream;
+use serde_json;
+use serde_json::Value;
+
+use std::collections::HashMap;
+
+use api::State;
+use api::access;
+use api::error::APIError;
+use api::stream::{stream_key, FlagEvent, FlagFeed};
+use flag::FlagPath;
+use grant::Role;
+use metrics;
+use user::User;
+
+#[derive(Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+enum SocketReq {
+    Subscribe {
+        app: String,
+        env: String,
+        owner: Option<String>,
+        last_id: Option<u64>,
+    },
+    Unsubscribe {
+        app: String,
+        env: String,
+        owner: Option<String>,
+    },
+}
+
+#[derive(Serialize)]
+struct SocketEvent<'a> {
+    event: &'a str,
+    app: &'a str,
+    env: &'a str,
+    id: Option<u64>,
+    data: Value,
+}
+
+// A single socket may follow several paths of the authenticated user. Each
+// subscription is backed by its own FlagFeed so that it receives the same
+// snapshot and patch events as the SSE stream.
+pub struct FlagSocket {
+    user: User,
+    subs: HashMap<String, SpawnHandle>,
+}
+
+impl FlagSocket {
+    // Subscriptions follow the user's own apps unless another owner is named
+    fn path(&self, owner: Option<String>, app: String, env: String) -> FlagPath {
+        FlagPath::new(owner.unwrap_or(self.user.uuid.clone()), app, env)
+    }
+
+    fn send(&self, ctx: &mut ws::WebsocketContext<Self, State>, event: &SocketEvent) {
+        match serde_json::to_string(event) {
+            Ok(json) => ctx.text(json),
+            Err(_) => error!("Failed to serialize socket event {}", event.event),
+        }
+    }
+
+    fn send_error(
+        &self,
+        ctx: &mut ws::WebsocketContext<Self, State>,
+        app: &str,
+        env: &str,
+        message: &str,
+    ) {
+        self.send(
+            ctx,
+            &SocketEvent {
+                event: "error",
+                app: app,
+                env: env,
+                id: None,
+                data: Value::String(message.to_string()),
+            },
+        )
+    }
+
+    fn subscribe(
+        &mut self,
+        path: FlagPath,
+        last_id: Option<u64>,
+        ctx: &mut ws::WebsocketContext<Self, State>,
+    ) {
+        if self.subs.contains_key(path.as_ref()) {
+            return;
+        }
+
+        let allowed = access::role_for(ctx.state(), &self.user, &path.owner, &path.app, Some(&path.env))
+            .and_then(|role| access::require(role, Role::Viewer))
+            .is_ok();
+
+        if !allowed {
+            return self.send_error(ctx, &path.app, &path.env, "forbidden");
+        }
+
+        if !ctx.state().open_stream(stream_key(&self.user.uuid, &path).as_str()) {
+            return self.send_error(ctx, &path.app, &path.env, "too many streams");
+        }
+
+        let feed_path = path.clone();
+        let feed = FlagFeed::new(ctx.state().clone(), path.clone(), last_id)
+            .map(move |event| (feed_path.clone(), event));
+
+        let handle = ctx.add_stream(feed);
+        self.subs.insert(path.path, handle);
+    }
+
+    fn unsubscribe(&mut self, path: FlagPath, ctx: &mut ws::WebsocketContext<Self, State>) {
+        if let Some(handle) = self.subs.remove(path.as_ref()) {
+            ctx.cancel_future(handle);
+            ctx.state().close_stream(stream_key(&self.user.uuid, &path).as_str());
+        }
+    }
+}
+
+impl Actor for FlagSocket {
+    type Context = ws::WebsocketContext<Self, State>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        let heartbeat = ctx.state().stream_config().heartbeat;
+        ctx.run_interval(heartbeat, |_, ctx| ctx.ping(""));
+    }
+
+    fn stopped(&mut self, ctx: &mut Self::Context) {
+        for (path, _) in self.subs.drain() {
+            ctx.state()
+                .close_stream([self.user.uuid.as_str(), "/", path.as_str()].concat().as_str());
+        }
+    }
+}
+
+impl StreamHandler<ws::Message, ws::ProtocolError> for FlagSocket {
+    fn handle(&mut self, msg: ws::Message, ctx: &mut Self::Context) {
+        match msg {
+            ws::Message::Ping(msg) => ctx.pong(&msg),
+            ws::Message::Text(text) => match serde_json::from_str::<SocketReq>(&text) {
+                Ok(SocketReq::Subscribe {
+                    app,
+                    env,
+                    owner,
+                    last_id,
+                }) => {
+                    let path = self.path(owner, app, env);
+                    self.subscribe(path, last_id, ctx)
+                }
+                Ok(SocketReq::Unsubscribe { app, env, owner }) => {
+                    let path = self.path(owner, app, env);
+                    self.unsubscribe(path, ctx)
+                }
+                Err(_) => self.send_error(ctx, "", "", "invalid request"),
+            },
+            ws::Message::Close(_) => ctx.stop(),
+            _ => (),
+        }
+    }
+}
+
+impl StreamHandler<(FlagPath, FlagEvent), ()> for FlagSocket {
+    fn handle(&mut self, msg: (FlagPath, FlagEvent), ctx: &mut Self::Context) {
+        let (path, event) = msg;
+
+        match event.data() {
+            Ok(data) => {
+                metrics::inc(metrics::EVENTS, &[("transport", "ws"), ("event", event.name())]);
+                self.send(
+                    ctx,
+                    &SocketEvent {
+                        event: event.name(),
+                        app: &path.app,
+                        env: &path.env,
+                        id: Some(event.id()),
+                        data: data,
+                    },
+                )
+            }
+            Err(_) => self.send_error(ctx, &path.app, &path.env, "failed to serialize event"),
+        }
+    }
+
+    // A feed only ends when the store can no longer be read. The socket is
+    // kept open for its other subscriptions.
+    fn finished(&mut self, _: &mut Self::Context) {}
+}
+
+pub fn flag_socket<'r>(req: &'r HttpRequest<State>) -> Result<HttpResponse, Error> {
+    let user = req.extensions()
+        .get::<User>()
+        .cloned()
+        .ok_or(APIError::Unauthorized)?;
+
+    ws::start(
+        req,
+        FlagSocket {
+            user: user,
+            subs: HashMap::new(),
+        },
+    )
+}
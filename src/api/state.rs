@@ -1,29 +1,106 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::Instant;
+
+use analytics::AnalyticsStore;
+use api::oidc::Oidc;
+use api::session::Sessions;
+use api::stream::StreamConfig;
 use error::BannerError;
 use flag::{Flag, FlagPath};
+use grant::Grant;
+use history::{HistoryEntry, Retention};
+use lockout::{Attempts, LockoutConfig};
+use revocation::Revocation;
+use schedule::Schedule;
+use sdk_key::SdkKey;
+use storage::metered::MeteredStore;
 use store::ThreadedStore;
+use team::Team;
 use user::User;
 
 pub type FlagStore = ThreadedStore<FlagPath, Flag, Error = BannerError>;
 pub type PathStore = ThreadedStore<String, FlagPath, Error = BannerError>;
 pub type UserStore = ThreadedStore<String, User, Error = BannerError>;
+pub type KeyStore = ThreadedStore<String, SdkKey, Error = BannerError>;
+pub type GrantStore = ThreadedStore<String, Grant, Error = BannerError>;
+pub type TeamStore = ThreadedStore<String, Team, Error = BannerError>;
+pub type LockoutStore = ThreadedStore<String, Attempts, Error = BannerError>;
+pub type HistoryStore = ThreadedStore<String, HistoryEntry, Error = BannerError>;
+pub type ScheduleStore = ThreadedStore<String, Schedule, Error = BannerError>;
+pub type RevocationStore = ThreadedStore<String, Revocation, Error = BannerError>;
 
 pub struct AppState {
     flag_store: Box<FlagStore>,
     path_store: Box<PathStore>,
     user_store: Box<UserStore>,
+    key_store: Box<KeyStore>,
+    grant_store: Box<GrantStore>,
+    team_store: Box<TeamStore>,
+    lockout_store: Box<LockoutStore>,
+    lockout_config: LockoutConfig,
+    history_store: Box<HistoryStore>,
+    history_retention: Retention,
+    schedule_store: Box<ScheduleStore>,
+    revocation_store: Box<RevocationStore>,
+    analytics: Box<AnalyticsStore>,
+    stream_config: StreamConfig,
+    streams: RwLock<HashMap<String, usize>>,
+    sessions: Sessions,
+    oidc: Option<Oidc>,
+    started: Instant,
 }
 
 impl AppState {
-    pub fn new<F, P, U>(flag_store: F, path_store: P, user_store: U) -> AppState
+    pub fn new<F, P, U, K, G, T, L, H, C, R>(
+        flag_store: F,
+        path_store: P,
+        user_store: U,
+        key_store: K,
+        grant_store: G,
+        team_store: T,
+        lockout_store: L,
+        lockout_config: LockoutConfig,
+        history_store: H,
+        history_retention: Retention,
+        schedule_store: C,
+        revocation_store: R,
+        analytics: Box<AnalyticsStore>,
+        stream_config: StreamConfig,
+        sessions: Sessions,
+        oidc: Option<Oidc>,
+    ) -> AppState
     where
         F: ThreadedStore<FlagPath, Flag, Error = BannerError> + 'static,
         P: ThreadedStore<String, FlagPath, Error = BannerError> + 'static,
         U: ThreadedStore<String, User, Error = BannerError> + 'static,
+        K: ThreadedStore<String, SdkKey, Error = BannerError> + 'static,
+        G: ThreadedStore<String, Grant, Error = BannerError> + 'static,
+        T: ThreadedStore<String, Team, Error = BannerError> + 'static,
+        L: ThreadedStore<String, Attempts, Error = BannerError> + 'static,
+        H: ThreadedStore<String, HistoryEntry, Error = BannerError> + 'static,
+        C: ThreadedStore<String, Schedule, Error = BannerError> + 'static,
+        R: ThreadedStore<String, Revocation, Error = BannerError> + 'static,
     {
         AppState {
-            flag_store: Box::new(flag_store),
-            path_store: Box::new(path_store),
-            user_store: Box::new(user_store),
+            flag_store: Box::new(MeteredStore::new("flags", flag_store)),
+            path_store: Box::new(MeteredStore::new("paths", path_store)),
+            user_store: Box::new(MeteredStore::new("users", user_store)),
+            key_store: Box::new(MeteredStore::new("keys", key_store)),
+            grant_store: Box::new(MeteredStore::new("grants", grant_store)),
+            team_store: Box::new(MeteredStore::new("teams", team_store)),
+            lockout_store: Box::new(MeteredStore::new("lockouts", lockout_store)),
+            lockout_config: lockout_config,
+            history_store: Box::new(MeteredStore::new("history", history_store)),
+            history_retention: history_retention,
+            schedule_store: Box::new(MeteredStore::new("schedules", schedule_store)),
+            revocation_store: Box::new(MeteredStore::new("revocations", revocation_store)),
+            analytics: analytics,
+            stream_config: stream_config,
+            streams: RwLock::new(HashMap::new()),
+            sessions: sessions,
+            oidc: oidc,
+            started: Instant::now(),
         }
     }
 
@@ -38,4 +115,131 @@ impl AppState {
     pub fn users(&self) -> &Box<ThreadedStore<String, User, Error = BannerError>> {
         &self.user_store
     }
+
+    pub fn keys(&self) -> &Box<ThreadedStore<String, SdkKey, Error = BannerError>> {
+        &self.key_store
+    }
+
+    pub fn grants(&self) -> &Box<ThreadedStore<String, Grant, Error = BannerError>> {
+        &self.grant_store
+    }
+
+    pub fn teams(&self) -> &Box<ThreadedStore<String, Team, Error = BannerError>> {
+        &self.team_store
+    }
+
+    pub fn lockouts(&self) -> &Box<ThreadedStore<String, Attempts, Error = BannerError>> {
+        &self.lockout_store
+    }
+
+    pub fn lockout_config(&self) -> &LockoutConfig {
+        &self.lockout_config
+    }
+
+    pub fn stream_config(&self) -> &StreamConfig {
+        &self.stream_config
+    }
+
+    pub fn sessions(&self) -> &Sessions {
+        &self.sessions
+    }
+
+    pub fn oidc(&self) -> Option<&Oidc> {
+        self.oidc.as_ref()
+    }
+
+    pub fn history(&self) -> &Box<HistoryStore> {
+        &self.history_store
+    }
+
+    pub fn history_retention(&self) -> &Retention {
+        &self.history_retention
+    }
+
+    pub fn schedules(&self) -> &Box<ScheduleStore> {
+        &self.schedule_store
+    }
+
+    pub fn revocations(&self) -> &Box<RevocationStore> {
+        &self.revocation_store
+    }
+
+    pub fn analytics(&self) -> &Box<AnalyticsStore> {
+        &self.analytics
+    }
+
+    pub fn started(&self) -> Instant {
+        self.started
+    }
+
+    // Registers a new stream under the given key, refusing it if the key
+    // already has the maximum number of open streams
+    pub fn open_stream(&self, key: &str) -> bool {
+        let limit = self.stream_config.max_per_path;
+
+        self.streams
+            .write()
+            .map(|mut streams| {
+                let count = streams.entry(key.to_string()).or_insert(0);
+
+                if *count < limit {
+                    *count = *count + 1;
+                    true
+                } else {
+                    false
+                }
+            })
+            .unwrap_or(false)
+    }
+
+    pub fn close_stream(&self, key: &str) {
+        if let Ok(mut streams) = self.streams.write() {
+            let empty = streams
+                .get_mut(key)
+                .map(|count| {
+                    *count = count.saturating_sub(1);
+                    *count == 0
+                })
+                .unwrap_or(false);
+
+            if empty {
+                streams.remove(key);
+            }
+        }
+    }
+
+    pub fn streams(&self) -> HashMap<String, usize> {
+        self.streams
+            .read()
+            .map(|streams| streams.clone())
+            .unwrap_or(HashMap::new())
+    }
+}
+
+// A state backed by memory stores, for tests that go through handlers
+#[cfg(all(test, feature = "mem-backend"))]
+pub fn mem_state() -> ::std::sync::Arc<AppState> {
+    use analytics::AnalyticsConfig;
+    use analytics::mem::MemAnalytics;
+    use storage::mem::MemStore;
+    use std::time::Duration;
+
+    ::std::sync::Arc::new(AppState::new(
+        MemStore::new(),
+        MemStore::new(),
+        MemStore::new(),
+        MemStore::new(),
+        MemStore::new(),
+        MemStore::new(),
+        MemStore::new(),
+        LockoutConfig::from_env(),
+        MemStore::new(),
+        Retention::from_env(),
+        MemStore::new(),
+        MemStore::new(),
+        Box::new(MemAnalytics::new(AnalyticsConfig::from_env())),
+        StreamConfig::from_env(),
+        Sessions::new(b"test-secret", Duration::from_secs(60)),
+        None,
+    ))
 }
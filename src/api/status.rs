@@ -1 +1,103 @@
+use actix_web::{HttpRequest, HttpResponse};
+use actix_web::http::StatusCode;
 
+use std::collections::HashMap;
+
+use api::{json_resp, State};
+use api::error::APIError;
+use error::BannerError;
+use store::StoreStats;
+
+const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+
+#[derive(Serialize)]
+struct Readiness {
+    ready: bool,
+    stores: HashMap<&'static str, String>,
+}
+
+#[derive(Serialize)]
+struct Status {
+    version: &'static str,
+    uptime_secs: u64,
+    stores: HashMap<&'static str, StoreStats>,
+    streams: HashMap<String, usize>,
+}
+
+fn pings(state: &State) -> Vec<(&'static str, Result<(), BannerError>)> {
+    vec![
+        ("flags", state.flags().ping()),
+        ("paths", state.paths().ping()),
+        ("users", state.users().ping()),
+        ("keys", state.keys().ping()),
+        ("grants", state.grants().ping()),
+        ("teams", state.teams().ping()),
+        ("lockouts", state.lockouts().ping()),
+        ("history", state.history().ping()),
+        ("schedules", state.schedules().ping()),
+        ("revocations", state.revocations().ping()),
+    ]
+}
+
+fn stats(state: &State) -> HashMap<&'static str, StoreStats> {
+    let mut stats = HashMap::new();
+    stats.insert("flags", state.flags().stats());
+    stats.insert("paths", state.paths().stats());
+    stats.insert("users", state.users().stats());
+    stats.insert("keys", state.keys().stats());
+    stats.insert("grants", state.grants().stats());
+    stats.insert("teams", state.teams().stats());
+    stats.insert("lockouts", state.lockouts().stats());
+    stats.insert("history", state.history().stats());
+    stats.insert("schedules", state.schedules().stats());
+    stats.insert("revocations", state.revocations().stats());
+    stats
+}
+
+// Liveness only says that the process is serving requests
+pub fn health<'r>(_req: &'r HttpRequest<State>) -> HttpResponse {
+    HttpResponse::Ok().content_type("text/plain").body("ok")
+}
+
+// Ready once every store answers a ping. Failures are logged with their
+// cause and only reported by store name.
+pub fn ready<'r>(req: &'r HttpRequest<State>) -> Result<HttpResponse, APIError> {
+    let mut stores = HashMap::new();
+    let mut ready = true;
+
+    for (name, res) in pings(req.state()).into_iter() {
+        match res {
+            Ok(_) => {
+                stores.insert(name, "ok".to_string());
+            }
+            Err(err) => {
+                warn!("Store {} is not ready: {}", name, err);
+                ready = false;
+                stores.insert(name, "unavailable".to_string());
+            }
+        }
+    }
+
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    json_resp(status, &Readiness {
+        ready: ready,
+        stores: stores,
+    })
+}
+
+// Subscriptions are listed per path, so this is limited to admins
+pub fn status<'r>(req: &'r HttpRequest<State>) -> Result<HttpResponse, APIError> {
+    let state = req.state();
+
+    json_resp(StatusCode::OK, &Status {
+        version: VERSION,
+        uptime_secs: state.started().elapsed().as_secs(),
+        stores: stats(state),
+        streams: state.streams(),
+    })
+}
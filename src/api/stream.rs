@@ -1,93 +1,337 @@
 use actix_web::http::ConnectionType;
 use actix_web::{http, Error, HttpRequest, HttpResponse};
 use bytes::Bytes;
-use futures::{task, Async, Poll, Stream};
+use futures::{task, Async, Future, Poll, Stream};
 use serde_json;
+use serde_json::Value;
+use tokio::timer::{Delay, Interval};
 use uuid::Uuid;
 
-use std::time::Instant;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
 
 use api::State;
 use api::error::APIError;
 use api::flag_req::FlagReq;
+use change_log::Change;
 use flag::{Flag, FlagPath};
+use metrics;
+use sdk_key::SdkKey;
+use user::User;
+use util::env_or;
 
-const HEADER: &'static str = "event:data\n";
+const LAST_EVENT_ID: &'static str = "Last-Event-ID";
+const HEARTBEAT: &'static str = ":heartbeat\n\n";
+const PUT_EVENT: &'static str = "put";
+const PATCH_EVENT: &'static str = "patch";
+const DELETE_EVENT: &'static str = "delete";
 
-struct FlagStream {
+#[derive(Serialize)]
+struct TicketResp<'a> {
+    ticket: &'a str,
+    expires: u64,
+}
+
+#[derive(Serialize)]
+struct FlagDelete<'a> {
+    key: &'a str,
+}
+
+#[derive(Debug, Clone)]
+pub struct StreamConfig {
+    pub heartbeat: Duration,
+    pub max_lifetime: Duration,
+    pub max_per_path: usize,
+    pub retry: Duration,
+    pub max_poll: Duration,
+    pub ticket_ttl: Duration,
+}
+
+impl StreamConfig {
+    // Timers can not run on a zero interval, so a zero heartbeat or lifetime
+    // is raised to a second
+    pub fn from_env() -> StreamConfig {
+        StreamConfig {
+            heartbeat: Duration::from_secs(env_or("STREAM_HEARTBEAT_SECS", 15u64).max(1)),
+            max_lifetime: Duration::from_secs(env_or("STREAM_MAX_LIFETIME_SECS", 3600u64).max(1)),
+            max_per_path: env_or("STREAM_MAX_PER_PATH", 10),
+            retry: Duration::from_millis(env_or("STREAM_RETRY_MS", 3000)),
+            max_poll: Duration::from_secs(env_or("POLL_MAX_TIMEOUT_SECS", 60)),
+            ticket_ttl: Duration::from_secs(env_or("STREAM_TICKET_TTL_SECS", 60)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum FlagEvent {
+    Put(u64, Vec<Flag>),
+    Patch(u64, Flag),
+    Delete(u64, String),
+}
+
+impl FlagEvent {
+    pub fn id(&self) -> u64 {
+        match self {
+            &FlagEvent::Put(id, _) => id,
+            &FlagEvent::Patch(id, _) => id,
+            &FlagEvent::Delete(id, _) => id,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            &FlagEvent::Put(_, _) => PUT_EVENT,
+            &FlagEvent::Patch(_, _) => PATCH_EVENT,
+            &FlagEvent::Delete(_, _) => DELETE_EVENT,
+        }
+    }
+
+    pub fn data(&self) -> Result<Value, serde_json::Error> {
+        match self {
+            &FlagEvent::Put(_, ref flags) => serde_json::to_value(flags),
+            &FlagEvent::Patch(_, ref flag) => serde_json::to_value(flag),
+            &FlagEvent::Delete(_, ref key) => serde_json::to_value(&FlagDelete {
+                key: key.as_str(),
+            }),
+        }
+    }
+
+    fn from_change(change: Change<Flag>) -> FlagEvent {
+        match change.item {
+            Some(flag) => FlagEvent::Patch(change.id, flag),
+            None => FlagEvent::Delete(change.id, change.key),
+        }
+    }
+}
+
+// Follows the changes of a single path. On first poll it subscribes to the
+// flag store and yields either a full snapshot or, when resuming, the changes
+// since the given id. After that each change is yielded as it is recorded.
+// This is shared by the SSE and WebSocket transports.
+pub struct FlagFeed {
     id: String,
     path: FlagPath,
     state: State,
-    last_seen: Instant,
+    last_id: u64,
+    resume_from: Option<u64>,
     subbed: bool,
+    pending: VecDeque<FlagEvent>,
 }
 
-impl FlagStream {
-    fn get_store_payload(&self) -> Poll<Option<Bytes>, Error> {
-        self.state
-            .flags()
-            .get_all(&self.path)
-            .and_then(|flags| {
-                let mut flag_list = flags.values().collect::<Vec<&Flag>>();
-                flag_list
-                    .as_mut_slice()
-                    .sort_by(|&a, &b| a.key().cmp(b.key()));
-                serde_json::to_string(&flag_list).map_err(|err| err.into())
-            })
-            .map(|json| HEADER.to_string() + "data:" + &json + "\n\n")
-            .map(|event| Bytes::from(event))
-            .map(|bytes| Async::Ready(Some(bytes)))
-            .or_else(|_| Ok(Async::NotReady))
+impl FlagFeed {
+    pub fn new(state: State, path: FlagPath, resume_from: Option<u64>) -> FlagFeed {
+        FlagFeed {
+            id: Uuid::new_v4().to_string(),
+            path: path,
+            state: state,
+            last_id: 0,
+            resume_from: resume_from,
+            subbed: false,
+            pending: VecDeque::new(),
+        }
     }
 
-    fn poll_store(&mut self) -> Poll<Option<Bytes>, Error> {
-        match self.state.flags().updated_at() {
-            Ok(updated) => {
-                if updated > self.last_seen {
-                    self.last_seen = updated;
-                    self.get_store_payload()
-                } else {
-                    Ok(Async::NotReady)
-                }
+    fn queue_snapshot(&mut self) -> bool {
+        // Read the sequence before the flags so that a change landing in
+        // between is replayed rather than skipped
+        let state = self.state.clone();
+        let flags = state.flags();
+
+        let snapshot = flags.last_change(&self.path).and_then(|last_id| {
+            let all = flags.get_all(&self.path)?;
+            let mut flag_list = all.into_iter().map(|(_, flag)| flag).collect::<Vec<Flag>>();
+            flag_list
+                .as_mut_slice()
+                .sort_by(|a, b| a.key().cmp(b.key()));
+
+            Ok((last_id, flag_list))
+        });
+
+        match snapshot {
+            Ok((last_id, flag_list)) => {
+                self.last_id = last_id;
+                self.pending.push_back(FlagEvent::Put(last_id, flag_list));
+                true
             }
-            Err(_) => Ok(Async::Ready(None)),
+            Err(_) => false,
+        }
+    }
+
+    // Each change carries the mutation that produced it, so there is no
+    // need to go back to the store for the rest of the path
+    fn queue_changes(&mut self, changes: Vec<Change<Flag>>) {
+        for change in changes.into_iter() {
+            self.last_id = change.id;
+            self.pending.push_back(FlagEvent::from_change(change));
+        }
+    }
+
+    // Resume from the id the client last saw when it is still covered by the
+    // change history, otherwise fall back to sending a full snapshot
+    fn resume(&mut self, id: u64) -> bool {
+        match self.state.flags().changes_since(&self.path, id) {
+            Ok(Some(changes)) => {
+                self.last_id = id;
+                self.queue_changes(changes);
+                true
+            }
+            _ => self.queue_snapshot(),
+        }
+    }
+
+    // Queue the changes since the last event as patches, or a fresh snapshot
+    // if this feed has fallen too far behind the change history
+    fn poll_store(&mut self) -> bool {
+        match self.state.flags().changes_since(&self.path, self.last_id) {
+            Ok(Some(changes)) => {
+                self.queue_changes(changes);
+                true
+            }
+            Ok(None) => self.queue_snapshot(),
+            Err(_) => false,
         }
     }
 }
 
-impl Drop for FlagStream {
+impl Drop for FlagFeed {
     fn drop(&mut self) {
         self.state.flags().unsub(self.id.as_str(), &self.path);
     }
 }
 
-impl Stream for FlagStream {
-    type Item = Bytes;
-    type Error = Error;
+impl Stream for FlagFeed {
+    type Item = FlagEvent;
+    type Error = ();
 
-    fn poll(&mut self) -> Poll<Option<Bytes>, Error> {
-        println!("stream poll");
-        if !self.subbed {
+    fn poll(&mut self) -> Poll<Option<FlagEvent>, ()> {
+        if let Some(event) = self.pending.pop_front() {
+            return Ok(Async::Ready(Some(event)));
+        }
+
+        let ok = if !self.subbed {
             self.subbed =
                 self.state
                     .flags()
                     .sub(self.id.as_str(), &self.path, Some(task::current()));
-            self.get_store_payload()
+
+            match self.resume_from {
+                Some(id) => self.resume(id),
+                None => self.queue_snapshot(),
+            }
         } else {
             self.poll_store()
+        };
+
+        match self.pending.pop_front() {
+            Some(event) => Ok(Async::Ready(Some(event))),
+            None if ok => Ok(Async::NotReady),
+            None => Ok(Async::Ready(None)),
+        }
+    }
+}
+
+struct FlagStream {
+    key: String,
+    feed: FlagFeed,
+    state: State,
+    retry_sent: bool,
+    heartbeat: Interval,
+    expires: Delay,
+}
+
+fn event(flag_event: &FlagEvent) -> Result<String, serde_json::Error> {
+    let json = serde_json::to_string(&flag_event.data()?)?;
+
+    Ok([
+        "id:",
+        &flag_event.id().to_string(),
+        "\nevent:",
+        flag_event.name(),
+        "\ndata:",
+        &json,
+        "\n\n",
+    ].concat())
+}
+
+impl Drop for FlagStream {
+    fn drop(&mut self) {
+        self.state.close_stream(self.key.as_str());
+    }
+}
+
+impl Stream for FlagStream {
+    type Item = Bytes;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Bytes>, Error> {
+        if !self.retry_sent {
+            self.retry_sent = true;
+            let retry = self.state.stream_config().retry;
+            let millis = retry.as_secs() * 1000 + retry.subsec_millis() as u64;
+
+            return Ok(Async::Ready(Some(Bytes::from(format!("retry:{}\n\n", millis)))));
+        }
+
+        // Streams are closed once they reach their maximum lifetime, the
+        // client will reconnect and resume with its last event id
+        match self.expires.poll() {
+            Ok(Async::NotReady) => (),
+            _ => return Ok(Async::Ready(None)),
+        }
+
+        match self.feed.poll() {
+            Ok(Async::Ready(Some(flag_event))) => {
+                metrics::inc(metrics::EVENTS, &[("transport", "sse"), ("event", flag_event.name())]);
+                Ok(Async::Ready(Some(Bytes::from(event(&flag_event)?))))
+            }
+            Ok(Async::NotReady) => match self.heartbeat.poll() {
+                Ok(Async::Ready(Some(_))) => Ok(Async::Ready(Some(Bytes::from(HEARTBEAT)))),
+                Ok(Async::NotReady) => Ok(Async::NotReady),
+                _ => Ok(Async::Ready(None)),
+            },
+            _ => Ok(Async::Ready(None)),
         }
     }
 }
 
+// Streams are counted per caller, which is either a user or an SDK key
+pub fn stream_key(caller: &str, path: &FlagPath) -> String {
+    [caller, "/", path.as_ref()].concat()
+}
+
+fn caller<S>(req: &HttpRequest<S>) -> Option<String> {
+    let ext = req.extensions();
+
+    ext.get::<User>()
+        .map(|user| user.uuid.clone())
+        .or_else(|| ext.get::<SdkKey>().map(|key| key.id.clone()))
+}
+
 pub fn flag_stream<'r>(req: &'r HttpRequest<State>) -> Result<HttpResponse, APIError> {
     let flag_req = FlagReq::from_req(&req)?;
+    let key = caller(req)
+        .map(|caller| stream_key(&caller, &flag_req.path))
+        .ok_or(APIError::Unauthorized)?;
+
+    if !req.state().open_stream(key.as_str()) {
+        Err(APIError::TooManyStreams)?
+    }
+
+    let resume_from = req.headers()
+        .get(LAST_EVENT_ID)
+        .and_then(|id| id.to_str().ok())
+        .and_then(|id| id.trim().parse::<u64>().ok());
+
+    let config = req.state().stream_config().clone();
+    let now = Instant::now();
 
     let stream = FlagStream {
-        id: Uuid::new_v4().to_string(),
-        path: flag_req.path,
+        key: key,
+        feed: FlagFeed::new(req.state().clone(), flag_req.path, resume_from),
         state: req.state().clone(),
-        last_seen: Instant::now(),
-        subbed: false,
+        retry_sent: false,
+        heartbeat: Interval::new(now + config.heartbeat, config.heartbeat),
+        expires: Delay::new(now + config.max_lifetime),
     };
 
     Ok(HttpResponse::Ok()
@@ -101,3 +345,23 @@ pub fn flag_stream<'r>(req: &'r HttpRequest<State>) -> Result<HttpResponse, APIE
         // .force_close()
         .streaming(stream))
 }
+
+// Tickets are only handed to users, SDK keys can already be used to open a
+// stream. The ticket is checked when the stream is opened, so it only needs to
+// live long enough for the client to connect with it.
+pub fn ticket<'r>(req: &'r HttpRequest<State>) -> Result<HttpResponse, APIError> {
+    let flag_req = FlagReq::from_req(&req)?;
+    let user = req.extensions()
+        .get::<User>()
+        .cloned()
+        .ok_or(APIError::Unauthorized)?;
+
+    let ttl = req.state().stream_config().ticket_ttl;
+    let (ticket, claims) = req.state().sessions().issue_ticket(&user, &flag_req.path, ttl)?;
+
+    serde_json::to_string(&TicketResp {
+        ticket: ticket.as_str(),
+        expires: claims.exp,
+    }).or(Err(APIError::FailedToSerialize))
+        .map(|json| HttpResponse::Ok().content_type("application/json").body(json))
+}
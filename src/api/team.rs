@@ -0,0 +1,196 @@
+use actix_web::*;
+use actix_web::http::StatusCode;
+use futures::{future, Future, Stream};
+use serde_json;
+
+use api::State;
+use api::access;
+use api::error::APIError;
+use grant::Role;
+use team::Team;
+use user::User;
+
+pub const TEAM_PATH: &'static str = "teams";
+
+#[derive(Deserialize)]
+struct TeamReq {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct MemberReq {
+    user: String,
+    role: Role,
+}
+
+fn caller(req: &HttpRequest<State>) -> Result<User, APIError> {
+    req.extensions()
+        .get::<User>()
+        .cloned()
+        .ok_or(APIError::Unauthorized)
+}
+
+fn param(req: &HttpRequest<State>, name: &str) -> Result<String, APIError> {
+    req.match_info()
+        .get(name)
+        .map(|val| val.to_string())
+        .ok_or(APIError::FailedToParseParams)
+}
+
+// Finds a team and checks that the caller holds at least the given role on it
+fn find_team(state: &State, user: &User, id: &str, role: Role) -> Result<Team, APIError> {
+    let team = state
+        .teams()
+        .get(&TEAM_PATH.to_string(), id)
+        .map_err(APIError::read)?
+        .ok_or(APIError::FailedToFind)?;
+
+    let held = if user.is_admin() {
+        Some(Role::Owner)
+    } else {
+        team.role_of(&user.uuid)
+    };
+
+    access::require(held, role)?;
+    Ok(team)
+}
+
+fn store_team(state: &State, team: &Team, status: StatusCode) -> Result<HttpResponse, APIError> {
+    state
+        .teams()
+        .upsert(&TEAM_PATH.to_string(), team.id.as_str(), team)
+        .map_err(APIError::write)?;
+
+    serde_json::to_string(team)
+        .or(Err(APIError::FailedToSerialize))
+        .map(|json| HttpResponse::build(status).body(json))
+}
+
+pub fn create<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+    let user = match caller(&req) {
+        Ok(user) => user,
+        Err(err) => return Box::new(future::err(err)),
+    };
+
+    req.json()
+        .from_err()
+        .and_then(move |team_req: TeamReq| {
+            // Disallow empty team names
+            if team_req.name.trim().len() == 0 {
+                Err(APIError::invalid("name", "must not be empty"))?
+            }
+
+            store_team(&state, &Team::new(team_req.name, &user.uuid), StatusCode::CREATED)
+        })
+        .responder()
+}
+
+// Lists the teams the caller is a member of
+pub fn all<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+    let user = match caller(&req) {
+        Ok(user) => user,
+        Err(err) => return Box::new(future::err(err)),
+    };
+
+    Box::new(future::ok(()).and_then(move |_| {
+        let mut teams = state
+            .teams()
+            .get_all(&TEAM_PATH.to_string())
+            .map_err(APIError::read)?
+            .into_iter()
+            .map(|(_, team)| team)
+            .filter(|team| team.role_of(&user.uuid).is_some())
+            .collect::<Vec<Team>>();
+        teams.as_mut_slice().sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(serde_json::to_string(&teams)
+            .or(Err(APIError::FailedToSerialize))
+            .into())
+    }))
+}
+
+pub fn read<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+    let (user, id) = match caller(&req).and_then(|user| Ok((user, param(&req, "team")?))) {
+        Ok(res) => res,
+        Err(err) => return Box::new(future::err(err)),
+    };
+
+    Box::new(future::ok(()).and_then(move |_| {
+        let team = find_team(&state, &user, &id, Role::Viewer)?;
+
+        Ok(serde_json::to_string(&team)
+            .or(Err(APIError::FailedToSerialize))
+            .into())
+    }))
+}
+
+pub fn delete<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+    let (user, id) = match caller(&req).and_then(|user| Ok((user, param(&req, "team")?))) {
+        Ok(res) => res,
+        Err(err) => return Box::new(future::err(err)),
+    };
+
+    Box::new(future::ok(()).and_then(move |_| {
+        find_team(&state, &user, &id, Role::Owner)?;
+
+        state
+            .teams()
+            .delete(&TEAM_PATH.to_string(), &id)
+            .and_then(|_| Ok(HttpResponse::new(StatusCode::OK)))
+            .map_err(APIError::write)
+    }))
+}
+
+pub fn add_member<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+    let (user, id) = match caller(&req).and_then(|user| Ok((user, param(&req, "team")?))) {
+        Ok(res) => res,
+        Err(err) => return Box::new(future::err(err)),
+    };
+
+    req.json()
+        .from_err()
+        .and_then(move |member_req: MemberReq| {
+            let mut team = find_team(&state, &user, &id, Role::Owner)?;
+            team.set_member(&member_req.user, member_req.role);
+
+            if !team.has_owner() {
+                Err(APIError::Forbidden)?
+            }
+
+            store_team(&state, &team, StatusCode::OK)
+        })
+        .responder()
+}
+
+// Owners may remove anyone, other members may only leave the team
+pub fn remove_member<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+    let (user, id, member) = match caller(&req)
+        .and_then(|user| Ok((user, param(&req, "team")?, param(&req, "user")?)))
+    {
+        Ok(res) => res,
+        Err(err) => return Box::new(future::err(err)),
+    };
+
+    Box::new(future::ok(()).and_then(move |_| {
+        let needed = if member == user.uuid {
+            Role::Viewer
+        } else {
+            Role::Owner
+        };
+
+        let mut team = find_team(&state, &user, &id, needed)?;
+        team.remove_member(&member).ok_or(APIError::FailedToFind)?;
+
+        if !team.has_owner() {
+            Err(APIError::Forbidden)?
+        }
+
+        store_team(&state, &team, StatusCode::OK)
+    }))
+}
@@ -0,0 +1,652 @@
+use actix_web::*;
+use actix_web::http::header;
+use futures::{future, Future};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+use serde_yaml;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use api::State;
+use api::access;
+use api::error::{APIError, FieldError};
+use api::flag::validate;
+use api::flag_req::FlagReq;
+use api::history;
+use flag::{Flag, FlagPath};
+use grant::Role;
+use history::{HistoryAction, HistoryEntry};
+use user::User;
+
+pub const SCHEMA_VERSION: u32 = 1;
+const PATH_KEY: &'static str = "paths";
+const IMPORT_SOURCE: &'static str = "import";
+const MAX_IMPORT_SIZE: usize = 4 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Json,
+    Yaml,
+}
+
+impl Format {
+    fn from_name(name: &str) -> Format {
+        if name.contains("yaml") || name.contains("yml") {
+            Format::Yaml
+        } else {
+            Format::Json
+        }
+    }
+
+    fn content_type(&self) -> &'static str {
+        match self {
+            &Format::Json => "application/json",
+            &Format::Yaml => "application/x-yaml",
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            &Format::Json => "json",
+            &Format::Yaml => "yaml",
+        }
+    }
+}
+
+// The flags of a single path. The owner is left out so that a document can
+// be imported into another account.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FlagExport {
+    pub schema_version: u32,
+    pub app: String,
+    pub env: String,
+    pub flags: Vec<Flag>,
+}
+
+// Every environment of an app that the caller can see, so that its paths
+// can be recreated along with their flags
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AppExport {
+    pub schema_version: u32,
+    pub app: String,
+    pub paths: Vec<PathExport>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PathExport {
+    pub env: String,
+    pub flags: Vec<Flag>,
+}
+
+// Documents carry their own version, which has to be checked before the
+// rest of them can be trusted
+trait Versioned {
+    fn schema_version(&self) -> u32;
+}
+
+impl Versioned for FlagExport {
+    fn schema_version(&self) -> u32 {
+        self.schema_version
+    }
+}
+
+impl Versioned for AppExport {
+    fn schema_version(&self) -> u32 {
+        self.schema_version
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportMode {
+    // Creates and updates the imported flags and leaves the others alone
+    Merge,
+    // Also deletes the flags that are not in the document
+    Replace,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub created: Vec<String>,
+    pub updated: Vec<String>,
+    pub deleted: Vec<String>,
+    pub skipped: Vec<String>,
+}
+
+// What an import of a whole app changed, by environment
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct AppImportReport {
+    pub dry_run: bool,
+    pub created_paths: Vec<String>,
+    pub envs: BTreeMap<String, ImportReport>,
+}
+
+// A single write of an import, along with what it replaces so that it can
+// be undone
+#[derive(Debug)]
+struct Write {
+    path: FlagPath,
+    key: String,
+    before: Option<Flag>,
+    after: Option<Flag>,
+}
+
+fn format_of(req: &HttpRequest<State>) -> Format {
+    req.query()
+        .get("format")
+        .map(|format| Format::from_name(format))
+        .unwrap_or_else(|| {
+            req.headers()
+                .get(header::ACCEPT)
+                .and_then(|accept| accept.to_str().ok())
+                .map(Format::from_name)
+                .unwrap_or(Format::Json)
+        })
+}
+
+fn sorted_flags(state: &State, path: &FlagPath) -> Result<Vec<Flag>, APIError> {
+    let mut flags = state
+        .flags()
+        .get_all(path)
+        .map_err(APIError::read)?
+        .into_iter()
+        .map(|(_, flag)| flag)
+        .collect::<Vec<Flag>>();
+    flags.sort_by(|a, b| a.key().cmp(b.key()));
+
+    Ok(flags)
+}
+
+fn download<T: Serialize>(doc: &T, format: Format, name: &str) -> Result<HttpResponse, APIError> {
+    let body = match format {
+        Format::Json => serde_json::to_string_pretty(doc).or(Err(APIError::FailedToSerialize))?,
+        Format::Yaml => serde_yaml::to_string(doc).or(Err(APIError::FailedToSerialize))?,
+    };
+    let file = format!("{}.{}", name, format.extension());
+
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .header(header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", file))
+        .body(body))
+}
+
+pub fn export<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+    let flag_req = match FlagReq::from_req(&req) {
+        Ok(res) => res,
+        Err(err) => return Box::new(future::err(err)),
+    };
+    let format = format_of(req);
+
+    Box::new(future::ok(()).and_then(move |_| {
+        let doc = FlagExport {
+            schema_version: SCHEMA_VERSION,
+            app: flag_req.path.app.clone(),
+            env: flag_req.path.env.clone(),
+            flags: sorted_flags(&state, &flag_req.path)?,
+        };
+
+        download(&doc, format, &format!("{}-{}", doc.app, doc.env))
+    }))
+}
+
+// The caller, the owner the request acts for and the app in the route
+fn app_req(req: &HttpRequest<State>) -> Result<(User, String, String), APIError> {
+    let user = req.extensions()
+        .get::<User>()
+        .cloned()
+        .ok_or(APIError::Unauthorized)?;
+    let app = req.match_info()
+        .get("app")
+        .map(|app| app.to_string())
+        .ok_or(APIError::FailedToParseParams)?;
+    let owner = access::owner(req, &user);
+
+    Ok((user, owner, app))
+}
+
+// The stored paths of an app, by environment
+fn app_paths(state: &State, owner: &str, app: &str) -> Result<BTreeMap<String, FlagPath>, APIError> {
+    Ok(state
+        .paths()
+        .get_all(&PATH_KEY.to_string())
+        .map_err(APIError::read)?
+        .into_iter()
+        .map(|(_, path)| path)
+        .filter(|path| path.owner == owner && path.app == app)
+        .map(|path| (path.env.clone(), path))
+        .collect())
+}
+
+// Exports every environment of an app that the caller may read
+pub fn export_app<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+    let (user, owner, app) = match app_req(req) {
+        Ok(res) => res,
+        Err(err) => return Box::new(future::err(err)),
+    };
+    let format = format_of(req);
+
+    Box::new(future::ok(()).and_then(move |_| {
+        let mut paths = vec![];
+
+        for (env, path) in app_paths(&state, &owner, &app)?.into_iter() {
+            if access::role_for(&state, &user, &owner, &app, Some(&env))?.is_some() {
+                paths.push(PathExport {
+                    flags: sorted_flags(&state, &path)?,
+                    env: env,
+                });
+            }
+        }
+
+        if paths.is_empty() {
+            return Err(APIError::FailedToFind);
+        }
+
+        let doc = AppExport {
+            schema_version: SCHEMA_VERSION,
+            app: app,
+            paths: paths,
+        };
+
+        download(&doc, format, &doc.app)
+    }))
+}
+
+fn decode<T: DeserializeOwned + Versioned>(body: &[u8], format: Format) -> Result<T, APIError> {
+    let doc: T = match format {
+        Format::Json => serde_json::from_slice(body).map_err(|err| APIError::MalformedJson(err.to_string()))?,
+        Format::Yaml => serde_yaml::from_slice(body).map_err(|err| APIError::MalformedYaml(err.to_string()))?,
+    };
+
+    if doc.schema_version() != SCHEMA_VERSION {
+        return Err(APIError::invalid("schema_version", format!("must be {}", SCHEMA_VERSION)));
+    }
+
+    Ok(doc)
+}
+
+// Checks every flag before anything is written
+fn check_flags(flags: &[Flag], field: &str, errors: &mut Vec<FieldError>) {
+    let mut seen = HashSet::new();
+
+    for (i, flag) in flags.iter().enumerate() {
+        if !seen.insert(flag.key()) {
+            errors.push(FieldError::new(format!("{}[{}].key", field, i), "is listed more than once"));
+        }
+
+        if let Err(APIError::InvalidFields(invalid)) = validate(flag) {
+            errors.extend(invalid.into_iter().map(|err| {
+                FieldError::new(format!("{}[{}].{}", field, i, err.field), err.message)
+            }));
+        }
+    }
+}
+
+fn checked<T>(doc: T, errors: Vec<FieldError>) -> Result<T, APIError> {
+    if errors.is_empty() {
+        Ok(doc)
+    } else {
+        Err(APIError::InvalidFields(errors))
+    }
+}
+
+fn parse(body: &[u8], format: Format) -> Result<FlagExport, APIError> {
+    let doc: FlagExport = decode(body, format)?;
+    let mut errors = vec![];
+
+    check_flags(&doc.flags, "flags", &mut errors);
+    checked(doc, errors)
+}
+
+fn parse_app(body: &[u8], format: Format) -> Result<AppExport, APIError> {
+    let doc: AppExport = decode(body, format)?;
+    let mut errors = vec![];
+    let mut seen = HashSet::new();
+
+    for (i, path) in doc.paths.iter().enumerate() {
+        if !seen.insert(path.env.as_str()) {
+            errors.push(FieldError::new(format!("paths[{}].env", i), "is listed more than once"));
+        }
+
+        check_flags(&path.flags, &format!("paths[{}].flags", i), &mut errors);
+    }
+
+    checked(doc, errors)
+}
+
+// Works out the writes an import into a path makes and reports them
+fn plan(path: &FlagPath, current: &HashMap<String, Flag>, flags: &[Flag], mode: ImportMode) -> (Vec<Write>, ImportReport) {
+    let mut writes = vec![];
+    let mut report = ImportReport::default();
+
+    for flag in flags.iter() {
+        match current.get(flag.key()) {
+            Some(existing) => {
+                let mut updated = existing.clone();
+                updated.update_from(flag, false);
+
+                if updated.same_as(existing) {
+                    report.skipped.push(flag.key().to_string());
+                } else {
+                    report.updated.push(flag.key().to_string());
+                    writes.push(Write {
+                        path: path.clone(),
+                        key: flag.key().to_string(),
+                        before: Some(existing.clone()),
+                        after: Some(updated),
+                    });
+                }
+            }
+            None => {
+                report.created.push(flag.key().to_string());
+                writes.push(Write {
+                    path: path.clone(),
+                    key: flag.key().to_string(),
+                    before: None,
+                    after: Some(Flag::copy_of(flag, IMPORT_SOURCE)),
+                });
+            }
+        }
+    }
+
+    if mode == ImportMode::Replace {
+        let imported = flags.iter().map(|flag| flag.key()).collect::<HashSet<&str>>();
+        let mut removed = current
+            .values()
+            .filter(|flag| !imported.contains(flag.key()))
+            .collect::<Vec<&Flag>>();
+        removed.sort_by(|a, b| a.key().cmp(b.key()));
+
+        for flag in removed.into_iter() {
+            report.deleted.push(flag.key().to_string());
+            writes.push(Write {
+                path: path.clone(),
+                key: flag.key().to_string(),
+                before: Some(flag.clone()),
+                after: None,
+            });
+        }
+    }
+
+    (writes, report)
+}
+
+fn write(state: &State, path: &FlagPath, key: &str, flag: Option<&Flag>) -> Result<(), APIError> {
+    match flag {
+        Some(flag) => state.flags().upsert(path, key, flag).map(|_| ()),
+        None => state.flags().delete(path, key).map(|_| ()),
+    }.map_err(APIError::write)
+}
+
+// Stores cannot write several items at once, so when a write fails the ones
+// before it are undone
+fn apply(state: &State, writes: &[Write], actor: &str) -> Result<(), APIError> {
+    for (i, change) in writes.iter().enumerate() {
+        if let Err(err) = write(state, &change.path, &change.key, change.after.as_ref()) {
+            for done in writes[..i].iter().rev() {
+                if write(state, &done.path, &done.key, done.before.as_ref()).is_err() {
+                    error!("Failed to undo import of {} in {}", done.key, done.path.as_ref());
+                }
+            }
+
+            return Err(err);
+        }
+    }
+
+    for change in writes.iter() {
+        let action = match change.after {
+            Some(_) => HistoryAction::Imported,
+            None => HistoryAction::Deleted,
+        };
+        let entry = HistoryEntry::new(change.key.as_str(), actor, action, change.after.clone());
+
+        history::record(state, &change.path, entry.with_note("import"));
+    }
+
+    Ok(())
+}
+
+// The mode, dry run and format of an import
+fn import_options(req: &HttpRequest<State>) -> Result<(ImportMode, bool, Format), APIError> {
+    let mode = match req.query().get("mode").map(|mode| mode.as_str()) {
+        None | Some("merge") => ImportMode::Merge,
+        Some("replace") => ImportMode::Replace,
+        Some(_) => return Err(APIError::invalid("mode", "must be merge or replace")),
+    };
+    let dry_run = req.query()
+        .get("dry_run")
+        .map(|dry_run| dry_run == "true" || dry_run == "1")
+        .unwrap_or(false);
+    let format = req.headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .map(Format::from_name)
+        .unwrap_or(Format::Json);
+
+    Ok((mode, dry_run, format))
+}
+
+// Imports a document exported from any path. The mode is given as `mode`
+// in the query and `dry_run` only reports what would change.
+pub fn import<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+    let flag_req = match FlagReq::from_req(&req)
+        .and_then(|flag_req| flag_req.require(Role::Editor))
+    {
+        Ok(res) => res,
+        Err(err) => return Box::new(future::err(err)),
+    };
+    let (mode, dry_run, format) = match import_options(req) {
+        Ok(res) => res,
+        Err(err) => return Box::new(future::err(err)),
+    };
+
+    req.body()
+        .limit(MAX_IMPORT_SIZE)
+        .from_err()
+        .and_then(move |body| {
+            let doc = parse(&body, format)?;
+            let current = state
+                .flags()
+                .get_all(&flag_req.path)
+                .map_err(APIError::read)?;
+            let (writes, mut report) = plan(&flag_req.path, &current, &doc.flags, mode);
+
+            report.dry_run = dry_run;
+
+            if !dry_run {
+                apply(&state, &writes, &flag_req.actor)?;
+            }
+
+            serde_json::to_string(&report)
+                .or(Err(APIError::FailedToSerialize))
+                .map(|json| HttpResponse::Ok().content_type("application/json").body(json))
+        })
+        .responder()
+}
+
+// Imports an app exported with its paths. Environments the app does not
+// have yet are created, which takes the owner role on the app like creating
+// them by hand does.
+pub fn import_app<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+    let (user, owner, app) = match app_req(req) {
+        Ok(res) => res,
+        Err(err) => return Box::new(future::err(err)),
+    };
+    let (mode, dry_run, format) = match import_options(req) {
+        Ok(res) => res,
+        Err(err) => return Box::new(future::err(err)),
+    };
+
+    req.body()
+        .limit(MAX_IMPORT_SIZE)
+        .from_err()
+        .and_then(move |body| {
+            let doc = parse_app(&body, format)?;
+            let existing = app_paths(&state, &owner, &app)?;
+            let mut report = AppImportReport::default();
+            let mut created = vec![];
+            let mut writes = vec![];
+
+            for imported in doc.paths.iter() {
+                let role = access::role_for(&state, &user, &owner, &app, Some(&imported.env))?;
+                access::require(role, Role::Editor)?;
+
+                let (path, current) = match existing.get(&imported.env) {
+                    Some(path) => (path.clone(), state.flags().get_all(path).map_err(APIError::read)?),
+                    None => {
+                        access::require(access::role_for(&state, &user, &owner, &app, None)?, Role::Owner)?;
+
+                        let path = FlagPath::new(owner.as_str(), app.as_str(), imported.env.as_str());
+                        report.created_paths.push(imported.env.clone());
+                        created.push(path.clone());
+                        (path, HashMap::new())
+                    }
+                };
+
+                let (path_writes, mut path_report) = plan(&path, &current, &imported.flags, mode);
+
+                path_report.dry_run = dry_run;
+                report.envs.insert(imported.env.clone(), path_report);
+                writes.extend(path_writes);
+            }
+
+            report.dry_run = dry_run;
+
+            if !dry_run {
+                for path in created.iter() {
+                    state
+                        .paths()
+                        .upsert(&PATH_KEY.to_string(), path.as_ref(), path)
+                        .map_err(APIError::write)?;
+                }
+
+                if let Err(err) = apply(&state, &writes, &user.uuid) {
+                    for path in created.iter() {
+                        if state.paths().delete(&PATH_KEY.to_string(), path.as_ref()).is_err() {
+                            error!("Failed to undo import of path {}", path.as_ref());
+                        }
+                    }
+
+                    return Err(err);
+                }
+            }
+
+            serde_json::to_string(&report)
+                .or(Err(APIError::FailedToSerialize))
+                .map(|json| HttpResponse::Ok().content_type("application/json").body(json))
+        })
+        .responder()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use flag::FlagValue;
+
+    fn doc(flags: Vec<Flag>) -> FlagExport {
+        FlagExport {
+            schema_version: SCHEMA_VERSION,
+            app: "app".to_string(),
+            env: "env".to_string(),
+            flags: flags,
+        }
+    }
+
+    #[test]
+    fn test_reads_json_and_yaml() {
+        let exported = doc(vec![Flag::new("f1", FlagValue::Bool(true), 2, true)]);
+        let json = serde_json::to_vec(&exported).unwrap();
+        let yaml = serde_yaml::to_vec(&exported).unwrap();
+
+        assert_eq!(parse(&json, Format::Json).unwrap(), exported);
+        assert_eq!(parse(&yaml, Format::Yaml).unwrap(), exported);
+        assert_eq!(parse(b"flags: [", Format::Yaml).unwrap_err().code(), "malformed_yaml");
+        assert_eq!(parse(b"{", Format::Json).unwrap_err().code(), "malformed_json");
+    }
+
+    #[test]
+    fn test_rejects_invalid_documents() {
+        let mut exported = doc(vec![
+            Flag::new("f1", FlagValue::Bool(true), 1, true),
+            Flag::new("f1", FlagValue::Bool(true), 1, true),
+        ]);
+        let json = serde_json::to_vec(&exported).unwrap();
+
+        match parse(&json, Format::Json) {
+            Err(APIError::InvalidFields(errors)) => assert_eq!(errors[0].field, "flags[1].key"),
+            _ => panic!("Expected invalid fields"),
+        }
+
+        exported.schema_version = 2;
+        assert!(parse(&serde_json::to_vec(&exported).unwrap(), Format::Json).is_err());
+    }
+
+    #[test]
+    fn test_plans_merge_and_replace() {
+        let mut current = HashMap::new();
+        current.insert("f1".to_string(), Flag::new("f1", FlagValue::Bool(true), 1, true));
+        current.insert("f2".to_string(), Flag::new("f2", FlagValue::Bool(true), 1, true));
+        current.insert("f3".to_string(), Flag::new("f3", FlagValue::Bool(true), 1, true));
+
+        let imported = doc(vec![
+            Flag::new("f1", FlagValue::Bool(true), 1, true),
+            Flag::new("f2", FlagValue::Bool(false), 1, true),
+            Flag::new("f4", FlagValue::Bool(true), 1, true),
+        ]);
+
+        let path = FlagPath::new("owner", "app", "env");
+
+        let (writes, report) = plan(&path, &current, &imported.flags, ImportMode::Merge);
+        assert_eq!(writes.len(), 2);
+        assert_eq!(report.created, vec!["f4"]);
+        assert_eq!(report.updated, vec!["f2"]);
+        assert_eq!(report.skipped, vec!["f1"]);
+
+        let (writes, report) = plan(&path, &current, &imported.flags, ImportMode::Replace);
+        assert_eq!(writes.len(), 3);
+        assert_eq!(writes[2].path.as_ref(), path.as_ref());
+        assert_eq!(report.deleted, vec!["f3"]);
+    }
+
+    #[test]
+    fn test_reads_app_exports() {
+        let exported = AppExport {
+            schema_version: SCHEMA_VERSION,
+            app: "app".to_string(),
+            paths: vec![
+                PathExport {
+                    env: "staging".to_string(),
+                    flags: vec![Flag::new("f1", FlagValue::Bool(true), 1, true)],
+                },
+                PathExport {
+                    env: "production".to_string(),
+                    flags: vec![],
+                },
+            ],
+        };
+        let yaml = serde_yaml::to_vec(&exported).unwrap();
+
+        assert_eq!(parse_app(&yaml, Format::Yaml).unwrap(), exported);
+
+        let mut twice = exported.clone();
+        twice.paths[1].env = "staging".to_string();
+        twice.paths[1].flags = vec![
+            Flag::new("f1", FlagValue::Bool(true), 1, true),
+            Flag::new("f1", FlagValue::Bool(true), 1, true),
+        ];
+
+        match parse_app(&serde_json::to_vec(&twice).unwrap(), Format::Json) {
+            Err(APIError::InvalidFields(errors)) => {
+                let fields = errors.into_iter().map(|err| err.field).collect::<Vec<String>>();
+                assert_eq!(fields, vec!["paths[1].env", "paths[1].flags[1].key"]);
+            }
+            _ => panic!("Expected invalid fields"),
+        }
+    }
+}
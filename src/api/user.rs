@@ -0,0 +1,237 @@
+use actix_web::*;
+use actix_web::http::StatusCode;
+use futures::{future, Future, Stream};
+use serde_json;
+use uuid::Uuid;
+
+use api::State;
+use api::error::APIError;
+use api::session;
+use user::User;
+
+pub const USER_PATH: &'static str = "users";
+
+#[derive(Deserialize)]
+struct UserReq {
+    key: String,
+    #[serde(default)]
+    is_admin: bool,
+}
+
+#[derive(Deserialize)]
+struct AdminReq {
+    is_admin: bool,
+}
+
+#[derive(Deserialize)]
+struct DisableReq {
+    disabled: bool,
+}
+
+#[derive(Serialize)]
+struct UserView<'a> {
+    uuid: &'a str,
+    key: &'a str,
+    is_admin: bool,
+    disabled: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    secret: Option<&'a str>,
+}
+
+impl<'a> UserView<'a> {
+    fn new(user: &'a User, secret: Option<&'a str>) -> UserView<'a> {
+        UserView {
+            uuid: user.uuid.as_str(),
+            key: user.key.as_str(),
+            is_admin: user.is_admin(),
+            disabled: user.is_disabled(),
+            secret: secret,
+        }
+    }
+}
+
+fn caller(req: &HttpRequest<State>) -> Result<User, APIError> {
+    req.extensions()
+        .get::<User>()
+        .cloned()
+        .ok_or(APIError::Unauthorized)
+}
+
+fn key_param(req: &HttpRequest<State>) -> Result<String, APIError> {
+    req.match_info()
+        .get("key")
+        .map(|key| key.to_string())
+        .ok_or(APIError::FailedToParseParams)
+}
+
+fn find_user(state: &State, key: &str) -> Result<User, APIError> {
+    state
+        .users()
+        .get(&USER_PATH.to_string(), key)
+        .map_err(APIError::read)?
+        .ok_or(APIError::FailedToFind)
+}
+
+fn store_user(
+    state: &State,
+    user: &User,
+    secret: Option<&str>,
+    status: StatusCode,
+) -> Result<HttpResponse, APIError> {
+    state
+        .users()
+        .upsert(&USER_PATH.to_string(), user.key.as_str(), user)
+        .map_err(APIError::write)?;
+
+    serde_json::to_string(&UserView::new(user, secret))
+        .or(Err(APIError::FailedToSerialize))
+        .map(|json| HttpResponse::build(status).body(json))
+}
+
+// Admins may not lock themselves out
+fn not_self(admin: &User, key: &str) -> Result<(), APIError> {
+    if admin.key == key {
+        Err(APIError::Forbidden)
+    } else {
+        Ok(())
+    }
+}
+
+pub fn all<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+
+    Box::new(future::ok(()).and_then(move |_| {
+        let mut users = state
+            .users()
+            .get_all(&USER_PATH.to_string())
+            .map_err(APIError::read)?
+            .into_iter()
+            .map(|(_, user)| user)
+            .collect::<Vec<User>>();
+        users.as_mut_slice().sort_by(|a, b| a.key.cmp(&b.key));
+
+        let views = users
+            .iter()
+            .map(|user| UserView::new(user, None))
+            .collect::<Vec<UserView>>();
+
+        Ok(serde_json::to_string(&views)
+            .or(Err(APIError::FailedToSerialize))
+            .into())
+    }))
+}
+
+// The generated secret is only ever returned by this call and by reset
+pub fn create<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+
+    req.json()
+        .from_err()
+        .and_then(move |user_req: UserReq| {
+            // Disallow empty string key
+            if user_req.key.trim().len() == 0 {
+                Err(APIError::invalid("key", "must not be empty"))?
+            }
+
+            if let Ok(Some(_exists)) = state.users().get(&USER_PATH.to_string(), &user_req.key) {
+                Err(APIError::AlreadyExists)?
+            }
+
+            let secret = User::generate_secret();
+            let user = User::new(
+                Uuid::new_v4().to_string(),
+                user_req.key,
+                secret.clone(),
+                user_req.is_admin,
+            );
+
+            store_user(&state, &user, Some(&secret), StatusCode::CREATED)
+        })
+        .responder()
+}
+
+pub fn reset_secret<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+    let key = match key_param(&req) {
+        Ok(key) => key,
+        Err(err) => return Box::new(future::err(err)),
+    };
+
+    Box::new(future::ok(()).and_then(move |_| {
+        let mut user = find_user(&state, &key)?;
+        let secret = User::generate_secret();
+        user.set_secret(&secret);
+        session::end_sessions(&state, &mut user)?;
+
+        store_user(&state, &user, Some(&secret), StatusCode::OK)
+    }))
+}
+
+pub fn set_admin<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+    let (admin, key) = match caller(&req).and_then(|admin| Ok((admin, key_param(&req)?))) {
+        Ok(res) => res,
+        Err(err) => return Box::new(future::err(err)),
+    };
+
+    req.json()
+        .from_err()
+        .and_then(move |admin_req: AdminReq| {
+            not_self(&admin, &key)?;
+
+            let mut user = find_user(&state, &key)?;
+            user.set_admin(admin_req.is_admin);
+
+            // Sessions carry the admin flag, so existing ones are ended
+            session::end_sessions(&state, &mut user)?;
+            store_user(&state, &user, None, StatusCode::OK)
+        })
+        .responder()
+}
+
+pub fn set_disabled<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+    let (admin, key) = match caller(&req).and_then(|admin| Ok((admin, key_param(&req)?))) {
+        Ok(res) => res,
+        Err(err) => return Box::new(future::err(err)),
+    };
+
+    req.json()
+        .from_err()
+        .and_then(move |disable_req: DisableReq| {
+            not_self(&admin, &key)?;
+
+            let mut user = find_user(&state, &key)?;
+            user.set_disabled(disable_req.disabled);
+
+            if disable_req.disabled {
+                session::end_sessions(&state, &mut user)?;
+            }
+
+            store_user(&state, &user, None, StatusCode::OK)
+        })
+        .responder()
+}
+
+pub fn delete<'r>(req: &'r HttpRequest<State>) -> Box<Future<Item = HttpResponse, Error = APIError>> {
+    let state = req.state().clone();
+    let (admin, key) = match caller(&req).and_then(|admin| Ok((admin, key_param(&req)?))) {
+        Ok(res) => res,
+        Err(err) => return Box::new(future::err(err)),
+    };
+
+    Box::new(future::ok(()).and_then(move |_| {
+        not_self(&admin, &key)?;
+
+        let mut user = find_user(&state, &key)?;
+        session::end_sessions(&state, &mut user)?;
+
+        state
+            .users()
+            .delete(&USER_PATH.to_string(), &key)
+            .map_err(APIError::write)?
+            .ok_or(APIError::FailedToFind)?;
+
+        Ok(HttpResponse::new(StatusCode::OK))
+    }))
+}
@@ -0,0 +1,155 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, RwLock};
+
+use error::BannerError;
+
+pub const DEFAULT_LEN: usize = 256;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change<T> {
+    pub id: u64,
+    pub key: String,
+    pub item: Option<T>,
+}
+
+// Keeps a bounded, per-path history of mutations. Each path has its own
+// monotonically increasing sequence so that stream consumers can resume
+// from the last id they have seen.
+#[derive(Clone, Debug)]
+pub struct ChangeLog<T> {
+    logs: Arc<RwLock<HashMap<String, (u64, VecDeque<Change<T>>)>>>,
+    len: usize,
+}
+
+pub type ChangeLogResult<T> = Result<T, BannerError>;
+
+impl<T> ChangeLog<T> {
+    pub fn new(len: usize) -> ChangeLog<T> {
+        ChangeLog {
+            logs: Arc::new(RwLock::new(HashMap::new())),
+            len: len,
+        }
+    }
+
+    pub fn last_id<P: AsRef<str>>(&self, path: &P) -> ChangeLogResult<u64> {
+        self.logs
+            .read()
+            .map(|logs| logs.get(path.as_ref()).map(|&(seq, _)| seq).unwrap_or(0))
+            .map_err(|_| {
+                error!("Failed to acquire read guard for change log due to poisoning");
+                BannerError::ChangeLogPoisoned
+            })
+    }
+}
+
+impl<T: Clone> ChangeLog<T> {
+    pub fn record<P: AsRef<str>>(&self, path: &P, key: &str, item: Option<&T>) -> ChangeLogResult<u64> {
+        let len = self.len;
+
+        self.logs
+            .write()
+            .map(|mut logs| {
+                let &mut (ref mut seq, ref mut log) = logs
+                    .entry(path.as_ref().to_string())
+                    .or_insert((0, VecDeque::new()));
+
+                *seq = *seq + 1;
+
+                log.push_back(Change {
+                    id: *seq,
+                    key: key.to_string(),
+                    item: item.cloned(),
+                });
+
+                while log.len() > len {
+                    log.pop_front();
+                }
+
+                *seq
+            })
+            .map_err(|_| {
+                error!("Failed to acquire write guard for change log due to poisoning");
+                BannerError::ChangeLogPoisoned
+            })
+    }
+
+    // Returns the changes made after the given id. If the id is no longer
+    // covered by the retained history (or is from the future, for instance
+    // after a restart) None is returned and the caller needs a full snapshot.
+    pub fn since<P: AsRef<str>>(&self, path: &P, id: u64) -> ChangeLogResult<Option<Vec<Change<T>>>> {
+        self.logs
+            .read()
+            .map(|logs| match logs.get(path.as_ref()) {
+                Some(&(seq, ref log)) => {
+                    if id > seq {
+                        None
+                    } else if id == seq {
+                        Some(vec![])
+                    } else {
+                        match log.front() {
+                            Some(oldest) if oldest.id <= id + 1 => Some(
+                                log.iter()
+                                    .filter(|change| change.id > id)
+                                    .cloned()
+                                    .collect(),
+                            ),
+                            _ => None,
+                        }
+                    }
+                }
+                None => if id == 0 {
+                    Some(vec![])
+                } else {
+                    None
+                },
+            })
+            .map_err(|_| {
+                error!("Failed to acquire read guard for change log due to poisoning");
+                BannerError::ChangeLogPoisoned
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATH: &'static str = "owner:app:env";
+
+    #[test]
+    fn test_assigns_increasing_ids_per_path() {
+        let log: ChangeLog<u8> = ChangeLog::new(DEFAULT_LEN);
+
+        assert_eq!(log.record(&PATH, "a", Some(&1)).unwrap(), 1);
+        assert_eq!(log.record(&PATH, "b", None).unwrap(), 2);
+        assert_eq!(log.record(&"other:app:env", "a", Some(&1)).unwrap(), 1);
+        assert_eq!(log.last_id(&PATH).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_returns_changes_since_id() {
+        let log: ChangeLog<u8> = ChangeLog::new(DEFAULT_LEN);
+        let _ = log.record(&PATH, "a", Some(&1));
+        let _ = log.record(&PATH, "b", Some(&2));
+        let _ = log.record(&PATH, "a", None);
+
+        let changes = log.since(&PATH, 1).unwrap().unwrap();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].key, "b");
+        assert_eq!(changes[1].item, None);
+
+        assert_eq!(log.since(&PATH, 3).unwrap(), Some(vec![]));
+        assert_eq!(log.since(&PATH, 4).unwrap(), None);
+    }
+
+    #[test]
+    fn test_requires_snapshot_when_history_is_truncated() {
+        let log: ChangeLog<u8> = ChangeLog::new(2);
+        let _ = log.record(&PATH, "a", Some(&1));
+        let _ = log.record(&PATH, "b", Some(&2));
+        let _ = log.record(&PATH, "c", Some(&3));
+
+        assert_eq!(log.since(&PATH, 0).unwrap(), None);
+        assert_eq!(log.since(&PATH, 1).unwrap().unwrap().len(), 2);
+    }
+}
@@ -4,6 +4,7 @@ use serde_json::Error as SerdeError;
 
 use std::error::Error;
 use std::fmt;
+use std::io;
 
 // use api::error::APIError;
 #[cfg(feature = "dynamo-backend")]
@@ -23,7 +24,10 @@ pub enum BannerError {
     #[cfg(feature = "redis-backend")] InvalidRedisConfig,
     AllCacheMissing,
     FailedToSerializeItem,
-    UpdatedAtPoisoned
+    UpdatedAtPoisoned,
+    ChangeLogPoisoned,
+    AnalyticsPoisoned,
+    FileFailure(io::Error),
 }
 
 #[cfg(feature = "dynamo-backend")]
@@ -47,20 +51,72 @@ impl From<MongoError> for BannerError {
     }
 }
 
+impl From<io::Error> for BannerError {
+    fn from(err: io::Error) -> BannerError {
+        BannerError::FileFailure(err)
+    }
+}
+
 impl From<SerdeError> for BannerError {
     fn from(_: SerdeError) -> BannerError {
         BannerError::FailedToSerializeItem
     }
 }
 
+impl BannerError {
+    // Whether the backend could not be reached at all, as opposed to failing
+    // to handle a particular request
+    pub fn is_unavailable(&self) -> bool {
+        match self {
+            #[cfg(feature = "redis-backend")]
+            &BannerError::RedisFailure(ref err) => {
+                err.is_io_error() || err.is_connection_refusal() || err.is_timeout()
+            }
+            #[cfg(feature = "dynamo-backend")]
+            &BannerError::DynamoFailure(DynamoError::Credentials(_)) => true,
+            #[cfg(feature = "dynamo-backend")]
+            &BannerError::DynamoFailure(DynamoError::Tls(_)) => true,
+            #[cfg(feature = "dynamo-backend")]
+            &BannerError::DynamoFailure(DynamoError::Describe(_)) => true,
+            _ => false,
+        }
+    }
+}
+
 impl Error for BannerError {
     fn description(&self) -> &str {
-        ""
+        match self {
+            &BannerError::CachePoisonedError => "The cache lock was poisoned",
+            &BannerError::FailedToParsePath => "Failed to parse flag path",
+            #[cfg(feature = "dynamo-backend")]
+            &BannerError::DynamoFailure(_) => "DynamoDB request failed",
+            #[cfg(feature = "mongo-backend")]
+            &BannerError::MongoFailure(_) => "MongoDB request failed",
+            #[cfg(feature = "redis-backend")]
+            &BannerError::RedisFailure(_) => "Redis request failed",
+            #[cfg(feature = "redis-backend")]
+            &BannerError::InvalidRedisConfig => "Invalid Redis configuration",
+            &BannerError::AllCacheMissing => "The cache of all items is missing",
+            &BannerError::FailedToSerializeItem => "Failed to serialize item",
+            &BannerError::UpdatedAtPoisoned => "The updated at lock was poisoned",
+            &BannerError::ChangeLogPoisoned => "The change log lock was poisoned",
+            &BannerError::AnalyticsPoisoned => "The analytics lock was poisoned",
+            &BannerError::FileFailure(_) => "File access failed",
+        }
     }
 }
 
 impl fmt::Display for BannerError {
-    fn fmt(&self, _: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        Ok(())
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            #[cfg(feature = "dynamo-backend")]
+            &BannerError::DynamoFailure(ref err) => write!(f, "{}: {:?}", self.description(), err),
+            #[cfg(feature = "mongo-backend")]
+            &BannerError::MongoFailure(ref err) => write!(f, "{}: {:?}", self.description(), err),
+            #[cfg(feature = "redis-backend")]
+            &BannerError::RedisFailure(ref err) => write!(f, "{}: {}", self.description(), err),
+            &BannerError::FileFailure(ref err) => write!(f, "{}: {}", self.description(), err),
+            _ => write!(f, "{}", self.description()),
+        }
     }
 }
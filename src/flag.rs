@@ -1,22 +1,24 @@
 #[cfg(feature = "mongo-backend")]
 use bson;
-#[cfg(feature = "redis-backend")]
-use redis::{ErrorKind, FromRedisValue, RedisResult, ToRedisArgs, Value as RedisValue};
 #[cfg(feature = "dynamo-backend")]
 use rusoto_dynamodb::AttributeValue;
-#[cfg(feature = "redis-backend")]
+use serde::{Deserialize, Deserializer};
+#[cfg(feature = "dynamo-backend")]
 use serde_json;
 
 #[cfg(feature = "dynamo-backend")]
 use std::collections::HashMap;
 use std::str::FromStr;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use error::BannerError;
 #[cfg(feature = "dynamo-backend")]
 use storage::dynamo::{DynamoError, FromAttrMap};
+use util::current_time;
 
 const PATH_SEP: &'static str = ":";
+const MAX_TAGS: usize = 20;
+const MAX_TEXT: usize = 1024;
+pub const FULL_ROLLOUT: u8 = 100;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FlagPath {
@@ -88,19 +90,121 @@ pub struct Flag {
     value: FlagValue,
     #[cfg_attr(feature = "mongo-backend", serde(with = "bson::compat::u2f"))] version: u64,
     enabled: bool,
+    // The share of users that are served the value, which SDKs pick by
+    // bucketing users. Flags stored before rollouts were added serve everyone.
+    #[serde(default = "full_rollout")]
+    percentage: u8,
     #[serde(default = "current_time")]
     #[cfg_attr(feature = "mongo-backend", serde(with = "bson::compat::u2f"))]
     created: u64,
     #[serde(default = "current_time")]
     #[cfg_attr(feature = "mongo-backend", serde(with = "bson::compat::u2f"))]
     updated: u64,
+    #[serde(flatten)]
+    meta: FlagMeta,
+}
+
+// Descriptive fields that only help people manage flags. They are never
+// evaluated and changing them does not bump the version.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct FlagMeta {
+    pub name: String,
+    pub description: String,
+    pub tags: Vec<String>,
+    pub maintainer: Option<String>,
+    pub permanent: bool,
+    pub links: Vec<FlagLink>,
+    pub source: Option<String>,
+}
+
+// The fields of a flag that an update from the api sets. The key comes from
+// the route. Descriptive fields that are left out keep their stored values.
+#[derive(Debug, Clone, Deserialize)]
+pub struct FlagUpdate {
+    pub value: FlagValue,
+    pub enabled: bool,
+    #[serde(default)]
+    pub percentage: Option<u8>,
+    #[serde(flatten)]
+    pub meta: MetaUpdate,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct MetaUpdate {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub tags: Option<Vec<String>>,
+    // A null maintainer clears it
+    #[serde(deserialize_with = "present")]
+    pub maintainer: Option<Option<String>>,
+    pub permanent: Option<bool>,
+    pub links: Option<Vec<FlagLink>>,
+}
+
+impl FlagUpdate {
+    // The flag as the update would leave it
+    pub fn applied_to(&self, flag: &Flag) -> Flag {
+        let update = &self.meta;
+        let mut meta = flag.meta().clone();
+
+        if let Some(ref name) = update.name {
+            meta.name = name.clone();
+        }
+
+        if let Some(ref description) = update.description {
+            meta.description = description.clone();
+        }
+
+        if let Some(ref tags) = update.tags {
+            meta.tags = tags.clone();
+        }
+
+        if let Some(ref maintainer) = update.maintainer {
+            meta.maintainer = maintainer.clone();
+        }
+
+        if let Some(permanent) = update.permanent {
+            meta.permanent = permanent;
+        }
+
+        if let Some(ref links) = update.links {
+            meta.links = links.clone();
+        }
+
+        let mut updated = Flag::new(flag.key(), self.value.clone(), flag.version(), self.enabled);
+        updated.percentage = self.percentage.unwrap_or(flag.percentage);
+        updated.meta = meta;
+        updated
+    }
+}
+
+fn full_rollout() -> u8 {
+    FULL_ROLLOUT
+}
+
+// Tells a field that was given as null apart from one that was left out
+fn present<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FlagLink {
+    pub kind: LinkKind,
+    pub url: String,
 }
 
-fn current_time() -> u64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or(Duration::from_secs(0))
-        .as_secs()
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkKind {
+    Ticket,
+    Docs,
+    Other,
 }
 
 impl Flag {
@@ -115,11 +219,19 @@ impl Flag {
             value: value,
             version: version,
             enabled: enabled,
+            percentage: FULL_ROLLOUT,
             created: created,
             updated: created,
+            meta: FlagMeta::default(),
         }
     }
 
+    // Builds a flag that starts out served to only part of the users
+    pub fn with_percentage(mut self, percentage: u8) -> Flag {
+        self.percentage = percentage;
+        self
+    }
+
     pub fn eval(&self) -> Option<&FlagValue> {
         if self.enabled {
             Some(&self.value)
@@ -136,6 +248,10 @@ impl Flag {
         self.enabled
     }
 
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
     pub fn is_ver(&self, ver: u64) -> bool {
         self.version == ver
     }
@@ -144,6 +260,14 @@ impl Flag {
         self.key.as_str()
     }
 
+    pub fn created(&self) -> u64 {
+        self.created
+    }
+
+    pub fn updated(&self) -> u64 {
+        self.updated
+    }
+
     pub fn set_value(&mut self, val: &FlagValue) {
         let new_val = val.clone();
 
@@ -154,110 +278,142 @@ impl Flag {
         }
     }
 
+    pub fn percentage(&self) -> u8 {
+        self.percentage
+    }
+
+    // Changes who is served the value, so like the value it bumps the version
+    pub fn set_percentage(&mut self, percentage: u8) {
+        if self.percentage != percentage {
+            self.version = self.version + 1;
+            self.percentage = percentage;
+            self.updated = current_time();
+        }
+    }
+
     pub fn toggle(&mut self, state: bool) {
         if self.enabled != state {
             self.enabled = !self.enabled;
             self.updated = current_time();
         }
     }
-}
-
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(untagged)]
-pub enum FlagValue {
-    Bool(bool),
-}
 
-// Backend Impls
+    pub fn meta(&self) -> &FlagMeta {
+        &self.meta
+    }
 
-#[cfg(feature = "redis-backend")]
-impl FromRedisValue for Flag {
-    fn from_redis_value(v: &RedisValue) -> RedisResult<Flag> {
-        match *v {
-            RedisValue::Data(ref data) => {
-                let data = String::from_utf8(data.clone());
-
-                data.or_else(|_| Err((ErrorKind::TypeError, "Expected utf8 string").into()))
-                    .and_then(|ser| {
-                        serde_json::from_str(ser.as_str()).or_else(|_| {
-                            let err = (ErrorKind::TypeError, "Unable to deserialize json to Flag");
-                            Err(err.into())
-                        })
-                    })
-            }
-            _ => {
-                let err = (
-                    ErrorKind::TypeError,
-                    "Recieved non-data type for deserializing",
-                );
-                Err(err.into())
-            }
+    pub fn set_meta(&mut self, meta: &FlagMeta) {
+        if &self.meta != meta {
+            self.meta = meta.clone();
+            self.updated = current_time();
         }
     }
-}
 
-#[cfg(feature = "redis-backend")]
-impl<'a> ToRedisArgs for Flag {
-    fn write_redis_args(&self, out: &mut Vec<Vec<u8>>) {
-        let ser = serde_json::to_string(&self);
+    // A fresh copy of another flag, as when it is promoted or imported
+    pub fn copy_of<S: Into<String>>(other: &Flag, source: S) -> Flag {
+        let mut flag = Flag::new(other.key(), other.value().clone(), 1, other.is_enabled())
+            .with_percentage(other.percentage());
+        let mut meta = other.meta().clone();
+        meta.source = Some(source.into());
+
+        flag.set_meta(&meta);
+        flag
+    }
+
+    // Takes over the value, state and description of another flag while
+    // keeping where this one came from
+    pub fn update_from(&mut self, other: &Flag, keep_enabled: bool) {
+        let mut meta = other.meta().clone();
+        meta.source = self.meta.source.clone();
 
-        out.push(
-            match ser {
-                Ok(json) => json.as_bytes().into(),
+        self.set_value(other.value());
+        self.set_percentage(other.percentage());
+        self.set_meta(&meta);
 
-                // Because this trait can not normally fail, but json serialization
-                // can fail, the failure cause is encoded as a special value that
-                // is checked by the store
-                Err(_) => "fail".to_string().as_bytes().into(),
-            },
-        )
+        if !keep_enabled {
+            self.toggle(other.is_enabled());
+        }
     }
-}
 
-#[cfg(feature = "redis-backend")]
-impl FromRedisValue for FlagPath {
-    fn from_redis_value(v: &RedisValue) -> RedisResult<FlagPath> {
-        match *v {
-            RedisValue::Data(ref data) => {
-                let data = String::from_utf8(data.clone());
-
-                data.or_else(|_| Err((ErrorKind::TypeError, "Expected utf8 string").into()))
-                    .and_then(|ser| {
-                        serde_json::from_str(ser.as_str()).or_else(|_| {
-                            let err = (ErrorKind::TypeError, "Unable to deserialize json to Path");
-                            Err(err.into())
-                        })
-                    })
-            }
-            _ => {
-                let err = (
-                    ErrorKind::TypeError,
-                    "Recieved non-data type for deserializing",
-                );
-                Err(err.into())
+    // Whether the fields people set are the same, ignoring timestamps
+    pub fn same_as(&self, other: &Flag) -> bool {
+        self.value == other.value
+            && self.enabled == other.enabled
+            && self.percentage == other.percentage
+            && self.meta == other.meta
+    }
+
+    // Checks the fields people write. Tags are listed with commas when
+    // filtering, so they can not contain one.
+    pub fn invalid_fields(&self) -> Vec<(String, &'static str)> {
+        let meta = &self.meta;
+        let mut errors = vec![];
+
+        if self.key.len() == 0 {
+            errors.push(("key".to_string(), "must not be empty"));
+        }
+
+        if self.percentage > FULL_ROLLOUT {
+            errors.push(("percentage".to_string(), "must be at most 100"));
+        }
+
+        if meta.name.len() > MAX_TEXT {
+            errors.push(("name".to_string(), "must be at most 1024 bytes"));
+        }
+
+        if meta.description.len() > MAX_TEXT {
+            errors.push(("description".to_string(), "must be at most 1024 bytes"));
+        }
+
+        if meta.tags.len() > MAX_TAGS {
+            errors.push(("tags".to_string(), "must have at most 20 tags"));
+        }
+
+        if meta.tags.iter().any(|tag| tag.trim().len() == 0 || tag.contains(',')) {
+            errors.push(("tags".to_string(), "must not be empty or contain commas"));
+        }
+
+        for (i, link) in meta.links.iter().enumerate() {
+            if !(link.url.starts_with("https://") || link.url.starts_with("http://")) {
+                errors.push((format!("links[{}].url", i), "must be an http or https url"));
             }
         }
+
+        errors
     }
-}
 
-#[cfg(feature = "redis-backend")]
-impl<'a> ToRedisArgs for FlagPath {
-    fn write_redis_args(&self, out: &mut Vec<Vec<u8>>) {
-        let ser = serde_json::to_string(&self);
+    pub fn has_tags<S: AsRef<str>>(&self, tags: &[S]) -> bool {
+        tags.iter()
+            .all(|tag| self.meta.tags.iter().any(|t| t == tag.as_ref()))
+    }
 
-        out.push(
-            match ser {
-                Ok(json) => json.as_bytes().into(),
+    // Case insensitive search over the key and the descriptive fields
+    pub fn matches(&self, text: &str) -> bool {
+        let text = text.to_lowercase();
+        let maintainer = self.meta.maintainer.as_ref().map(|m| m.as_str()).unwrap_or("");
 
-                // Because this trait can not normally fail, but json serialization
-                // can fail, the failure cause is encoded as a special value that
-                // is checked by the store
-                Err(_) => "fail".to_string().as_bytes().into(),
-            },
-        )
+        [
+            self.key.as_str(),
+            self.meta.name.as_str(),
+            self.meta.description.as_str(),
+            maintainer,
+        ].iter()
+            .any(|field| field.to_lowercase().contains(&text))
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum FlagValue {
+    Bool(bool),
+}
+
+// Backend Impls
+
+redis_json!(Flag);
+
+redis_json!(FlagPath);
+
 #[cfg(feature = "dynamo-backend")]
 impl Into<HashMap<String, AttributeValue>> for Flag {
     fn into(self) -> HashMap<String, AttributeValue> {
@@ -283,6 +439,9 @@ impl Into<HashMap<String, AttributeValue>> for Flag {
         let mut created_attr = AttributeValue::default();
         created_attr.n = Some(self.created.to_string());
 
+        let mut percentage_attr = AttributeValue::default();
+        percentage_attr.n = Some(self.percentage.to_string());
+
         let mut updated_attr = AttributeValue::default();
         updated_attr.n = Some(self.updated.to_string());
 
@@ -291,9 +450,17 @@ impl Into<HashMap<String, AttributeValue>> for Flag {
         map.insert("value".into(), value_attr);
         map.insert("version".into(), version_attr);
         map.insert("enabled".into(), enabled_attr);
+        map.insert("percentage".into(), percentage_attr);
         map.insert("created".into(), created_attr);
         map.insert("updated".into(), updated_attr);
 
+        // Metadata is never queried, so it is kept as a single document
+        if let Ok(meta) = serde_json::to_string(&self.meta) {
+            let mut meta_attr = AttributeValue::default();
+            meta_attr.s = Some(meta);
+            map.insert("meta".into(), meta_attr);
+        }
+
         map
     }
 }
@@ -315,6 +482,12 @@ impl FromAttrMap<Flag> for Flag {
             });
         let enabled = map.get("enabled")
             .and_then(|enabled_data| enabled_data.bool);
+        let percentage = map.get("percentage")
+            .and_then(|percentage_data| match percentage_data.n {
+                Some(ref percentage) => percentage.parse::<u8>().ok(),
+                None => None,
+            })
+            .unwrap_or(FULL_ROLLOUT);
         let created = map.get("created")
             .and_then(|created_data| match created_data.n {
                 Some(ref created) => created.parse::<u64>().ok(),
@@ -325,6 +498,10 @@ impl FromAttrMap<Flag> for Flag {
                 Some(ref updated) => updated.parse::<u64>().ok(),
                 None => None,
             });
+        let meta = map.get("meta")
+            .and_then(|meta_data| meta_data.s.as_ref())
+            .and_then(|meta| serde_json::from_str(meta).ok())
+            .unwrap_or_default();
 
         if let (Some(k), Some(vl), Some(vr), Some(e), Some(c), Some(u)) =
             (key, value, version, enabled, created, updated)
@@ -334,8 +511,10 @@ impl FromAttrMap<Flag> for Flag {
                 value: FlagValue::Bool(vl),
                 version: vr,
                 enabled: e,
+                percentage: percentage,
                 created: c,
                 updated: u,
+                meta: meta,
             })
         } else {
             Err(DynamoError::FailedToParseResponse.into())
@@ -390,6 +569,8 @@ impl FromAttrMap<FlagPath> for FlagPath {
 mod tests {
     use super::*;
 
+    use serde_json;
+
     #[test]
     fn test_returns_some_if_enabled() {
         let f = Flag::new("key-string", FlagValue::Bool(true), 1, true);
@@ -416,4 +597,80 @@ mod tests {
         assert_eq!(f.is_ver(1), true);
         assert_eq!(f.is_ver(2), false);
     }
+
+    #[test]
+    fn test_reads_flags_without_meta() {
+        let f: Flag = serde_json::from_str(r#"{"key":"f1","value":true,"version":1,"enabled":true}"#).unwrap();
+        assert_eq!(f.meta(), &FlagMeta::default());
+        assert_eq!(f.percentage(), FULL_ROLLOUT);
+    }
+
+    #[test]
+    fn test_changing_the_percentage_bumps_the_version() {
+        let mut f = Flag::new("f1", FlagValue::Bool(true), 1, true);
+
+        f.set_percentage(FULL_ROLLOUT);
+        assert_eq!(f.version(), 1);
+
+        f.set_percentage(25);
+        assert_eq!(f.version(), 2);
+        assert_eq!(f.percentage(), 25);
+
+        f.set_percentage(101);
+        assert_eq!(f.invalid_fields(), vec![("percentage".to_string(), "must be at most 100")]);
+
+        let update: FlagUpdate = serde_json::from_str(r#"{"value":true,"enabled":true}"#).unwrap();
+        assert_eq!(update.applied_to(&f).percentage(), 101);
+    }
+
+    #[test]
+    fn test_filters_by_tags_and_text() {
+        let mut f = Flag::new("new-checkout", FlagValue::Bool(true), 1, true);
+        f.set_meta(&FlagMeta {
+            name: "New checkout".into(),
+            tags: vec!["payments".into(), "web".into()],
+            ..FlagMeta::default()
+        });
+
+        assert!(f.has_tags(&["payments"]));
+        assert!(!f.has_tags(&["payments", "ios"]));
+        assert!(f.matches("CHECKOUT"));
+        assert!(!f.matches("search"));
+    }
+
+    #[test]
+    fn test_updates_keep_meta_that_is_left_out() {
+        let mut f = Flag::new("f1", FlagValue::Bool(true), 1, true);
+        f.set_meta(&FlagMeta {
+            name: "Checkout".into(),
+            description: "New checkout".into(),
+            tags: vec!["web".into()],
+            maintainer: Some("team-a".into()),
+            ..FlagMeta::default()
+        });
+
+        let update: FlagUpdate = serde_json::from_str(r#"{"value":false,"enabled":true,"tags":["ios"]}"#).unwrap();
+        let updated = update.applied_to(&f);
+
+        assert_eq!(updated.key(), "f1");
+        assert_eq!(updated.value(), &FlagValue::Bool(false));
+        assert_eq!(updated.meta().name, "Checkout");
+        assert_eq!(updated.meta().description, "New checkout");
+        assert_eq!(updated.meta().tags, vec!["ios".to_string()]);
+        assert_eq!(updated.meta().maintainer, Some("team-a".to_string()));
+
+        let cleared: FlagUpdate = serde_json::from_str(r#"{"value":false,"enabled":true,"maintainer":null}"#).unwrap();
+        assert_eq!(cleared.applied_to(&f).meta().maintainer, None);
+    }
+
+    #[test]
+    fn test_reports_long_names_as_name() {
+        let mut f = Flag::new("f1", FlagValue::Bool(true), 1, true);
+        f.set_meta(&FlagMeta {
+            name: "n".repeat(MAX_TEXT + 1),
+            ..FlagMeta::default()
+        });
+
+        assert_eq!(f.invalid_fields(), vec![("name".to_string(), "must be at most 1024 bytes")]);
+    }
 }
@@ -1,6 +1,7 @@
 import React from 'react';
 import PropTypes from 'prop-types';
 import { withStyles } from 'material-ui/styles';
+import Button from 'material-ui/Button';
 import Paper from 'material-ui/Paper';
 import TextField from 'material-ui/TextField';
 
@@ -66,6 +67,11 @@ class Login extends React.Component {
     this.updateSecret = this.updateSecret.bind(this);
   }
 
+  componentDidMount() {
+    // Picks up a session established through single sign on
+    this.props.loadApps();
+  }
+
   updateKey(e) {
     this.props.updateKey(e.target.value);
   }
@@ -94,6 +100,11 @@ class Login extends React.Component {
             label="Secret"
             value={apiSecret}
             onChange={this.updateSecret} />
+          <Button
+            className={fieldClasses}
+            href="/auth/login/">
+            Sign in with SSO
+          </Button>
         </Paper>
       </div>
     );
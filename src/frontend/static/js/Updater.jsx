@@ -1,4 +1,5 @@
 import React from 'react';
+import axios from 'axios';
 
 import { connector } from './store';
 
@@ -11,6 +12,9 @@ class Updater extends React.Component {
     };
 
     this.update = this.update.bind(this);
+    this.patch = this.patch.bind(this);
+    this.remove = this.remove.bind(this);
+    this.connect = this.connect.bind(this);
   }
 
   shouldComponentUpdate(nextProps) {
@@ -23,28 +27,73 @@ class Updater extends React.Component {
 
   componentDidUpdate() {
 
-    // Close any existing connections
+    // Close any existing connections and drop pending retries
     if (this.state.stream && this.state.stream.close) {
       this.state.stream.close();
     }
 
-    let { app, env, apiKey, apiSecret, baseUrl } = this.props;
+    clearTimeout(this.retry);
+
+    let { app, env, apiKey, apiSecret } = this.props;
 
     if (app && env && apiKey && apiSecret) {
-      let auth = btoa(apiKey + ':' + apiSecret);
-      let stream = new EventSource(`${window.location.origin}${baseUrl}/stream/${app}/${env}/?auth=${auth}`);
-      stream.addEventListener('data', e => this.update(e.data));
+      this.connect();
+    }
+  }
+
+  isCurrent(props) {
+    return props.app === this.props.app && props.env === this.props.env &&
+      props.apiKey === this.props.apiKey && props.apiSecret === this.props.apiSecret;
+  }
+
+  // Streams are opened with a short lived ticket rather than credentials in
+  // the url. A closed stream is reopened with a new ticket. Failed ticket
+  // requests are retried with a growing delay, and tickets that arrive after
+  // the app or env changed are dropped.
+  connect(attempt = 0) {
+    let props = this.props;
+    let { app, env, apiKey, apiSecret, baseUrl } = props;
+    let headers = { Authorization: 'Basic ' + btoa(apiKey + ':' + apiSecret) };
+    let url = `${baseUrl}/stream/${app}/${env}/`;
+
+    axios.post(`${url}ticket/`, null, { headers }).then(resp => {
+      if (!this.isCurrent(props)) {
+        return;
+      }
+
+      let stream = new EventSource(`${window.location.origin}${url}?ticket=${encodeURIComponent(resp.data.ticket)}`);
+      stream.addEventListener('put', e => this.update(e.data));
+      stream.addEventListener('patch', e => this.patch(e.data));
+      stream.addEventListener('delete', e => this.remove(e.data));
+      stream.onerror = () => {
+        if (stream.readyState === EventSource.CLOSED && this.state.stream === stream) {
+          this.retry = setTimeout(() => this.state.stream === stream && this.connect(), 5000);
+        }
+      };
 
       this.setState({
         stream: stream
       });
-    }
+    }).catch(() => {
+      if (this.isCurrent(props)) {
+        let delay = Math.min(1000 * Math.pow(2, attempt), 30000);
+        this.retry = setTimeout(() => this.isCurrent(props) && this.connect(attempt + 1), delay);
+      }
+    });
   }
 
   update(data) {
     this.props.loadFlags(JSON.parse(data))
   }
 
+  patch(data) {
+    this.props.patchFlag(JSON.parse(data))
+  }
+
+  remove(data) {
+    this.props.removeFlag(JSON.parse(data).key)
+  }
+
   render() {
     return <div></div>;
   }
@@ -2,6 +2,7 @@ export const ADD_FLAG = "ADD_FLAG";
 export const DELETE_FLAG = "DELETE_FLAG";
 export const LOAD_APPS = "LOAD_APPS";
 export const LOAD_DATA = "LOAD_DATA";
+export const PATCH_FLAG = "PATCH_FLAG";
 export const SELECT_APP = "SELECT_APP";
 export const UPDATE_FLAG = "UPDATE_FLAG";
 
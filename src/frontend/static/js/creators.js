@@ -23,6 +23,11 @@ function newFlag(app, env, key) {
 function auth(getState) {
   let { apiKey, apiSecret } = getState();
 
+  // Browsers signed in through single sign on are authenticated by cookie
+  if (!apiKey) {
+    return {};
+  }
+
   return {
     Authorization: 'Basic ' + btoa(apiKey + ':' + apiSecret)
   };
@@ -92,6 +97,14 @@ export function loadFlags(flags) {
   return { type: actions.LOAD_DATA, payload: flags };
 }
 
+export function patchFlag(flag) {
+  return { type: actions.PATCH_FLAG, payload: flag };
+}
+
+export function removeFlag(key) {
+  return { type: actions.DELETE_FLAG, payload: key };
+}
+
 export function loadFlagsFor(app, env) {
   return function(dispatch, getState) {
     if (app && env) {
@@ -133,16 +146,13 @@ export function updateFlag(key, enabled) {
     let flag = flags.filter(f => f.key === key);
 
     if (flag.length > 0) {
-      let f = {
+      // Metadata is sent back as is so that toggling does not clear it
+      let f = Object.assign({}, flag[0], {
         app,
         env,
-        key: flag[0].key,
-        value: flag[0].value,
-        version: flag[0].version,
-        created: flag[0].created,
         updated: t(),
         enabled
-      };
+      });
 
       dispatch({ type: actions.UPDATE_FLAG, payload: f });
 
@@ -28,7 +28,7 @@ function reducer(state = initialState, action) {
       let flag = action.payload;
       let flags = state.flags.slice();
       flags.push(flag);
-      flags = flags.sort((a, b) => a.key > b.key);
+      flags = flags.sort((a, b) => a.key.localeCompare(b.key));
 
       return Object.assign(
         {},
@@ -68,6 +68,19 @@ function reducer(state = initialState, action) {
       );
     }
 
+    case actions.PATCH_FLAG: {
+      let flag = action.payload;
+      let flags = state.flags.filter(f => f.key !== flag.key);
+      flags.push(flag);
+      flags = flags.sort((a, b) => a.key.localeCompare(b.key));
+
+      return Object.assign(
+        {},
+        state,
+        { flags }
+      );
+    }
+
     case actions.SELECT_APP: {
       let { app, env } = action.payload;
 
@@ -182,6 +195,12 @@ const mapDispatchToProps = dispatch => {
     loadFlags(flags) {
       dispatch(creators.loadFlags(flags));
     },
+    patchFlag(flag) {
+      dispatch(creators.patchFlag(flag));
+    },
+    removeFlag(key) {
+      dispatch(creators.removeFlag(key));
+    },
     loadFlagsFor(app, env) {
       dispatch(creators.loadFlagsFor(app, env));
     },
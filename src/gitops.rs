@@ -0,0 +1,436 @@
+use serde_yaml;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use error::BannerError;
+use flag::{Flag, FlagMeta, FlagPath, FlagValue, FULL_ROLLOUT};
+use history::{HistoryAction, HistoryEntry};
+use store::Store;
+
+const PATH_KEY: &'static str = "paths";
+
+// Flags written by the sync are marked with this source and its history
+// entries with this actor, which is how changes made by hand are told apart
+pub const GITOPS: &'static str = "gitops";
+
+#[derive(Debug)]
+pub enum GitopsError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, String),
+    Invalid(String),
+    Store(BannerError),
+    Drift(usize),
+}
+
+impl From<BannerError> for GitopsError {
+    fn from(err: BannerError) -> GitopsError {
+        GitopsError::Store(err)
+    }
+}
+
+impl fmt::Display for GitopsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            &GitopsError::Io(ref path, ref err) => write!(f, "Failed to read {}: {}", path.display(), err),
+            &GitopsError::Parse(ref path, ref err) => write!(f, "Failed to parse {}: {}", path.display(), err),
+            &GitopsError::Invalid(ref reason) => write!(f, "Invalid configuration: {}", reason),
+            &GitopsError::Store(ref err) => write!(f, "{}", err),
+            &GitopsError::Drift(count) => write!(
+                f,
+                "{} flags were changed outside of the repository, rerun with --allow-drift to overwrite them",
+                count
+            ),
+        }
+    }
+}
+
+fn enabled_default() -> bool {
+    true
+}
+
+fn percentage_default() -> u8 {
+    FULL_ROLLOUT
+}
+
+// A flag as it is written in the repository
+#[derive(Debug, Clone, Deserialize)]
+pub struct FlagSpec {
+    pub value: FlagValue,
+    #[serde(default = "enabled_default")]
+    pub enabled: bool,
+    #[serde(default = "percentage_default")]
+    pub percentage: u8,
+    #[serde(flatten)]
+    pub meta: FlagMeta,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EnvSpec {
+    #[serde(default)]
+    pub flags: BTreeMap<String, FlagSpec>,
+}
+
+// One file describes one app. Flags are only switched on or off, so there
+// is nothing like targeting rules to describe yet.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AppSpec {
+    pub app: String,
+    #[serde(default)]
+    pub owner: Option<String>,
+    #[serde(default)]
+    pub environments: BTreeMap<String, EnvSpec>,
+}
+
+// The flags every managed path should have
+#[derive(Debug, Clone, Default)]
+pub struct Desired {
+    pub apps: Vec<(String, String)>,
+    pub paths: BTreeMap<String, (FlagPath, BTreeMap<String, Flag>)>,
+}
+
+fn is_yaml(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext == "yaml" || ext == "yml")
+        .unwrap_or(false)
+}
+
+fn read_dir(dir: &Path) -> Result<Vec<PathBuf>, GitopsError> {
+    let mut files = vec![];
+
+    for entry in fs::read_dir(dir).map_err(|err| GitopsError::Io(dir.to_path_buf(), err))? {
+        let path = entry
+            .map_err(|err| GitopsError::Io(dir.to_path_buf(), err))?
+            .path();
+
+        if path.is_dir() {
+            files.extend(read_dir(&path)?);
+        } else if is_yaml(&path) {
+            files.push(path);
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+// Reads every YAML file below a directory. Apps without an owner in their
+// file belong to the default owner.
+pub fn load(dir: &Path, default_owner: Option<&str>) -> Result<Desired, GitopsError> {
+    let mut desired = Desired::default();
+
+    for file in read_dir(dir)?.iter() {
+        let text = fs::read_to_string(file).map_err(|err| GitopsError::Io(file.clone(), err))?;
+        let spec: AppSpec = serde_yaml::from_str(&text).map_err(|err| GitopsError::Parse(file.clone(), err.to_string()))?;
+        let invalid = |reason: String| GitopsError::Invalid(format!("{}: {}", file.display(), reason));
+
+        let owner = spec.owner
+            .clone()
+            .or(default_owner.map(|owner| owner.to_string()))
+            .ok_or(invalid("no owner given in the file or with --owner".to_string()))?;
+
+        if desired.apps.contains(&(owner.clone(), spec.app.clone())) {
+            return Err(invalid(format!("app {} is described more than once", spec.app)));
+        }
+
+        desired.apps.push((owner.clone(), spec.app.clone()));
+
+        for (env, env_spec) in spec.environments.iter() {
+            let path = FlagPath::new(owner.as_str(), spec.app.as_str(), env.as_str());
+            let mut flags = BTreeMap::new();
+
+            for (key, flag_spec) in env_spec.flags.iter() {
+                let mut flag = Flag::new(key.as_str(), flag_spec.value.clone(), 1, flag_spec.enabled)
+                    .with_percentage(flag_spec.percentage);
+                let mut meta = flag_spec.meta.clone();
+                meta.source = Some(GITOPS.to_string());
+                flag.set_meta(&meta);
+
+                if let Some(&(ref field, message)) = flag.invalid_fields().first() {
+                    return Err(invalid(format!("{}/{}: {} {}", env, key, field, message)));
+                }
+
+                flags.insert(key.clone(), flag);
+            }
+
+            desired.paths.insert(path.as_ref().to_string(), (path, flags));
+        }
+    }
+
+    Ok(desired)
+}
+
+#[derive(Debug, Clone)]
+pub enum Op {
+    CreatePath(FlagPath),
+    Create(FlagPath, Flag),
+    Update {
+        path: FlagPath,
+        before: Flag,
+        after: Flag,
+        drifted: bool,
+    },
+    // A flag in a managed path that the repository does not describe. It is
+    // deleted when pruning and only reported otherwise.
+    Unlisted(FlagPath, Flag),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Plan {
+    pub ops: Vec<Op>,
+}
+
+impl Plan {
+    pub fn has_changes(&self, prune: bool) -> bool {
+        self.ops.iter().any(|op| match op {
+            &Op::Unlisted(..) => prune,
+            _ => true,
+        })
+    }
+
+    pub fn drifted(&self) -> usize {
+        self.ops
+            .iter()
+            .filter(|op| match op {
+                &&Op::Update { drifted, .. } => drifted,
+                _ => false,
+            })
+            .count()
+    }
+}
+
+// The actor of the latest change to each flag of a path
+fn last_actors<H>(history: &H, path: &FlagPath) -> Result<HashMap<String, String>, H::Error>
+where
+    H: Store<String, HistoryEntry>,
+{
+    let mut latest: HashMap<String, HistoryEntry> = HashMap::new();
+
+    for (_, entry) in history.get_all(&HistoryEntry::store_path(path))?.into_iter() {
+        let newer = latest.get(&entry.key).map(|last| entry.id > last.id).unwrap_or(true);
+
+        if newer {
+            latest.insert(entry.key.clone(), entry);
+        }
+    }
+
+    Ok(latest.into_iter().map(|(key, entry)| (key, entry.actor)).collect())
+}
+
+// Compares the repository with the live stores. A flag drifted when it was
+// last written by the sync and has been changed by someone else since.
+pub fn plan<F, P, H>(desired: &Desired, flags: &F, paths: &P, history: &H) -> Result<Plan, BannerError>
+where
+    F: Store<FlagPath, Flag, Error = BannerError>,
+    P: Store<String, FlagPath, Error = BannerError>,
+    H: Store<String, HistoryEntry, Error = BannerError>,
+{
+    let live_paths = paths.get_all(&PATH_KEY.to_string())?;
+    let mut ops = vec![];
+
+    for &(ref path, ref wanted) in desired.paths.values() {
+        if !live_paths.contains_key(path.as_ref()) {
+            ops.push(Op::CreatePath(path.clone()));
+        }
+
+        let live = flags.get_all(path)?;
+        let actors = last_actors(history, path)?;
+
+        for (key, flag) in wanted.iter() {
+            match live.get(key) {
+                None => ops.push(Op::Create(path.clone(), flag.clone())),
+                Some(existing) => {
+                    let mut after = existing.clone();
+                    after.update_from(flag, false);
+
+                    let mut meta = after.meta().clone();
+                    meta.source = Some(GITOPS.to_string());
+                    after.set_meta(&meta);
+
+                    if !after.same_as(existing) {
+                        let managed = existing.meta().source.as_ref().map(|s| s == GITOPS).unwrap_or(false);
+                        let by_hand = actors.get(key).map(|actor| actor != GITOPS).unwrap_or(false);
+
+                        ops.push(Op::Update {
+                            path: path.clone(),
+                            before: existing.clone(),
+                            after: after,
+                            drifted: managed && by_hand,
+                        });
+                    }
+                }
+            }
+        }
+
+        let mut unlisted = live.values()
+            .filter(|flag| !wanted.contains_key(flag.key()))
+            .cloned()
+            .collect::<Vec<Flag>>();
+        unlisted.sort_by(|a, b| a.key().cmp(b.key()));
+
+        ops.extend(unlisted.into_iter().map(|flag| Op::Unlisted(path.clone(), flag)));
+    }
+
+    Ok(Plan { ops: ops })
+}
+
+fn describe_path(path: &FlagPath) -> String {
+    [path.app.as_str(), "/", path.env.as_str()].concat()
+}
+
+pub fn render(plan: &Plan, prune: bool) -> String {
+    let mut out = String::new();
+
+    for op in plan.ops.iter() {
+        let _ = match op {
+            &Op::CreatePath(ref path) => writeln!(out, "+ {} (environment)", describe_path(path)),
+            &Op::Create(ref path, ref flag) => writeln!(out, "+ {}/{}", describe_path(path), flag.key()),
+            &Op::Update { ref path, ref after, drifted, .. } => writeln!(
+                out,
+                "~ {}/{}{}",
+                describe_path(path),
+                after.key(),
+                if drifted { " (changed outside of the repository)" } else { "" }
+            ),
+            &Op::Unlisted(ref path, ref flag) if prune => writeln!(out, "- {}/{}", describe_path(path), flag.key()),
+            &Op::Unlisted(ref path, ref flag) => {
+                writeln!(out, "? {}/{} (not in the repository)", describe_path(path), flag.key())
+            }
+        };
+    }
+
+    if !plan.has_changes(prune) {
+        out.push_str("No changes\n");
+    }
+
+    out
+}
+
+// Applies a plan. Drifted flags are only overwritten when allowed, so that
+// a scheduled run does not silently undo a change made during an incident.
+pub fn apply<F, P, H>(plan: &Plan, flags: &F, paths: &P, history: &H, prune: bool, allow_drift: bool) -> Result<(), GitopsError>
+where
+    F: Store<FlagPath, Flag, Error = BannerError>,
+    P: Store<String, FlagPath, Error = BannerError>,
+    H: Store<String, HistoryEntry, Error = BannerError>,
+{
+    if plan.drifted() > 0 && !allow_drift {
+        return Err(GitopsError::Drift(plan.drifted()));
+    }
+
+    for op in plan.ops.iter() {
+        let (path, key, action, flag) = match op {
+            &Op::CreatePath(ref path) => {
+                paths.upsert(&PATH_KEY.to_string(), path.as_ref(), path)?;
+                continue;
+            }
+            &Op::Create(ref path, ref flag) => {
+                flags.upsert(path, flag.key(), flag)?;
+                (path, flag.key(), HistoryAction::Created, Some(flag.clone()))
+            }
+            &Op::Update { ref path, ref after, .. } => {
+                flags.upsert(path, after.key(), after)?;
+                (path, after.key(), HistoryAction::Updated, Some(after.clone()))
+            }
+            &Op::Unlisted(ref path, ref flag) if prune => {
+                flags.delete(path, flag.key())?;
+                (path, flag.key(), HistoryAction::Deleted, None)
+            }
+            &Op::Unlisted(..) => continue,
+        };
+
+        let entry = HistoryEntry::new(key, GITOPS, action, flag);
+
+        if let Err(err) = history.upsert(&HistoryEntry::store_path(path), &entry.id.clone(), &entry) {
+            error!("Failed to record history of {} in {}: {}", key, path.as_ref(), err);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "mem-backend")]
+    use storage::mem::MemStore;
+
+    fn desired(yaml: &str) -> Desired {
+        let spec: AppSpec = serde_yaml::from_str(yaml).unwrap();
+        let dir = ::std::env::temp_dir().join(format!("masquerade-gitops-{}", spec.app));
+        let _ = fs::create_dir_all(&dir);
+        let _ = fs::write(dir.join("app.yaml"), yaml);
+
+        let desired = load(&dir, Some("owner")).unwrap();
+        let _ = fs::remove_dir_all(&dir);
+        desired
+    }
+
+    #[test]
+    fn test_loads_flags_marked_as_synced() {
+        let desired = desired("app: loads\nenvironments:\n  prod:\n    flags:\n      f1: {value: true, enabled: false}\n");
+        let &(ref path, ref flags) = desired.paths.values().next().unwrap();
+        let flag = flags.get("f1").unwrap();
+
+        assert_eq!(path.as_ref(), FlagPath::new("owner", "loads", "prod").as_ref());
+        assert_eq!(flag.meta().source, Some(GITOPS.to_string()));
+        assert_eq!(flag.eval(), None);
+    }
+
+    #[cfg(feature = "mem-backend")]
+    #[test]
+    fn test_plans_and_applies() {
+        let desired = desired(
+            "app: plans\nenvironments:\n  prod:\n    flags:\n      f1: {value: true}\n      f2: {value: false, enabled: false}\n",
+        );
+        let flags = MemStore::new();
+        let paths = MemStore::new();
+        let history = MemStore::new();
+
+        let path = FlagPath::new("owner", "plans", "prod");
+        let _ = flags.upsert(&path, "f2", &Flag::new("f2", FlagValue::Bool(true), 1, true));
+        let _ = flags.upsert(&path, "f3", &Flag::new("f3", FlagValue::Bool(true), 1, true));
+
+        let first = plan(&desired, &flags, &paths, &history).unwrap();
+        assert_eq!(first.ops.len(), 4);
+        assert_eq!(first.drifted(), 0);
+
+        apply(&first, &flags, &paths, &history, true, false).unwrap();
+
+        let second = plan(&desired, &flags, &paths, &history).unwrap();
+        assert!(!second.has_changes(true));
+        assert_eq!(flags.get(&path, "f3").unwrap(), None);
+    }
+
+    #[cfg(feature = "mem-backend")]
+    #[test]
+    fn test_detects_drift() {
+        let desired = desired("app: drift\nenvironments:\n  prod:\n    flags:\n      f1: {value: true}\n");
+        let flags = MemStore::new();
+        let paths = MemStore::new();
+        let history = MemStore::new();
+
+        apply(&plan(&desired, &flags, &paths, &history).unwrap(), &flags, &paths, &history, false, false).unwrap();
+
+        // Someone turns the flag off by hand
+        let path = FlagPath::new("owner", "drift", "prod");
+        let mut flag = flags.get(&path, "f1").unwrap().unwrap();
+        flag.toggle(false);
+        let _ = flags.upsert(&path, "f1", &flag);
+        let entry = HistoryEntry::new("f1", "someone", HistoryAction::Updated, Some(flag));
+        let _ = history.upsert(&HistoryEntry::store_path(&path), &entry.id.clone(), &entry);
+
+        let drifted = plan(&desired, &flags, &paths, &history).unwrap();
+        assert_eq!(drifted.drifted(), 1);
+        assert!(apply(&drifted, &flags, &paths, &history, false, false).is_err());
+        assert!(apply(&drifted, &flags, &paths, &history, false, true).is_ok());
+    }
+}
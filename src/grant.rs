@@ -0,0 +1,174 @@
+#[cfg(feature = "dynamo-backend")]
+use rusoto_dynamodb::AttributeValue;
+
+#[cfg(feature = "dynamo-backend")]
+use std::collections::HashMap;
+
+#[cfg(feature = "dynamo-backend")]
+use error::BannerError;
+#[cfg(feature = "dynamo-backend")]
+use storage::dynamo::{DynamoError, FromAttrMap};
+
+// Marks a grant that applies to every environment of an app
+const ALL_ENVS: &'static str = "*";
+
+// Roles are ordered so that each one includes everything the roles before
+// it may do.
+//
+// * viewer    - read flags and follow their streams
+// * editor    - create, change and delete flags
+// * env_admin - manage the SDK keys and grants of an environment
+// * owner     - create environments and grant any role across the app
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Viewer,
+    Editor,
+    EnvAdmin,
+    Owner,
+}
+
+impl Role {
+    pub fn name(&self) -> &'static str {
+        match self {
+            &Role::Viewer => "viewer",
+            &Role::Editor => "editor",
+            &Role::EnvAdmin => "env_admin",
+            &Role::Owner => "owner",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Role> {
+        match name {
+            "viewer" => Some(Role::Viewer),
+            "editor" => Some(Role::Editor),
+            "env_admin" => Some(Role::EnvAdmin),
+            "owner" => Some(Role::Owner),
+            _ => None,
+        }
+    }
+}
+
+// A role given to a user on an app owned by someone else. Grants without an
+// env apply to every environment of the app.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Grant {
+    pub user: String,
+    pub owner: String,
+    pub app: String,
+    pub env: Option<String>,
+    pub role: Role,
+}
+
+impl Grant {
+    pub fn new(user: String, owner: String, app: String, env: Option<String>, role: Role) -> Grant {
+        Grant {
+            user: user,
+            owner: owner,
+            app: app,
+            env: env,
+            role: role,
+        }
+    }
+
+    // All grants of an app are stored under the same path so that they can
+    // be listed together
+    pub fn store_path(owner: &str, app: &str) -> String {
+        [owner, ":", app].concat()
+    }
+
+    pub fn store_key(user: &str, env: Option<&str>) -> String {
+        [user, ":", env.unwrap_or(ALL_ENVS)].concat()
+    }
+
+    pub fn path(&self) -> String {
+        Grant::store_path(&self.owner, &self.app)
+    }
+
+    pub fn key(&self) -> String {
+        Grant::store_key(&self.user, self.env.as_ref().map(|env| env.as_str()))
+    }
+
+    pub fn applies_to(&self, env: &str) -> bool {
+        self.env.as_ref().map(|e| e == env).unwrap_or(true)
+    }
+}
+
+// Backend Impls
+
+redis_json!(Grant);
+
+#[cfg(feature = "dynamo-backend")]
+impl Into<HashMap<String, AttributeValue>> for Grant {
+    fn into(self) -> HashMap<String, AttributeValue> {
+        let mut user_attr = AttributeValue::default();
+        user_attr.s = Some(self.user);
+
+        let mut owner_attr = AttributeValue::default();
+        owner_attr.s = Some(self.owner);
+
+        let mut app_attr = AttributeValue::default();
+        app_attr.s = Some(self.app);
+
+        let mut env_attr = AttributeValue::default();
+        env_attr.s = Some(self.env.unwrap_or(ALL_ENVS.to_string()));
+
+        let mut role_attr = AttributeValue::default();
+        role_attr.s = Some(self.role.name().to_string());
+
+        let mut map = HashMap::new();
+        map.insert("user".into(), user_attr);
+        map.insert("owner".into(), owner_attr);
+        map.insert("app".into(), app_attr);
+        map.insert("env".into(), env_attr);
+        map.insert("role".into(), role_attr);
+
+        map
+    }
+}
+
+#[cfg(feature = "dynamo-backend")]
+impl FromAttrMap<Grant> for Grant {
+    type Error = BannerError;
+
+    fn from_attr_map(mut map: HashMap<String, AttributeValue>) -> Result<Grant, BannerError> {
+        let user = map.remove("user").and_then(|user_data| user_data.s);
+        let owner = map.remove("owner").and_then(|owner_data| owner_data.s);
+        let app = map.remove("app").and_then(|app_data| app_data.s);
+        let env = map.remove("env")
+            .and_then(|env_data| env_data.s)
+            .map(|env| if env == ALL_ENVS { None } else { Some(env) });
+        let role = map.remove("role")
+            .and_then(|role_data| role_data.s)
+            .and_then(|role| Role::from_name(&role));
+
+        if let (Some(u), Some(o), Some(a), Some(e), Some(r)) = (user, owner, app, env, role) {
+            Ok(Grant::new(u, o, a, e, r))
+        } else {
+            Err(DynamoError::FailedToParseResponse.into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roles_are_ordered() {
+        assert!(Role::Viewer < Role::Editor);
+        assert!(Role::Editor < Role::EnvAdmin);
+        assert!(Role::EnvAdmin < Role::Owner);
+    }
+
+    #[test]
+    fn test_app_grants_apply_to_all_envs() {
+        let app = Grant::new("u".into(), "o".into(), "app".into(), None, Role::Editor);
+        let env = Grant::new("u".into(), "o".into(), "app".into(), Some("staging".into()), Role::Editor);
+
+        assert!(app.applies_to("prod"));
+        assert!(env.applies_to("staging"));
+        assert!(!env.applies_to("prod"));
+        assert!(app.key() != env.key());
+    }
+}
@@ -3,6 +3,7 @@ use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
 use std::time::{Duration, Instant};
 
 use error::BannerError;
+use metrics;
 
 #[derive(Clone, Debug)]
 pub struct HashCache<T> {
@@ -43,6 +44,12 @@ impl<T> HashCache<T> {
         })
     }
 
+    // Number of entries held, including ones that have expired but have not
+    // been replaced yet
+    pub fn len(&self) -> usize {
+        self.reader().map(|reader| reader.len()).unwrap_or(0)
+    }
+
     fn ignore_dur(&self) -> bool {
         self.duration.as_secs() as f64 + self.duration.subsec_nanos() as f64 == 0.0
     }
@@ -53,7 +60,7 @@ impl<T: Clone> HashCache<T> {
         self.reader().map(|reader| {
             let entry = reader.get(key.into());
 
-            match entry {
+            let found = match entry {
                 Some(&(ref val, created)) => {
                     if self.ignore_dur() || created.elapsed() <= self.duration {
                         Some(val.clone())
@@ -62,7 +69,15 @@ impl<T: Clone> HashCache<T> {
                     }
                 }
                 _ => None,
+            };
+
+            if found.is_some() {
+                metrics::inc(metrics::CACHE_HITS, &[]);
+            } else {
+                metrics::inc(metrics::CACHE_MISSES, &[]);
             }
+
+            found
         })
     }
 
@@ -0,0 +1,215 @@
+#[cfg(feature = "mongo-backend")]
+use bson;
+#[cfg(feature = "dynamo-backend")]
+use rusoto_dynamodb::AttributeValue;
+#[cfg(feature = "dynamo-backend")]
+use serde_json;
+use uuid::Uuid;
+
+#[cfg(feature = "dynamo-backend")]
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[cfg(feature = "dynamo-backend")]
+use error::BannerError;
+use flag::{Flag, FlagPath};
+#[cfg(feature = "dynamo-backend")]
+use storage::dynamo::{DynamoError, FromAttrMap};
+use store::Store;
+use util::{current_millis, current_time, env_or};
+
+const HISTORY_PREFIX: &'static str = "history:";
+const DAY: u64 = 24 * 60 * 60;
+
+// Tells apart entries made in the same millisecond by this process
+static SEQUENCE: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryAction {
+    Created,
+    Updated,
+    Deleted,
+    Promoted,
+    Imported,
+}
+
+// A change made to a flag, along with who made it and the flag as it was
+// afterwards. Deleted flags have no state.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub id: String,
+    pub key: String,
+    #[cfg_attr(feature = "mongo-backend", serde(with = "bson::compat::u2f"))]
+    pub at: u64,
+    pub actor: String,
+    pub action: HistoryAction,
+    pub flag: Option<Flag>,
+    pub note: Option<String>,
+}
+
+impl HistoryEntry {
+    pub fn new<S, T>(key: S, actor: T, action: HistoryAction, flag: Option<Flag>) -> HistoryEntry
+    where
+        S: Into<String>,
+        T: Into<String>,
+    {
+        let seq = SEQUENCE.fetch_add(1, Ordering::SeqCst) % 1_000_000;
+
+        HistoryEntry {
+            // Ids start with the time and a sequence so that they sort in
+            // the order they were made
+            id: format!("{:015}-{:06}-{}", current_millis(), seq, Uuid::new_v4()),
+            key: key.into(),
+            at: current_time(),
+            actor: actor.into(),
+            action: action,
+            flag: flag,
+            note: None,
+        }
+    }
+
+    pub fn with_note<S: Into<String>>(mut self, note: S) -> HistoryEntry {
+        self.note = Some(note.into());
+        self
+    }
+
+    // Entries of all flags of a path are kept together
+    pub fn store_path(path: &FlagPath) -> String {
+        [HISTORY_PREFIX, path.as_ref()].concat()
+    }
+}
+
+// How much history is kept for each path. Entries past the age, and the
+// oldest ones past the count, are removed by the pruner.
+#[derive(Debug, Clone)]
+pub struct Retention {
+    pub max_age: u64,
+    pub max_entries: usize,
+}
+
+impl Retention {
+    pub fn from_env() -> Retention {
+        Retention {
+            max_age: env_or("HISTORY_RETENTION_DAYS", 365u64).saturating_mul(DAY),
+            max_entries: env_or("HISTORY_MAX_ENTRIES", 1000usize).max(1),
+        }
+    }
+
+    // The ids of the entries that are no longer kept
+    pub fn expired(&self, entries: &[HistoryEntry], now: u64) -> Vec<String> {
+        let cutoff = now.saturating_sub(self.max_age);
+        let mut sorted = entries.iter().collect::<Vec<&HistoryEntry>>();
+
+        // Newest first
+        sorted.sort_by(|a, b| b.id.cmp(&a.id));
+
+        sorted
+            .into_iter()
+            .enumerate()
+            .filter(|&(i, entry)| i >= self.max_entries || entry.at < cutoff)
+            .map(|(_, entry)| entry.id.clone())
+            .collect()
+    }
+}
+
+// Removes the entries of a path that are no longer kept, returning how many
+pub fn prune<H>(history: &H, path: &FlagPath, retention: &Retention, now: u64) -> Result<usize, H::Error>
+where
+    H: Store<String, HistoryEntry> + ?Sized,
+{
+    let store_path = HistoryEntry::store_path(path);
+    let entries = history
+        .get_all(&store_path)?
+        .into_iter()
+        .map(|(_, entry)| entry)
+        .collect::<Vec<HistoryEntry>>();
+    let expired = retention.expired(&entries, now);
+
+    for id in expired.iter() {
+        history.delete(&store_path, id)?;
+    }
+
+    Ok(expired.len())
+}
+
+// Backend Impls
+
+redis_json!(HistoryEntry);
+
+// The entry is stored whole as it is only ever read back whole
+#[cfg(feature = "dynamo-backend")]
+impl Into<HashMap<String, AttributeValue>> for HistoryEntry {
+    fn into(self) -> HashMap<String, AttributeValue> {
+        let mut id_attr = AttributeValue::default();
+        id_attr.s = Some(self.id.clone());
+
+        let mut entry_attr = AttributeValue::default();
+        entry_attr.s = serde_json::to_string(&self).ok();
+
+        let mut map = HashMap::new();
+        map.insert("id".into(), id_attr);
+        map.insert("entry".into(), entry_attr);
+
+        map
+    }
+}
+
+#[cfg(feature = "dynamo-backend")]
+impl FromAttrMap<HistoryEntry> for HistoryEntry {
+    type Error = BannerError;
+
+    fn from_attr_map(mut map: HashMap<String, AttributeValue>) -> Result<HistoryEntry, BannerError> {
+        map.remove("entry")
+            .and_then(|entry_data| entry_data.s)
+            .and_then(|entry| serde_json::from_str(&entry).ok())
+            .ok_or(DynamoError::FailedToParseResponse.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use flag::FlagValue;
+
+    #[test]
+    fn test_ids_sort_by_time() {
+        let first = HistoryEntry::new("f1", "user", HistoryAction::Created, None);
+        let second = HistoryEntry::new("f1", "user", HistoryAction::Deleted, None);
+        let third = HistoryEntry::new("f1", "user", HistoryAction::Created, None);
+
+        // Made within the same second, and likely the same millisecond
+        assert!(first.id < second.id);
+        assert!(second.id < third.id);
+    }
+
+    #[test]
+    fn test_expires_old_and_excess_entries() {
+        let retention = Retention {
+            max_age: 100,
+            max_entries: 2,
+        };
+        let mut entries = (0..4)
+            .map(|_| HistoryEntry::new("f1", "user", HistoryAction::Updated, None))
+            .collect::<Vec<HistoryEntry>>();
+        let now = entries[0].at;
+        entries[2].at = now - 101;
+
+        let expired = vec![entries[2].id.clone(), entries[1].id.clone(), entries[0].id.clone()];
+        assert_eq!(retention.expired(&entries, now), expired);
+
+        entries.truncate(2);
+        assert!(retention.expired(&entries, now).is_empty());
+    }
+
+    #[test]
+    fn test_keeps_flag_state() {
+        let flag = Flag::new("f1", FlagValue::Bool(true), 1, true);
+        let entry = HistoryEntry::new("f1", "user", HistoryAction::Updated, Some(flag.clone()))
+            .with_note("from staging");
+
+        assert_eq!(entry.flag, Some(flag));
+        assert_eq!(entry.note, Some("from staging".to_string()));
+    }
+}
@@ -0,0 +1,218 @@
+#[cfg(feature = "dynamo-backend")]
+use rusoto_dynamodb::AttributeValue;
+
+#[cfg(feature = "dynamo-backend")]
+use std::collections::HashMap;
+use std::env;
+use std::net::IpAddr;
+
+#[cfg(feature = "dynamo-backend")]
+use error::BannerError;
+#[cfg(feature = "dynamo-backend")]
+use storage::dynamo::{DynamoError, FromAttrMap};
+use util::env_or;
+
+#[derive(Debug, Clone)]
+pub struct LockoutConfig {
+    pub max_failures: u32,
+    pub base_lockout: u64,
+    pub max_lockout: u64,
+    pub window: u64,
+    // Behind a proxy every login comes from the proxy's address, so the
+    // client's address is read from this header instead when it is set
+    pub ip_header: Option<String>,
+}
+
+impl LockoutConfig {
+    pub fn from_env() -> LockoutConfig {
+        LockoutConfig {
+            max_failures: env_or("LOGIN_MAX_FAILURES", 5),
+            base_lockout: env_or("LOGIN_LOCKOUT_SECS", 30),
+            max_lockout: env_or("LOGIN_LOCKOUT_MAX_SECS", 3600),
+            window: env_or("LOGIN_FAILURE_WINDOW_SECS", 900),
+            ip_header: env::var("LOGIN_CLIENT_IP_HEADER").ok().filter(|header| !header.is_empty()),
+        }
+    }
+}
+
+// The address a trusted proxy put in a forwarded header. Proxies append the
+// address they saw to any list the client sent, so only the last entry can be
+// trusted.
+pub fn forwarded_ip(value: &str) -> Option<IpAddr> {
+    value
+        .rsplit(',')
+        .next()
+        .and_then(|addr| addr.trim().parse::<IpAddr>().ok())
+}
+
+// Failed logins for a single key or address. Once the allowed number of
+// failures is reached every further failure locks it out for twice as long
+// as the one before, up to the configured maximum. Failures are forgotten
+// after a quiet window.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Attempts {
+    pub id: String,
+    pub failures: u32,
+    pub last_failure: u64,
+    pub locked_until: u64,
+}
+
+impl Attempts {
+    pub fn new<S: Into<String>>(id: S) -> Attempts {
+        Attempts {
+            id: id.into(),
+            failures: 0,
+            last_failure: 0,
+            locked_until: 0,
+        }
+    }
+
+    pub fn is_locked(&self, now: u64) -> bool {
+        self.locked_until > now
+    }
+
+    pub fn record_failure(&mut self, now: u64, config: &LockoutConfig) {
+        if self.last_failure + config.window < now && !self.is_locked(now) {
+            self.failures = 0;
+        }
+
+        self.failures = self.failures.saturating_add(1);
+        self.last_failure = now;
+
+        if self.failures >= config.max_failures {
+            let doublings = ::std::cmp::min(self.failures - config.max_failures, 32);
+            let lockout = config
+                .base_lockout
+                .checked_shl(doublings)
+                .unwrap_or(config.max_lockout);
+
+            self.locked_until = now + ::std::cmp::min(lockout, config.max_lockout);
+        }
+    }
+}
+
+// Backend Impls
+
+redis_json!(Attempts);
+
+#[cfg(feature = "dynamo-backend")]
+impl Into<HashMap<String, AttributeValue>> for Attempts {
+    fn into(self) -> HashMap<String, AttributeValue> {
+        let mut id_attr = AttributeValue::default();
+        id_attr.s = Some(self.id);
+
+        let mut failures_attr = AttributeValue::default();
+        failures_attr.n = Some(self.failures.to_string());
+
+        let mut last_failure_attr = AttributeValue::default();
+        last_failure_attr.n = Some(self.last_failure.to_string());
+
+        let mut locked_until_attr = AttributeValue::default();
+        locked_until_attr.n = Some(self.locked_until.to_string());
+
+        let mut map = HashMap::new();
+        map.insert("id".into(), id_attr);
+        map.insert("failures".into(), failures_attr);
+        map.insert("last_failure".into(), last_failure_attr);
+        map.insert("locked_until".into(), locked_until_attr);
+
+        map
+    }
+}
+
+#[cfg(feature = "dynamo-backend")]
+fn number_attr(map: &HashMap<String, AttributeValue>, name: &str) -> Option<u64> {
+    map.get(name).and_then(|data| match data.n {
+        Some(ref n) => n.parse::<u64>().ok(),
+        None => None,
+    })
+}
+
+#[cfg(feature = "dynamo-backend")]
+impl FromAttrMap<Attempts> for Attempts {
+    type Error = BannerError;
+
+    fn from_attr_map(mut map: HashMap<String, AttributeValue>) -> Result<Attempts, BannerError> {
+        let failures = number_attr(&map, "failures");
+        let last_failure = number_attr(&map, "last_failure");
+        let locked_until = number_attr(&map, "locked_until");
+        let id = map.remove("id").and_then(|id_data| id_data.s);
+
+        if let (Some(i), Some(f), Some(l), Some(u)) = (id, failures, last_failure, locked_until) {
+            Ok(Attempts {
+                id: i,
+                failures: f as u32,
+                last_failure: l,
+                locked_until: u,
+            })
+        } else {
+            Err(DynamoError::FailedToParseResponse.into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> LockoutConfig {
+        LockoutConfig {
+            max_failures: 3,
+            base_lockout: 10,
+            max_lockout: 35,
+            window: 100,
+            ip_header: None,
+        }
+    }
+
+    #[test]
+    fn test_trusts_the_last_forwarded_address() {
+        assert_eq!(forwarded_ip("10.0.0.1"), "10.0.0.1".parse().ok());
+        assert_eq!(forwarded_ip("6.6.6.6, 10.0.0.1"), "10.0.0.1".parse().ok());
+        assert_eq!(forwarded_ip("2001:db8::1"), "2001:db8::1".parse().ok());
+        assert_eq!(forwarded_ip("6.6.6.6, unknown"), None);
+        assert_eq!(forwarded_ip(""), None);
+    }
+
+    #[test]
+    fn test_locks_after_max_failures() {
+        let mut attempts = Attempts::new("key:dev");
+        attempts.record_failure(1000, &config());
+        attempts.record_failure(1001, &config());
+
+        assert!(!attempts.is_locked(1002));
+
+        attempts.record_failure(1002, &config());
+
+        assert!(attempts.is_locked(1011));
+        assert!(!attempts.is_locked(1012));
+    }
+
+    #[test]
+    fn test_backs_off_exponentially() {
+        let mut attempts = Attempts::new("key:dev");
+
+        for _ in 0..4 {
+            attempts.record_failure(1000, &config());
+        }
+
+        assert_eq!(attempts.locked_until, 1020);
+
+        for _ in 0..4 {
+            attempts.record_failure(1000, &config());
+        }
+
+        assert_eq!(attempts.locked_until, 1035);
+    }
+
+    #[test]
+    fn test_forgets_old_failures() {
+        let mut attempts = Attempts::new("key:dev");
+        attempts.record_failure(1000, &config());
+        attempts.record_failure(1001, &config());
+        attempts.record_failure(1200, &config());
+
+        assert_eq!(attempts.failures, 1);
+        assert!(!attempts.is_locked(1200));
+    }
+}
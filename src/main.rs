@@ -13,6 +13,8 @@ extern crate http;
 #[cfg(feature = "dynamo-backend")]
 extern crate hyper;
 #[macro_use]
+extern crate lazy_static;
+#[macro_use]
 extern crate log;
 #[cfg(feature = "mongo-backend")]
 extern crate mongo_driver;
@@ -29,19 +31,38 @@ extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
+extern crate serde_yaml;
 extern crate tokio;
+extern crate untrusted;
 extern crate uuid;
 
 use std::env;
 
 use store::Store;
 
+// Declared first so that its macros can be used by the modules below
+#[macro_use]
+mod util;
+
+mod analytics;
 mod api;
+mod change_log;
 mod error;
 mod flag;
+mod gitops;
+mod grant;
 mod hash_cache;
+mod history;
+mod lockout;
+mod metrics;
+mod oidc;
+mod revocation;
+mod schedule;
+mod sdk_key;
+mod stale;
 mod storage;
 mod store;
+mod team;
 mod user;
 
 fn main() {
@@ -103,6 +124,206 @@ fn main() {
         None,
     ).unwrap();
 
+    #[cfg(feature = "dynamo-backend")]
+    let keys = storage::dynamo::DynamoStore::new("keys").unwrap();
+
+    #[cfg(feature = "mem-backend")]
+    let keys = storage::mem::MemStore::new();
+
+    #[cfg(feature = "mongo-backend")]
+    let keys = storage::mongo::MongoStore::open("0.0.0.0", 27017, "banner", "", "", None).unwrap();
+
+    #[cfg(feature = "redis-backend")]
+    let keys = storage::redis::RedisStore::open(
+        env::var("REDIS_HOST").unwrap_or("redis".to_string()),
+        6379,
+        Some("banner"),
+        None,
+    ).unwrap();
+
+    #[cfg(feature = "dynamo-backend")]
+    let grants = storage::dynamo::DynamoStore::new("grants").unwrap();
+
+    #[cfg(feature = "mem-backend")]
+    let grants = storage::mem::MemStore::new();
+
+    #[cfg(feature = "mongo-backend")]
+    let grants = storage::mongo::MongoStore::open("0.0.0.0", 27017, "banner", "", "", None).unwrap();
+
+    #[cfg(feature = "redis-backend")]
+    let grants = storage::redis::RedisStore::open(
+        env::var("REDIS_HOST").unwrap_or("redis".to_string()),
+        6379,
+        Some("banner"),
+        None,
+    ).unwrap();
+
+    #[cfg(feature = "dynamo-backend")]
+    let teams = storage::dynamo::DynamoStore::new("teams").unwrap();
+
+    #[cfg(feature = "mem-backend")]
+    let teams = storage::mem::MemStore::new();
+
+    #[cfg(feature = "mongo-backend")]
+    let teams = storage::mongo::MongoStore::open("0.0.0.0", 27017, "banner", "", "", None).unwrap();
+
+    #[cfg(feature = "redis-backend")]
+    let teams = storage::redis::RedisStore::open(
+        env::var("REDIS_HOST").unwrap_or("redis".to_string()),
+        6379,
+        Some("banner"),
+        None,
+    ).unwrap();
+
+    #[cfg(feature = "dynamo-backend")]
+    let lockouts = storage::dynamo::DynamoStore::new("lockouts").unwrap();
+
+    #[cfg(feature = "mem-backend")]
+    let lockouts = storage::mem::MemStore::new();
+
+    #[cfg(feature = "mongo-backend")]
+    let lockouts = storage::mongo::MongoStore::open("0.0.0.0", 27017, "banner", "", "", None).unwrap();
+
+    #[cfg(feature = "redis-backend")]
+    let lockouts = storage::redis::RedisStore::open(
+        env::var("REDIS_HOST").unwrap_or("redis".to_string()),
+        6379,
+        Some("banner"),
+        None,
+    ).unwrap();
+
+    #[cfg(feature = "dynamo-backend")]
+    let history = storage::dynamo::DynamoStore::new("history").unwrap();
+
+    #[cfg(feature = "mem-backend")]
+    let history = storage::mem::MemStore::new();
+
+    #[cfg(feature = "mongo-backend")]
+    let history = storage::mongo::MongoStore::open("0.0.0.0", 27017, "banner", "", "", None).unwrap();
+
+    #[cfg(feature = "redis-backend")]
+    let history = storage::redis::RedisStore::open(
+        env::var("REDIS_HOST").unwrap_or("redis".to_string()),
+        6379,
+        Some("banner"),
+        None,
+    ).unwrap();
+
+    #[cfg(feature = "dynamo-backend")]
+    let schedules = storage::dynamo::DynamoStore::new("schedules").unwrap();
+
+    #[cfg(feature = "mem-backend")]
+    let schedules = storage::mem::MemStore::new();
+
+    #[cfg(feature = "mongo-backend")]
+    let schedules = storage::mongo::MongoStore::open("0.0.0.0", 27017, "banner", "", "", None).unwrap();
+
+    #[cfg(feature = "redis-backend")]
+    let schedules = storage::redis::RedisStore::open(
+        env::var("REDIS_HOST").unwrap_or("redis".to_string()),
+        6379,
+        Some("banner"),
+        None,
+    ).unwrap();
+
+    #[cfg(feature = "dynamo-backend")]
+    let revocations = storage::dynamo::DynamoStore::new("revocations").unwrap();
+
+    #[cfg(feature = "mem-backend")]
+    let revocations = storage::mem::MemStore::new();
+
+    #[cfg(feature = "mongo-backend")]
+    let revocations = storage::mongo::MongoStore::open("0.0.0.0", 27017, "banner", "", "", None).unwrap();
+
+    #[cfg(feature = "redis-backend")]
+    let revocations = storage::redis::RedisStore::open(
+        env::var("REDIS_HOST").unwrap_or("redis".to_string()),
+        6379,
+        Some("banner"),
+        None,
+    ).unwrap();
+
+    // Evaluation counts are kept in a file when one is configured, and are
+    // otherwise lost on restart
+    let analytics_config = analytics::AnalyticsConfig::from_env();
+    let analytics: Box<analytics::AnalyticsStore> = match env::var("ANALYTICS_FILE") {
+        Ok(file) => Box::new(analytics::file::FileAnalytics::open(file, analytics_config).unwrap()),
+        Err(_) => Box::new(analytics::mem::MemAnalytics::new(analytics_config)),
+    };
+
+    // `masquerade stale [--max-age-days N] [--json]` prints the stale flag
+    // report instead of starting the server
+    let args = env::args().skip(1).collect::<Vec<String>>();
+
+    if args.first().map(|cmd| cmd == "stale").unwrap_or(false) {
+        let config = stale::StaleConfig::from_env().with_overrides(&util::cli_options(&args[1..]));
+
+        match stale::report(&flags, &apps, &analytics, &config) {
+            Ok(ref groups) if args.iter().any(|arg| arg == "--json") => {
+                println!("{}", serde_json::to_string_pretty(groups).unwrap_or_default())
+            }
+            Ok(groups) => print!("{}", stale::render(&groups)),
+            Err(err) => {
+                eprintln!("Failed to build the stale flag report: {}", err);
+                std::process::exit(1);
+            }
+        }
+
+        return;
+    }
+
+    // `masquerade gitops plan|apply <dir> [--owner ID] [--prune] [--allow-drift]`
+    // syncs flags with a directory of YAML files. Plan exits with 2 when there
+    // are changes so that CI can tell them apart from failures.
+    //
+    // The sync writes to the stores directly rather than through a server.
+    // Servers only wake the streams and long polls of their own writes, so
+    // connected clients see synced flags once they reconnect or poll again.
+    if args.first().map(|cmd| cmd == "gitops").unwrap_or(false) {
+        let prune = args.iter().any(|arg| arg == "--prune");
+        let allow_drift = args.iter().any(|arg| arg == "--allow-drift");
+        let options = util::cli_options(&args[1..]);
+
+        // The memory backend only holds what this process writes, so a sync
+        // to it would be lost as soon as the command exits
+        let in_memory = cfg!(all(
+            feature = "mem-backend",
+            not(any(feature = "mongo-backend", feature = "redis-backend"))
+        ));
+
+        let command = args.get(1).map(|cmd| cmd.as_str());
+        let result = args.get(2)
+            .filter(|_| command == Some("plan") || command == Some("apply"))
+            .ok_or(gitops::GitopsError::Invalid("usage: gitops plan|apply <dir>".to_string()))
+            .and_then(|dir| if in_memory {
+                Err(gitops::GitopsError::Invalid("gitops needs a shared backend, not the memory one".to_string()))
+            } else {
+                Ok(dir)
+            })
+            .and_then(|dir| gitops::load(std::path::Path::new(dir), options.get("owner").map(|o| o.as_str())))
+            .and_then(|desired| Ok(gitops::plan(&desired, &flags, &apps, &history)?))
+            .and_then(|plan| {
+                print!("{}", gitops::render(&plan, prune));
+
+                if command == Some("apply") {
+                    gitops::apply(&plan, &flags, &apps, &history, prune, allow_drift).map(|_| false)
+                } else {
+                    Ok(plan.has_changes(prune))
+                }
+            });
+
+        match result {
+            Ok(true) => std::process::exit(2),
+            Ok(false) => (),
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+
+        return;
+    }
+
     let flag = flag::Flag::new("f1", flag::FlagValue::Bool(true), 1, true);
 
     let u = user::User::new(
@@ -143,7 +364,7 @@ fn main() {
     let _ = flags.upsert(&a, "f1", &flag);
     let _ = users.upsert(&"users".to_string(), "dev", &u);
 
-    api::boot(flags, apps, users);
+    api::boot(flags, apps, users, keys, grants, teams, lockouts, history, schedules, revocations, analytics);
 
     // let mut entry = Mount::new();
 
@@ -0,0 +1,202 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+pub const HTTP_REQUESTS: &'static str = "masquerade_http_requests_total";
+pub const HTTP_DURATION: &'static str = "masquerade_http_request_duration_seconds";
+pub const AUTH_FAILURES: &'static str = "masquerade_auth_failures_total";
+pub const STORE_DURATION: &'static str = "masquerade_store_operation_duration_seconds";
+pub const STORE_ERRORS: &'static str = "masquerade_store_errors_total";
+pub const CACHE_HITS: &'static str = "masquerade_cache_hits_total";
+pub const CACHE_MISSES: &'static str = "masquerade_cache_misses_total";
+pub const CACHE_ENTRIES: &'static str = "masquerade_cache_entries";
+pub const STREAMS: &'static str = "masquerade_streams_active";
+pub const EVENTS: &'static str = "masquerade_stream_events_total";
+
+// Name, type and help of every metric, in the order they are rendered
+const FAMILIES: [(&'static str, &'static str, &'static str); 10] = [
+    (HTTP_REQUESTS, "counter", "Requests handled by route, method and status"),
+    (HTTP_DURATION, "histogram", "Request latency by route and method"),
+    (AUTH_FAILURES, "counter", "Rejected authentication attempts by method"),
+    (STORE_DURATION, "histogram", "Store operation latency by store, backend and operation"),
+    (STORE_ERRORS, "counter", "Failed store operations by store, backend and operation"),
+    (CACHE_HITS, "counter", "Cache lookups that found a fresh entry"),
+    (CACHE_MISSES, "counter", "Cache lookups that found no fresh entry"),
+    (CACHE_ENTRIES, "gauge", "Entries held in the caches of each store"),
+    (STREAMS, "gauge", "Open flag streams by path"),
+    (EVENTS, "counter", "Flag events pushed to clients by transport and event"),
+];
+
+const BUCKETS: [f64; 12] = [
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+type Labels = Vec<(&'static str, String)>;
+
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    buckets: [u64; 12],
+    count: u64,
+    sum: f64,
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    values: BTreeMap<(&'static str, Labels), f64>,
+    histograms: BTreeMap<(&'static str, Labels), Histogram>,
+}
+
+lazy_static! {
+    static ref REGISTRY: Mutex<Registry> = Mutex::new(Registry::default());
+}
+
+fn labels(pairs: &[(&'static str, &str)]) -> Labels {
+    pairs
+        .iter()
+        .map(|&(name, value)| (name, value.to_string()))
+        .collect()
+}
+
+pub fn seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1_000_000_000.0
+}
+
+pub fn inc(name: &'static str, pairs: &[(&'static str, &str)]) {
+    if let Ok(mut registry) = REGISTRY.lock() {
+        *registry.values.entry((name, labels(pairs))).or_insert(0.0) += 1.0;
+    }
+}
+
+pub fn observe(name: &'static str, pairs: &[(&'static str, &str)], value: f64) {
+    if let Ok(mut registry) = REGISTRY.lock() {
+        let histogram = registry
+            .histograms
+            .entry((name, labels(pairs)))
+            .or_insert(Histogram::default());
+
+        for (i, bound) in BUCKETS.iter().enumerate() {
+            if value <= *bound {
+                histogram.buckets[i] += 1;
+            }
+        }
+
+        histogram.count += 1;
+        histogram.sum += value;
+    }
+}
+
+// Replaces every series of a gauge, so that series which are gone are not
+// reported anymore
+pub fn set_gauges(name: &'static str, series: Vec<(Labels, f64)>) {
+    if let Ok(mut registry) = REGISTRY.lock() {
+        let stale = registry
+            .values
+            .keys()
+            .filter(|&&(key, _)| key == name)
+            .cloned()
+            .collect::<Vec<(&'static str, Labels)>>();
+
+        for key in stale.into_iter() {
+            registry.values.remove(&key);
+        }
+
+        for (labels, value) in series.into_iter() {
+            registry.values.insert((name, labels), value);
+        }
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn format_labels(labels: &Labels, extra: Option<(&str, String)>) -> String {
+    let mut pairs = labels
+        .iter()
+        .map(|&(name, ref value)| format!("{}=\"{}\"", name, escape(value)))
+        .collect::<Vec<String>>();
+
+    if let Some((name, value)) = extra {
+        pairs.push(format!("{}=\"{}\"", name, value));
+    }
+
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        ["{", &pairs.join(","), "}"].concat()
+    }
+}
+
+// Renders every metric in the Prometheus text exposition format
+pub fn render() -> String {
+    let registry = match REGISTRY.lock() {
+        Ok(registry) => registry,
+        Err(_) => return String::new(),
+    };
+    let mut out = String::new();
+
+    for &(name, kind, help) in FAMILIES.iter() {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} {}", name, kind);
+
+        for (&(_, ref labels), value) in registry.values.iter().filter(|&(key, _)| key.0 == name) {
+            let _ = writeln!(out, "{}{} {}", name, format_labels(labels, None), value);
+        }
+
+        for (&(_, ref labels), histogram) in registry.histograms.iter().filter(|&(key, _)| key.0 == name) {
+            for (i, bound) in BUCKETS.iter().enumerate() {
+                let le = Some(("le", bound.to_string()));
+                let _ = writeln!(out, "{}_bucket{} {}", name, format_labels(labels, le), histogram.buckets[i]);
+            }
+
+            let inf = Some(("le", "+Inf".to_string()));
+            let _ = writeln!(out, "{}_bucket{} {}", name, format_labels(labels, inf), histogram.count);
+            let _ = writeln!(out, "{}_sum{} {}", name, format_labels(labels, None), histogram.sum);
+            let _ = writeln!(out, "{}_count{} {}", name, format_labels(labels, None), histogram.count);
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_renders_counters() {
+        inc(AUTH_FAILURES, &[("method", "test_counter")]);
+        inc(AUTH_FAILURES, &[("method", "test_counter")]);
+
+        let out = render();
+
+        assert!(out.contains("# TYPE masquerade_auth_failures_total counter"));
+        assert!(out.contains("masquerade_auth_failures_total{method=\"test_counter\"} 2"));
+    }
+
+    #[test]
+    fn test_renders_histograms() {
+        observe(STORE_DURATION, &[("op", "test_histogram")], 0.02);
+
+        let out = render();
+
+        assert!(out.contains("masquerade_store_operation_duration_seconds_bucket{op=\"test_histogram\",le=\"0.01\"} 0"));
+        assert!(out.contains("masquerade_store_operation_duration_seconds_bucket{op=\"test_histogram\",le=\"0.025\"} 1"));
+        assert!(out.contains("masquerade_store_operation_duration_seconds_count{op=\"test_histogram\"} 1"));
+    }
+
+    #[test]
+    fn test_replaces_gauges() {
+        set_gauges(STREAMS, vec![(vec![("path", "a:b:c".to_string())], 2.0)]);
+        set_gauges(STREAMS, vec![(vec![("path", "d:e:f".to_string())], 1.0)]);
+
+        let out = render();
+
+        assert!(!out.contains("path=\"a:b:c\""));
+        assert!(out.contains("masquerade_streams_active{path=\"d:e:f\"} 1"));
+    }
+}
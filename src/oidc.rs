@@ -0,0 +1,328 @@
+use base64::{decode_config, URL_SAFE_NO_PAD};
+use ring::{digest, hmac, signature};
+use serde_json;
+use serde_json::Value;
+use untrusted;
+
+// ID tokens issued slightly in the future or expired a moment ago are still
+// accepted to allow for clock drift between us and the issuer
+const LEEWAY: u64 = 60;
+
+#[derive(Debug, PartialEq)]
+pub enum OidcError {
+    Malformed,
+    UnsupportedAlg,
+    UnknownKey,
+    BadSignature,
+    InvalidIssuer,
+    InvalidAudience,
+    InvalidNonce,
+    Expired,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Jwk {
+    pub kty: String,
+    pub kid: Option<String>,
+    pub n: Option<String>,
+    pub e: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct JwkSet {
+    pub keys: Vec<Jwk>,
+}
+
+#[derive(Deserialize)]
+struct Header {
+    alg: String,
+    kid: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Audience {
+    fn contains(&self, client_id: &str) -> bool {
+        match self {
+            &Audience::One(ref aud) => aud == client_id,
+            &Audience::Many(ref auds) => auds.iter().any(|aud| aud == client_id),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Registered {
+    iss: String,
+    sub: String,
+    aud: Audience,
+    exp: u64,
+    iat: Option<u64>,
+    nonce: Option<String>,
+}
+
+// The claims of a validated ID token
+#[derive(Debug, Clone)]
+pub struct IdToken {
+    pub sub: String,
+    claims: Value,
+}
+
+impl IdToken {
+    pub fn claim(&self, name: &str) -> Option<String> {
+        match self.claims.get(name) {
+            Some(&Value::String(ref val)) => Some(val.clone()),
+            Some(&Value::Number(ref val)) => Some(val.to_string()),
+            _ => None,
+        }
+    }
+
+    pub fn is_true(&self, name: &str) -> bool {
+        self.claims.get(name).and_then(|val| val.as_bool()).unwrap_or(false)
+    }
+}
+
+// What an ID token is expected to contain for the current login attempt
+pub struct Validation<'a> {
+    pub issuer: &'a str,
+    pub client_id: &'a str,
+    // Only given when the issuer advertises HS256 and a secret is configured
+    pub client_secret: Option<&'a str>,
+    pub nonce: &'a str,
+    pub now: u64,
+}
+
+fn decode_part(part: &str) -> Result<Vec<u8>, OidcError> {
+    decode_config(part, URL_SAFE_NO_PAD).or(Err(OidcError::Malformed))
+}
+
+// The modulus and exponent are big endian integers, ring rejects them when
+// they are padded with leading zeros
+fn trim_zeros(bytes: &[u8]) -> &[u8] {
+    let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+    &bytes[start..]
+}
+
+fn verify_rs256(key: &Jwk, msg: &[u8], sig: &[u8]) -> Result<(), OidcError> {
+    let (n, e) = match (&key.n, &key.e) {
+        (&Some(ref n), &Some(ref e)) if key.kty == "RSA" => (decode_part(n)?, decode_part(e)?),
+        _ => return Err(OidcError::UnknownKey),
+    };
+
+    signature::primitive::verify_rsa(
+        &signature::RSA_PKCS1_2048_8192_SHA256,
+        (
+            untrusted::Input::from(trim_zeros(&n)),
+            untrusted::Input::from(trim_zeros(&e)),
+        ),
+        untrusted::Input::from(msg),
+        untrusted::Input::from(sig),
+    ).or(Err(OidcError::BadSignature))
+}
+
+// Symmetric signatures use the client secret as the key
+fn verify_hs256(secret: &str, msg: &[u8], sig: &[u8]) -> Result<(), OidcError> {
+    let key = hmac::VerificationKey::new(&digest::SHA256, secret.as_bytes());
+    hmac::verify(&key, msg, sig).or(Err(OidcError::BadSignature))
+}
+
+fn find_key<'a>(keys: &'a JwkSet, kid: Option<&String>) -> Result<&'a Jwk, OidcError> {
+    match kid {
+        Some(kid) => keys.keys.iter().find(|key| key.kid.as_ref() == Some(kid)),
+        None if keys.keys.len() == 1 => keys.keys.first(),
+        None => None,
+    }.ok_or(OidcError::UnknownKey)
+}
+
+pub fn validate_id_token(token: &str, keys: &JwkSet, expected: &Validation) -> Result<IdToken, OidcError> {
+    let parts = token.split('.').collect::<Vec<&str>>();
+
+    if parts.len() != 3 {
+        return Err(OidcError::Malformed);
+    }
+
+    let header = serde_json::from_slice::<Header>(&decode_part(parts[0])?)
+        .or(Err(OidcError::Malformed))?;
+    let signed = [parts[0], ".", parts[1]].concat();
+    let sig = decode_part(parts[2])?;
+
+    // Only the algorithms we verify are accepted, which rules out "none".
+    // HS256 is refused unless it was enabled with a non-empty secret, as
+    // anyone could sign with an empty one.
+    match (header.alg.as_str(), expected.client_secret) {
+        ("RS256", _) => verify_rs256(find_key(keys, header.kid.as_ref())?, signed.as_bytes(), &sig)?,
+        ("HS256", Some(secret)) if !secret.is_empty() => verify_hs256(secret, signed.as_bytes(), &sig)?,
+        _ => return Err(OidcError::UnsupportedAlg),
+    }
+
+    let claims = serde_json::from_slice::<Value>(&decode_part(parts[1])?)
+        .or(Err(OidcError::Malformed))?;
+    let registered = serde_json::from_value::<Registered>(claims.clone())
+        .or(Err(OidcError::Malformed))?;
+
+    if registered.iss.trim_right_matches('/') != expected.issuer.trim_right_matches('/') {
+        return Err(OidcError::InvalidIssuer);
+    }
+
+    if !registered.aud.contains(expected.client_id) {
+        return Err(OidcError::InvalidAudience);
+    }
+
+    if registered.nonce.as_ref().map(|nonce| nonce.as_str()) != Some(expected.nonce) {
+        return Err(OidcError::InvalidNonce);
+    }
+
+    if registered.exp + LEEWAY <= expected.now
+        || registered.iat.map(|iat| iat > expected.now + LEEWAY).unwrap_or(false)
+    {
+        return Err(OidcError::Expired);
+    }
+
+    Ok(IdToken {
+        sub: registered.sub,
+        claims: claims,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::encode_config;
+
+    const SECRET: &'static str = "client-secret";
+    const NOW: u64 = 1_500_000_000;
+
+    // The claims below signed with RS256 by the key TEST_N, under the kid
+    // "test-key"
+    const RS256_TOKEN: &'static str = "eyJhbGciOiJSUzI1NiIsImtpZCI6InRlc3Qta2V5In0.\
+        eyJhdWQiOlsiZGFzaGJvYXJkIl0sImVtYWlsIjoiZGV2QGV4YW1wbGUuY29tIiwiZXhwIjoxNTAwMDAwMzAwLCJpYXQiOjE1MDAwMDAwMDAsImlzcyI6Imh0dHA6Ly9sb2NhbGhvc3Q6OTAwMC8iLCJub25jZSI6Im5vbmNlIiwic3ViIjoic3ViamVjdCJ9.\
+        IPg-fUJHIpmrme_myiVjg4UUWjO2nU8ZxSbFmCvQ6HkasJnQAUU0Pkl8so6aHxbJMchPFT3EaebUBDGRSLxYmFFnSv3p32_bZ7eb2EtQCQ_slXbfK-S-\
+        8v4OEbOIANG8CK5tfI7B2poI8GItHg-L0E4jhZAeASwLNLIPB1d_3ez2fsvvDzxs54yGdXx-UAW1IhD1LHd9gy4JTA3828AarkJXkydUbhj8wiGEWbiJr19r7\
+        vdxc6EtxqnB7jikz593ktFJUiP2Z6qu2a6GBUxZS1IlXNDlX4VGYb-ebOOX-Rp9djB6zjb6pS0wb-hEbBBn630pIaIwG4hcZCVe0liwLQ";
+    const TEST_N: &'static str = "wmmcfdQ10C-9VXnconC-kj_r0JCoHG4Ql_C0UTpoDTyRpq1YVUCg6-yC7nsYOl67VS0OG3NkUE3E3luHoDeCG9KEpcqrosqa\
+        S10Wl4S4Jhd3-KCpvJAb8KK2dE2TnI4gwcbWq0yME0yckLBH-fPrM-dYmLAZNxgZzYpZ4Zrqn_BLwoXDvBmBDeGBsSMZIsbKp_eWachwyBusIvFXgfDDx4iFE_I9\
+        EFKdlouq3ec3OZAMGp6dN7G0aawgm2DpXZXah7cZ90Figz9wGyGyS-YOli5we1s_9CE3Fw0u7AYRFkoF6zngilQ6f7q0LE54ZjzXfU2xcA8N-SvFgUCSmLMKXQ";
+    const OTHER_N: &'static str = "z-yNz-xzoddE9W8LPRW2gNXJxsrYme6QtzMJRGxtKxgBkBVJQ3SQgPCwCssvqoC7Nkt2LeFffDyEs5X9h-StrHmYolblys\
+        7mNxLRdibHCYgDYkmIAS0mG0Q5-73GTPf1ahn-GbOPaKclucd1MI-roGa6X8GOuY6wZJBeLxC5-l1To3slkGyStqbdESNPnB6DMmvusbgT8sW1hZXZNDTx7Llg0ac\
+        ci-bbRErLQc0RE8cdyvaCCGen-nZAFN_Pu0odBycwU08sNwytEb1DBY8wAghKNMK3nroyZAeg-RiiKSXlKglhex5G948kMoqwnueGoS4hsxGXjdLRF6vz5zhbKw";
+
+    fn token(alg: &str, claims: Value) -> String {
+        let header = encode_config(format!("{{\"alg\":\"{}\"}}", alg).as_bytes(), URL_SAFE_NO_PAD);
+        let body = encode_config(claims.to_string().as_bytes(), URL_SAFE_NO_PAD);
+        let signed = [header.as_str(), ".", body.as_str()].concat();
+        let key = hmac::SigningKey::new(&digest::SHA256, SECRET.as_bytes());
+        let sig = hmac::sign(&key, signed.as_bytes());
+
+        [signed.as_str(), ".", &encode_config(sig.as_ref(), URL_SAFE_NO_PAD)].concat()
+    }
+
+    fn claims() -> Value {
+        let mut claims: Value = serde_json::from_str(
+            r#"{
+                "iss": "http://localhost:9000/",
+                "sub": "subject",
+                "aud": ["dashboard"],
+                "nonce": "nonce",
+                "email": "dev@example.com"
+            }"#,
+        ).unwrap();
+        claims["exp"] = Value::from(NOW + 300);
+        claims["iat"] = Value::from(NOW);
+        claims
+    }
+
+    fn rsa_key(kid: &str, n: &str) -> Jwk {
+        Jwk {
+            kty: "RSA".to_string(),
+            kid: Some(kid.to_string()),
+            n: Some(n.to_string()),
+            e: Some("AQAB".to_string()),
+        }
+    }
+
+    fn validate_with(token: &str, keys: &JwkSet, secret: Option<&str>) -> Result<IdToken, OidcError> {
+        validate_id_token(
+            token,
+            keys,
+            &Validation {
+                issuer: "http://localhost:9000",
+                client_id: "dashboard",
+                client_secret: secret,
+                nonce: "nonce",
+                now: NOW,
+            },
+        )
+    }
+
+    fn validate(token: &str) -> Result<IdToken, OidcError> {
+        validate_with(token, &JwkSet { keys: vec![] }, Some(SECRET))
+    }
+
+    #[test]
+    fn test_accepts_valid_tokens() {
+        let id = validate(&token("HS256", claims())).unwrap();
+
+        assert_eq!(id.sub, "subject");
+        assert_eq!(id.claim("email"), Some("dev@example.com".to_string()));
+    }
+
+    #[test]
+    fn test_accepts_rs256_tokens_by_kid() {
+        let keys = JwkSet {
+            keys: vec![rsa_key("other-key", OTHER_N), rsa_key("test-key", TEST_N)],
+        };
+        let id = validate_with(RS256_TOKEN, &keys, None).unwrap();
+
+        assert_eq!(id.sub, "subject");
+        assert_eq!(id.claim("email"), Some("dev@example.com".to_string()));
+
+        let rotated = JwkSet {
+            keys: vec![rsa_key("other-key", OTHER_N)],
+        };
+        let swapped = JwkSet {
+            keys: vec![rsa_key("test-key", OTHER_N)],
+        };
+
+        assert_eq!(validate_with(RS256_TOKEN, &rotated, None).unwrap_err(), OidcError::UnknownKey);
+        assert_eq!(validate_with(RS256_TOKEN, &swapped, None).unwrap_err(), OidcError::BadSignature);
+    }
+
+    #[test]
+    fn test_refuses_hs256_without_a_secret() {
+        let keys = JwkSet { keys: vec![] };
+
+        assert_eq!(validate_with(&token("HS256", claims()), &keys, None).unwrap_err(), OidcError::UnsupportedAlg);
+        assert_eq!(validate_with(&token("HS256", claims()), &keys, Some("")).unwrap_err(), OidcError::UnsupportedAlg);
+    }
+
+    #[test]
+    fn test_rejects_bad_signatures() {
+        let mut t = token("HS256", claims());
+        t.push('A');
+
+        assert!(validate(&t).is_err());
+        assert_eq!(validate(&token("none", claims())).unwrap_err(), OidcError::UnsupportedAlg);
+        assert_eq!(validate(&token("RS256", claims())).unwrap_err(), OidcError::UnknownKey);
+    }
+
+    #[test]
+    fn test_rejects_mismatched_claims() {
+        let mut aud = claims();
+        aud["aud"] = Value::from("other");
+        let mut nonce = claims();
+        nonce["nonce"] = Value::from("replayed");
+        let mut exp = claims();
+        exp["exp"] = Value::from(NOW - LEEWAY);
+        let mut iss = claims();
+        iss["iss"] = Value::from("http://evil");
+
+        assert_eq!(validate(&token("HS256", aud)).unwrap_err(), OidcError::InvalidAudience);
+        assert_eq!(validate(&token("HS256", nonce)).unwrap_err(), OidcError::InvalidNonce);
+        assert_eq!(validate(&token("HS256", exp)).unwrap_err(), OidcError::Expired);
+        assert_eq!(validate(&token("HS256", iss)).unwrap_err(), OidcError::InvalidIssuer);
+    }
+}
@@ -0,0 +1,255 @@
+#[cfg(feature = "mongo-backend")]
+use bson;
+#[cfg(feature = "dynamo-backend")]
+use rusoto_dynamodb::AttributeValue;
+
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+use error::BannerError;
+#[cfg(feature = "dynamo-backend")]
+use storage::dynamo::{DynamoError, FromAttrMap};
+use store::Store;
+use util::current_time;
+
+// Every revocation is kept under one path until the sessions it refuses would
+// have expired anyway. A dynamo table can use `exp` as its ttl attribute.
+const REVOCATION_PATH: &'static str = "revocations";
+const USER_PREFIX: &'static str = "user:";
+
+// A session token that was ended before its expiry, by its id. Ending every
+// session of a user is stored under the user's id instead, refusing the
+// sessions issued before the given time in milliseconds.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Revocation {
+    pub jti: String,
+    #[cfg_attr(feature = "mongo-backend", serde(with = "bson::compat::u2f"))]
+    pub exp: u64,
+    #[serde(default)]
+    #[cfg_attr(feature = "mongo-backend", serde(with = "bson::compat::u2f"))]
+    pub issued_before: u64,
+}
+
+impl Revocation {
+    pub fn new<S: Into<String>>(jti: S, exp: u64) -> Revocation {
+        Revocation {
+            jti: jti.into(),
+            exp: exp,
+            issued_before: 0,
+        }
+    }
+
+    pub fn user(uuid: &str, issued_before: u64, exp: u64) -> Revocation {
+        Revocation {
+            jti: Revocation::user_key(uuid),
+            exp: exp,
+            issued_before: issued_before,
+        }
+    }
+
+    pub fn user_key(uuid: &str) -> String {
+        [USER_PREFIX, uuid].concat()
+    }
+
+    pub fn store_path() -> String {
+        REVOCATION_PATH.to_string()
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.exp <= now
+    }
+}
+
+// Whether a session, by its token id when it has one, its user and when it
+// was issued, has been revoked
+pub fn is_revoked(revocations: &HashMap<String, Revocation>, jti: Option<&str>, sub: &str, iat: u64) -> bool {
+    let token = jti.map(|jti| revocations.contains_key(jti)).unwrap_or(false);
+    let user = revocations
+        .get(&Revocation::user_key(sub))
+        .map(|revocation| iat < revocation.issued_before)
+        .unwrap_or(false);
+
+    token || user
+}
+
+// The revocations of every replica, read from the store at most once per ttl
+// so that checking a session does not read the store on every request.
+// Revocations made by this replica are added straight away.
+pub struct RevocationCache {
+    ttl: Duration,
+    entries: RwLock<(Option<Instant>, HashMap<String, Revocation>)>,
+}
+
+impl RevocationCache {
+    pub fn new(ttl: Duration) -> RevocationCache {
+        RevocationCache {
+            ttl: ttl,
+            entries: RwLock::new((None, HashMap::new())),
+        }
+    }
+
+    // Sessions are refused while the revocations can not be read
+    pub fn is_revoked<S>(&self, store: &S, jti: Option<&str>, sub: &str, iat: u64) -> bool
+    where
+        S: Store<String, Revocation, Error = BannerError> + ?Sized,
+    {
+        let cached = self.entries.read().ok().and_then(|entries| match entries.0 {
+            Some(loaded) if loaded.elapsed() < self.ttl => Some(is_revoked(&entries.1, jti, sub, iat)),
+            _ => None,
+        });
+
+        if let Some(revoked) = cached {
+            return revoked;
+        }
+
+        let now = current_time();
+
+        match store.get_all(&Revocation::store_path()) {
+            Ok(all) => {
+                let live = all.into_iter()
+                    .filter(|&(_, ref revocation)| !revocation.is_expired(now))
+                    .collect::<HashMap<String, Revocation>>();
+                let revoked = is_revoked(&live, jti, sub, iat);
+
+                if let Ok(mut entries) = self.entries.write() {
+                    *entries = (Some(Instant::now()), live);
+                }
+
+                revoked
+            }
+            Err(err) => {
+                error!("Failed to read revocations: {}", err);
+                true
+            }
+        }
+    }
+
+    pub fn insert(&self, revocation: Revocation) {
+        if let Ok(mut entries) = self.entries.write() {
+            entries.1.insert(revocation.jti.clone(), revocation);
+        }
+    }
+}
+
+// Removes the revocations of sessions that have expired anyway, returning
+// how many
+pub fn prune<S>(store: &S, now: u64) -> Result<usize, BannerError>
+where
+    S: Store<String, Revocation, Error = BannerError> + ?Sized,
+{
+    let path = Revocation::store_path();
+    let mut pruned = 0;
+
+    for (jti, revocation) in store.get_all(&path)?.into_iter() {
+        if revocation.is_expired(now) {
+            store.delete(&path, &jti)?;
+            pruned += 1;
+        }
+    }
+
+    Ok(pruned)
+}
+
+// Backend Impls
+
+redis_json!(Revocation);
+
+#[cfg(feature = "dynamo-backend")]
+impl Into<HashMap<String, AttributeValue>> for Revocation {
+    fn into(self) -> HashMap<String, AttributeValue> {
+        let mut jti_attr = AttributeValue::default();
+        jti_attr.s = Some(self.jti);
+
+        let mut exp_attr = AttributeValue::default();
+        exp_attr.n = Some(self.exp.to_string());
+
+        let mut issued_before_attr = AttributeValue::default();
+        issued_before_attr.n = Some(self.issued_before.to_string());
+
+        let mut map = HashMap::new();
+        map.insert("jti".into(), jti_attr);
+        map.insert("exp".into(), exp_attr);
+        map.insert("issued_before".into(), issued_before_attr);
+
+        map
+    }
+}
+
+#[cfg(feature = "dynamo-backend")]
+impl FromAttrMap<Revocation> for Revocation {
+    type Error = BannerError;
+
+    fn from_attr_map(mut map: HashMap<String, AttributeValue>) -> Result<Revocation, BannerError> {
+        let exp = map.get("exp")
+            .and_then(|data| data.n.as_ref())
+            .and_then(|n| n.parse::<u64>().ok());
+        let issued_before = map.get("issued_before")
+            .and_then(|data| data.n.as_ref())
+            .and_then(|n| n.parse::<u64>().ok())
+            .unwrap_or(0);
+        let jti = map.remove("jti").and_then(|jti_data| jti_data.s);
+
+        if let (Some(j), Some(e)) = (jti, exp) {
+            Ok(Revocation {
+                jti: j,
+                exp: e,
+                issued_before: issued_before,
+            })
+        } else {
+            Err(DynamoError::FailedToParseResponse.into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "mem-backend")]
+    use storage::mem::MemStore;
+
+    #[test]
+    fn test_revokes_tokens_and_earlier_user_sessions() {
+        let mut revocations = HashMap::new();
+        revocations.insert("t1".to_string(), Revocation::new("t1", 100));
+        revocations.insert(Revocation::user_key("u1"), Revocation::user("u1", 5000, 100));
+
+        assert!(is_revoked(&revocations, Some("t1"), "u2", 9000));
+        assert!(is_revoked(&revocations, Some("t2"), "u1", 4999));
+        assert!(is_revoked(&revocations, None, "u1", 4999));
+        assert!(!is_revoked(&revocations, Some("t2"), "u1", 5000));
+        assert!(!is_revoked(&revocations, None, "u2", 0));
+    }
+
+    #[test]
+    #[cfg(feature = "mem-backend")]
+    fn test_reads_the_store_once_per_ttl() {
+        let store = MemStore::new();
+        let cache = RevocationCache::new(Duration::from_secs(60));
+        let exp = current_time() + 60;
+
+        assert!(!cache.is_revoked(&store, Some("t1"), "u1", 0));
+
+        // Made by another replica, so only seen once the cache is reloaded
+        let _ = store.upsert(&Revocation::store_path(), "t1", &Revocation::new("t1", exp));
+        assert!(!cache.is_revoked(&store, Some("t1"), "u1", 0));
+
+        cache.insert(Revocation::new("t1", exp));
+        assert!(cache.is_revoked(&store, Some("t1"), "u1", 0));
+
+        let fresh = RevocationCache::new(Duration::from_secs(0));
+        assert!(fresh.is_revoked(&store, Some("t1"), "u1", 0));
+    }
+
+    #[test]
+    #[cfg(feature = "mem-backend")]
+    fn test_prunes_expired_revocations() {
+        let store = MemStore::new();
+        let _ = store.upsert(&Revocation::store_path(), "old", &Revocation::new("old", 10));
+        let _ = store.upsert(&Revocation::store_path(), "live", &Revocation::new("live", 100));
+
+        assert_eq!(prune(&store, 50).unwrap(), 1);
+        assert_eq!(store.get_all(&Revocation::store_path()).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,322 @@
+#[cfg(feature = "mongo-backend")]
+use bson;
+#[cfg(feature = "dynamo-backend")]
+use rusoto_dynamodb::AttributeValue;
+#[cfg(feature = "dynamo-backend")]
+use serde_json;
+use uuid::Uuid;
+
+#[cfg(feature = "dynamo-backend")]
+use std::collections::HashMap;
+
+#[cfg(feature = "dynamo-backend")]
+use error::BannerError;
+use flag::{Flag, FlagPath, FlagValue, FULL_ROLLOUT};
+#[cfg(feature = "dynamo-backend")]
+use storage::dynamo::{DynamoError, FromAttrMap};
+use util::current_time;
+
+// Every schedule is kept under one path so that the scheduler can find the
+// due ones with a single read. Finished ones are pruned to keep it small.
+const SCHEDULE_PATH: &'static str = "schedules";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ScheduledOp {
+    Toggle { enabled: bool },
+    SetValue { value: FlagValue },
+    SetPercentage { percentage: u8 },
+}
+
+impl ScheduledOp {
+    pub fn invalid_fields(&self) -> Vec<(&'static str, &'static str)> {
+        match self {
+            &ScheduledOp::SetPercentage { percentage } if percentage > FULL_ROLLOUT => {
+                vec![("percentage", "must be at most 100")]
+            }
+            _ => vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduleStatus {
+    Pending,
+    // Steps of a rollout that has been paused are skipped until resumed
+    Paused,
+    Applied,
+    Failed,
+    Cancelled,
+}
+
+// Where a step sits in a rollout plan
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RolloutStep {
+    pub plan: String,
+    pub step: u32,
+    pub steps: u32,
+}
+
+// The scheduler applying a schedule and until when. A claim that runs out is
+// taken over, so a scheduler that stopped midway does not hold on to it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Claim {
+    pub by: String,
+    #[cfg_attr(feature = "mongo-backend", serde(with = "bson::compat::u2f"))]
+    pub until: u64,
+}
+
+// A change to a flag that is made once its time has come
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Schedule {
+    pub id: String,
+    pub path: FlagPath,
+    pub key: String,
+    #[cfg_attr(feature = "mongo-backend", serde(with = "bson::compat::u2f"))]
+    pub at: u64,
+    pub op: ScheduledOp,
+    pub actor: String,
+    pub status: ScheduleStatus,
+    #[cfg_attr(feature = "mongo-backend", serde(with = "bson::compat::u2f"))]
+    pub created: u64,
+    #[cfg_attr(feature = "mongo-backend", serde(with = "bson::compat::u2f"))]
+    pub updated: u64,
+    pub error: Option<String>,
+    #[serde(default)]
+    pub rollout: Option<RolloutStep>,
+    #[serde(default)]
+    pub claim: Option<Claim>,
+}
+
+impl Schedule {
+    pub fn new<S, T>(path: FlagPath, key: S, at: u64, op: ScheduledOp, actor: T) -> Schedule
+    where
+        S: Into<String>,
+        T: Into<String>,
+    {
+        let now = current_time();
+
+        Schedule {
+            // Ids start with the due time so that they sort in the order
+            // they are applied
+            id: format!("{:012}-{}", at, Uuid::new_v4()),
+            path: path,
+            key: key.into(),
+            at: at,
+            op: op,
+            actor: actor.into(),
+            status: ScheduleStatus::Pending,
+            created: now,
+            updated: now,
+            error: None,
+            rollout: None,
+            claim: None,
+        }
+    }
+
+    pub fn in_plan(&self, plan: &str) -> bool {
+        self.rollout.as_ref().map(|step| step.plan == plan).unwrap_or(false)
+    }
+
+    // The history note of the change, naming the rollout step it belongs to
+    pub fn note(&self) -> String {
+        match self.rollout {
+            Some(ref step) => format!("rollout {} step {} of {}", step.plan, step.step, step.steps),
+            None => ["scheduled ", self.id.as_str()].concat(),
+        }
+    }
+
+    pub fn store_path() -> String {
+        SCHEDULE_PATH.to_string()
+    }
+
+    pub fn is_due(&self, now: u64) -> bool {
+        self.status == ScheduleStatus::Pending && self.at <= now && !self.is_claimed(now)
+    }
+
+    pub fn is_claimed(&self, now: u64) -> bool {
+        self.claim.as_ref().map(|claim| claim.until > now).unwrap_or(false)
+    }
+
+    pub fn is_claimed_by(&self, by: &str) -> bool {
+        self.claim.as_ref().map(|claim| claim.by == by).unwrap_or(false)
+    }
+
+    pub fn claim<S: Into<String>>(&mut self, by: S, until: u64) {
+        self.claim = Some(Claim {
+            by: by.into(),
+            until: until,
+        });
+    }
+
+    // Finished schedules are kept for a while so that they still show up in
+    // listings
+    pub fn is_expired(&self, now: u64, max_age: u64) -> bool {
+        let finished = self.status != ScheduleStatus::Pending && self.status != ScheduleStatus::Paused;
+        finished && self.updated.saturating_add(max_age) < now
+    }
+
+    pub fn invalid_fields(&self, now: u64) -> Vec<(String, &'static str)> {
+        let mut errors = vec![];
+
+        if self.at <= now {
+            errors.push(("at".to_string(), "must be in the future"));
+        }
+
+        for (field, message) in self.op.invalid_fields().into_iter() {
+            errors.push((["op.", field].concat(), message));
+        }
+
+        errors
+    }
+
+    // Makes the change on a copy of the stored flag
+    pub fn apply_to(&self, flag: &mut Flag) {
+        match self.op {
+            ScheduledOp::Toggle { enabled } => flag.toggle(enabled),
+            ScheduledOp::SetValue { ref value } => flag.set_value(value),
+            ScheduledOp::SetPercentage { percentage } => flag.set_percentage(percentage),
+        }
+    }
+
+    pub fn finish(&mut self, status: ScheduleStatus, error: Option<String>) {
+        self.status = status;
+        self.error = error;
+        self.updated = current_time();
+        self.claim = None;
+    }
+}
+
+// Spreads the steps of a rollout plan out from the start, one every
+// interval. Each step is a schedule of its own, so the scheduler applies
+// them like any other. None when a step would fall past the end of time.
+pub fn rollout<S, T>(path: &FlagPath, key: S, steps: &[ScheduledOp], start: u64, every: u64, actor: T) -> Option<Vec<Schedule>>
+where
+    S: Into<String>,
+    T: Into<String>,
+{
+    let key = key.into();
+    let actor = actor.into();
+    let plan = Uuid::new_v4().to_string();
+
+    steps
+        .iter()
+        .enumerate()
+        .map(|(i, op)| {
+            let at = every.checked_mul(i as u64).and_then(|offset| start.checked_add(offset))?;
+            let mut scheduled = Schedule::new(path.clone(), key.as_str(), at, op.clone(), actor.as_str());
+            scheduled.rollout = Some(RolloutStep {
+                plan: plan.clone(),
+                step: i as u32 + 1,
+                steps: steps.len() as u32,
+            });
+            Some(scheduled)
+        })
+        .collect()
+}
+
+// Backend Impls
+
+redis_json!(Schedule);
+
+#[cfg(feature = "dynamo-backend")]
+impl Into<HashMap<String, AttributeValue>> for Schedule {
+    fn into(self) -> HashMap<String, AttributeValue> {
+        let mut id_attr = AttributeValue::default();
+        id_attr.s = Some(self.id.clone());
+
+        let mut schedule_attr = AttributeValue::default();
+        schedule_attr.s = serde_json::to_string(&self).ok();
+
+        let mut map = HashMap::new();
+        map.insert("id".into(), id_attr);
+        map.insert("schedule".into(), schedule_attr);
+
+        map
+    }
+}
+
+#[cfg(feature = "dynamo-backend")]
+impl FromAttrMap<Schedule> for Schedule {
+    type Error = BannerError;
+
+    fn from_attr_map(mut map: HashMap<String, AttributeValue>) -> Result<Schedule, BannerError> {
+        map.remove("schedule")
+            .and_then(|schedule_data| schedule_data.s)
+            .and_then(|schedule| serde_json::from_str(&schedule).ok())
+            .ok_or(DynamoError::FailedToParseResponse.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule(op: ScheduledOp) -> Schedule {
+        Schedule::new(FlagPath::new("owner", "app", "env"), "f1", 100, op, "user")
+    }
+
+    #[test]
+    fn test_applies_ops() {
+        let mut flag = Flag::new("f1", FlagValue::Bool(true), 1, true);
+
+        schedule(ScheduledOp::Toggle { enabled: false }).apply_to(&mut flag);
+        assert!(!flag.is_enabled());
+
+        schedule(ScheduledOp::SetValue { value: FlagValue::Bool(false) }).apply_to(&mut flag);
+        assert_eq!(flag.value(), &FlagValue::Bool(false));
+        assert_eq!(flag.version(), 2);
+
+        schedule(ScheduledOp::SetPercentage { percentage: 25 }).apply_to(&mut flag);
+        assert_eq!(flag.percentage(), 25);
+        assert_eq!(flag.version(), 3);
+    }
+
+    #[test]
+    fn test_only_pending_schedules_are_due() {
+        let mut due = schedule(ScheduledOp::Toggle { enabled: false });
+        assert!(due.is_due(100));
+        assert!(!due.is_due(99));
+        assert_eq!(due.invalid_fields(99), vec![]);
+        assert_eq!(due.invalid_fields(100).len(), 1);
+
+        let over = schedule(ScheduledOp::SetPercentage { percentage: 101 });
+        assert_eq!(over.invalid_fields(99), vec![("op.percentage".to_string(), "must be at most 100")]);
+
+        due.finish(ScheduleStatus::Cancelled, None);
+        assert!(!due.is_due(100));
+    }
+
+    #[test]
+    fn test_claimed_schedules_are_due_once_the_claim_runs_out() {
+        let mut due = schedule(ScheduledOp::Toggle { enabled: false });
+        due.claim("scheduler", 160);
+
+        assert!(due.is_claimed_by("scheduler"));
+        assert!(!due.is_due(100));
+        assert!(due.is_due(160));
+
+        due.finish(ScheduleStatus::Applied, None);
+        assert_eq!(due.claim, None);
+        assert!(!due.is_expired(due.updated + 10, 10));
+        assert!(due.is_expired(due.updated + 11, 10));
+    }
+
+    #[test]
+    fn test_spreads_rollout_steps() {
+        let steps = vec![
+            ScheduledOp::SetValue { value: FlagValue::Bool(false) },
+            ScheduledOp::Toggle { enabled: true },
+        ];
+        let plan = rollout(&FlagPath::new("owner", "app", "env"), "f1", &steps, 100, 1800, "user").unwrap();
+
+        assert_eq!(plan.iter().map(|s| s.at).collect::<Vec<u64>>(), vec![100, 1900]);
+        assert!(plan[0].id < plan[1].id);
+        assert_eq!(plan[1].rollout.as_ref().map(|step| step.step), Some(2));
+        assert!(plan[1].in_plan(&plan[0].rollout.as_ref().unwrap().plan));
+        assert_eq!(plan[1].note(), format!("rollout {} step 2 of 2", plan[1].rollout.as_ref().unwrap().plan));
+
+        assert!(rollout(&FlagPath::new("owner", "app", "env"), "f1", &steps, u64::max_value() - 10, 1800, "user").is_none());
+    }
+}
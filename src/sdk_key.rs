@@ -0,0 +1,202 @@
+use ring::digest;
+#[cfg(feature = "dynamo-backend")]
+use rusoto_dynamodb::AttributeValue;
+use uuid::Uuid;
+
+#[cfg(feature = "dynamo-backend")]
+use std::collections::HashMap;
+
+#[cfg(feature = "dynamo-backend")]
+use error::BannerError;
+use flag::FlagPath;
+#[cfg(feature = "dynamo-backend")]
+use storage::dynamo::{DynamoError, FromAttrMap};
+use util::current_time;
+
+const SERVER_PREFIX: &'static str = "sdk-";
+const CLIENT_PREFIX: &'static str = "client-";
+const HINT_LEN: usize = 4;
+
+// Server keys are secrets and are only accepted in the Authorization header.
+// Client keys are meant to be embedded in browsers and may also be passed in
+// the query string, for instance by EventSource.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SdkKeyKind {
+    Server,
+    Client,
+}
+
+impl SdkKeyKind {
+    fn prefix(&self) -> &'static str {
+        match self {
+            &SdkKeyKind::Server => SERVER_PREFIX,
+            &SdkKeyKind::Client => CLIENT_PREFIX,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SdkKey {
+    pub id: String,
+    pub kind: SdkKeyKind,
+    pub path: FlagPath,
+    pub hash: String,
+    pub hint: String,
+    pub created: u64,
+}
+
+impl SdkKey {
+    // Returns the new key along with its token. Only a digest of the token
+    // is kept, so the token can not be recovered after this call.
+    pub fn generate(kind: SdkKeyKind, path: FlagPath) -> (SdkKey, String) {
+        let token = [
+            kind.prefix(),
+            &Uuid::new_v4().simple().to_string(),
+            &Uuid::new_v4().simple().to_string(),
+        ].concat();
+
+        let key = SdkKey {
+            id: Uuid::new_v4().to_string(),
+            kind: kind,
+            path: path,
+            hash: SdkKey::hash_token(&token),
+            hint: token[token.len() - HINT_LEN..].to_string(),
+            created: current_time(),
+        };
+
+        (key, token)
+    }
+
+    pub fn is_token(token: &str) -> bool {
+        token.starts_with(SERVER_PREFIX) || token.starts_with(CLIENT_PREFIX)
+    }
+
+    pub fn hash_token(token: &str) -> String {
+        digest::digest(&digest::SHA256, token.as_bytes())
+            .as_ref()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    pub fn allows(&self, app: &str, env: &str) -> bool {
+        self.path.app == app && self.path.env == env
+    }
+}
+
+// Backend Impls
+
+redis_json!(SdkKey);
+
+#[cfg(feature = "dynamo-backend")]
+impl Into<HashMap<String, AttributeValue>> for SdkKey {
+    fn into(self) -> HashMap<String, AttributeValue> {
+        let mut id_attr = AttributeValue::default();
+        id_attr.s = Some(self.id);
+
+        let mut kind_attr = AttributeValue::default();
+        kind_attr.s = Some(self.kind.prefix().to_string());
+
+        let mut path_attr = AttributeValue::default();
+        path_attr.s = Some(self.path.path);
+
+        let mut hash_attr = AttributeValue::default();
+        hash_attr.s = Some(self.hash);
+
+        let mut hint_attr = AttributeValue::default();
+        hint_attr.s = Some(self.hint);
+
+        let mut created_attr = AttributeValue::default();
+        created_attr.n = Some(self.created.to_string());
+
+        let mut map = HashMap::new();
+        map.insert("id".into(), id_attr);
+        map.insert("kind".into(), kind_attr);
+        map.insert("path".into(), path_attr);
+        map.insert("hash".into(), hash_attr);
+        map.insert("hint".into(), hint_attr);
+        map.insert("created".into(), created_attr);
+
+        map
+    }
+}
+
+#[cfg(feature = "dynamo-backend")]
+impl FromAttrMap<SdkKey> for SdkKey {
+    type Error = BannerError;
+
+    fn from_attr_map(mut map: HashMap<String, AttributeValue>) -> Result<SdkKey, BannerError> {
+        let id = map.remove("id").and_then(|id_data| id_data.s);
+        let kind = map.remove("kind")
+            .and_then(|kind_data| kind_data.s)
+            .and_then(|kind| match kind.as_str() {
+                SERVER_PREFIX => Some(SdkKeyKind::Server),
+                CLIENT_PREFIX => Some(SdkKeyKind::Client),
+                _ => None,
+            });
+        let path = map.remove("path")
+            .and_then(|path_data| path_data.s)
+            .and_then(|path| path.parse::<FlagPath>().ok());
+        let hash = map.remove("hash").and_then(|hash_data| hash_data.s);
+        let hint = map.remove("hint").and_then(|hint_data| hint_data.s);
+        let created = map.get("created")
+            .and_then(|created_data| match created_data.n {
+                Some(ref created) => created.parse::<u64>().ok(),
+                None => None,
+            });
+
+        if let (Some(i), Some(k), Some(p), Some(h), Some(t), Some(c)) =
+            (id, kind, path, hash, hint, created)
+        {
+            Ok(SdkKey {
+                id: i,
+                kind: k,
+                path: p,
+                hash: h,
+                hint: t,
+                created: c,
+            })
+        } else {
+            Err(DynamoError::FailedToParseResponse.into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path() -> FlagPath {
+        "owner:app:env".parse::<FlagPath>().unwrap()
+    }
+
+    #[test]
+    fn test_generates_prefixed_tokens() {
+        let (server, server_token) = SdkKey::generate(SdkKeyKind::Server, path());
+        let (client, client_token) = SdkKey::generate(SdkKeyKind::Client, path());
+
+        assert!(server_token.starts_with(SERVER_PREFIX));
+        assert!(client_token.starts_with(CLIENT_PREFIX));
+        assert!(SdkKey::is_token(&server_token));
+        assert!(server.id != client.id);
+    }
+
+    #[test]
+    fn test_stores_only_token_digest() {
+        let (key, token) = SdkKey::generate(SdkKeyKind::Server, path());
+
+        assert_eq!(key.hash, SdkKey::hash_token(&token));
+        assert!(key.hash != token);
+        assert!(token.ends_with(key.hint.as_str()));
+    }
+
+    #[test]
+    fn test_allows_only_its_path() {
+        let (key, _) = SdkKey::generate(SdkKeyKind::Client, path());
+
+        assert!(key.allows("app", "env"));
+        assert!(!key.allows("app", "prod"));
+        assert!(!key.allows("other", "env"));
+    }
+}
@@ -0,0 +1,327 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use flag::{Flag, FlagPath, FlagValue, FULL_ROLLOUT};
+use store::Store;
+use util::{current_time, env_or};
+
+const DAY: u64 = 24 * 60 * 60;
+const PATH_KEY: &'static str = "paths";
+
+// Thresholds in days after which a flag is reported
+#[derive(Debug, Clone, PartialEq)]
+pub struct StaleConfig {
+    pub max_age: u64,
+    pub rolled_out: u64,
+    pub unchanged: u64,
+    pub unused: u64,
+}
+
+impl StaleConfig {
+    pub fn from_env() -> StaleConfig {
+        StaleConfig {
+            max_age: env_or("STALE_MAX_AGE_DAYS", 90),
+            rolled_out: env_or("STALE_ROLLED_OUT_DAYS", 14),
+            unchanged: env_or("STALE_UNCHANGED_DAYS", 60),
+            unused: env_or("STALE_UNUSED_DAYS", 30),
+        }
+    }
+
+    // Overrides any threshold given in a query or on the command line
+    pub fn with_overrides(mut self, overrides: &HashMap<String, String>) -> StaleConfig {
+        let days = |name: &str, default: u64| {
+            overrides
+                .get(name)
+                .and_then(|val| val.parse::<u64>().ok())
+                .unwrap_or(default)
+        };
+
+        self.max_age = days("max_age_days", self.max_age);
+        self.rolled_out = days("rolled_out_days", self.rolled_out);
+        self.unchanged = days("unchanged_days", self.unchanged);
+        self.unused = days("unused_days", self.unused);
+
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum StaleReason {
+    Old { days: u64 },
+    RolledOut { days: u64 },
+    Unchanged { days: u64 },
+    Unused { days: Option<u64> },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    // Nothing reads the flag, so it can be deleted right away
+    Delete,
+    // Everyone gets the flag, so the code paths can be cleaned up first
+    RemoveFromCode,
+    // Still in use but old, so the maintainer should decide
+    Review,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StaleFlag {
+    pub key: String,
+    pub name: String,
+    pub maintainer: Option<String>,
+    pub created: u64,
+    pub updated: u64,
+    pub last_evaluated: Option<u64>,
+    pub reasons: Vec<StaleReason>,
+    pub action: Action,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StaleGroup {
+    pub owner: String,
+    pub app: String,
+    pub env: String,
+    pub flags: Vec<StaleFlag>,
+}
+
+// When evaluations started being tracked and when a flag was last
+// evaluated since then
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tracking {
+    pub since: u64,
+    pub last_evaluated: Option<u64>,
+}
+
+// Flags without tracking are never reported as unused
+pub trait Evaluations {
+    fn tracking(&self, path: &FlagPath, key: &str) -> Option<Tracking>;
+}
+
+fn days_since(now: u64, time: u64) -> u64 {
+    now.saturating_sub(time) / DAY
+}
+
+// Checks a single flag. Permanent flags are only reported when unused, as
+// they are expected to stay old and unchanged.
+pub fn check(
+    flag: &Flag,
+    tracking: Option<Tracking>,
+    now: u64,
+    config: &StaleConfig,
+) -> Option<StaleFlag> {
+    let meta = flag.meta();
+    let age = days_since(now, flag.created());
+    let unchanged = days_since(now, flag.updated());
+    let mut reasons = vec![];
+
+    if !meta.permanent {
+        if age >= config.max_age {
+            reasons.push(StaleReason::Old { days: age });
+        }
+
+        let everyone = flag.percentage() == FULL_ROLLOUT;
+
+        if everyone && flag.eval() == Some(&FlagValue::Bool(true)) && unchanged >= config.rolled_out {
+            reasons.push(StaleReason::RolledOut { days: unchanged });
+        }
+
+        if unchanged >= config.unchanged {
+            reasons.push(StaleReason::Unchanged { days: unchanged });
+        }
+    }
+
+    // Flags that were never evaluated are only unused once they and the
+    // tracking have been around long enough for clients to pick them up
+    let last_evaluated = tracking.as_ref().and_then(|tracking| tracking.last_evaluated);
+
+    match tracking {
+        Some(Tracking { last_evaluated: Some(at), .. }) if days_since(now, at) >= config.unused => {
+            reasons.push(StaleReason::Unused { days: Some(days_since(now, at)) });
+        }
+        Some(Tracking { since, last_evaluated: None })
+            if days_since(now, since.max(flag.created())) >= config.unused =>
+        {
+            reasons.push(StaleReason::Unused { days: None });
+        }
+        _ => (),
+    }
+
+    if reasons.is_empty() {
+        return None;
+    }
+
+    let action = if reasons.iter().any(|r| match r {
+        &StaleReason::Unused { .. } => true,
+        _ => false,
+    }) {
+        Action::Delete
+    } else if reasons.iter().any(|r| match r {
+        &StaleReason::RolledOut { .. } => true,
+        _ => false,
+    }) {
+        Action::RemoveFromCode
+    } else {
+        Action::Review
+    };
+
+    Some(StaleFlag {
+        key: flag.key().to_string(),
+        name: meta.name.clone(),
+        maintainer: meta.maintainer.clone(),
+        created: flag.created(),
+        updated: flag.updated(),
+        last_evaluated: last_evaluated,
+        reasons: reasons,
+        action: action,
+    })
+}
+
+pub fn group<E: Evaluations>(
+    path: &FlagPath,
+    flags: &HashMap<String, Flag>,
+    evaluations: &E,
+    now: u64,
+    config: &StaleConfig,
+) -> Option<StaleGroup> {
+    let mut stale = flags
+        .values()
+        .filter_map(|flag| check(flag, evaluations.tracking(path, flag.key()), now, config))
+        .collect::<Vec<StaleFlag>>();
+
+    if stale.is_empty() {
+        return None;
+    }
+
+    stale.sort_by(|a, b| a.key.cmp(&b.key));
+
+    Some(StaleGroup {
+        owner: path.owner.clone(),
+        app: path.app.clone(),
+        env: path.env.clone(),
+        flags: stale,
+    })
+}
+
+// Builds the report for every path straight from the stores, for use from
+// the command line
+pub fn report<F, P, E>(flags: &F, paths: &P, evaluations: &E, config: &StaleConfig) -> Result<Vec<StaleGroup>, F::Error>
+where
+    F: Store<FlagPath, Flag>,
+    P: Store<String, FlagPath, Error = F::Error>,
+    E: Evaluations,
+{
+    let now = current_time();
+    let mut groups = vec![];
+
+    for path in paths.get_all(&PATH_KEY.to_string())?.values() {
+        if let Some(group) = group(path, &flags.get_all(path)?, evaluations, now, config) {
+            groups.push(group);
+        }
+    }
+
+    groups.sort_by(|a, b| (&a.owner, &a.app, &a.env).cmp(&(&b.owner, &b.app, &b.env)));
+
+    Ok(groups)
+}
+
+fn describe(reason: &StaleReason) -> String {
+    match reason {
+        &StaleReason::Old { days } => format!("created {} days ago", days),
+        &StaleReason::RolledOut { days } => format!("fully rolled out for {} days", days),
+        &StaleReason::Unchanged { days } => format!("unchanged for {} days", days),
+        &StaleReason::Unused { days: Some(days) } => format!("not evaluated for {} days", days),
+        &StaleReason::Unused { days: None } => "not evaluated since tracking started".to_string(),
+    }
+}
+
+fn describe_action(action: Action) -> &'static str {
+    match action {
+        Action::Delete => "delete",
+        Action::RemoveFromCode => "remove from code, then delete",
+        Action::Review => "review",
+    }
+}
+
+pub fn render(groups: &[StaleGroup]) -> String {
+    let mut out = String::new();
+
+    if groups.is_empty() {
+        out.push_str("No stale flags\n");
+    }
+
+    for group in groups.iter() {
+        let _ = writeln!(out, "{}/{} (owner {})", group.app, group.env, group.owner);
+
+        for flag in group.flags.iter() {
+            let reasons = flag.reasons.iter().map(describe).collect::<Vec<String>>();
+            let _ = writeln!(
+                out,
+                "  {}: {} ({})",
+                flag.key,
+                describe_action(flag.action),
+                reasons.join(", ")
+            );
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use flag::FlagMeta;
+    use util::cli_options;
+
+    fn config() -> StaleConfig {
+        StaleConfig {
+            max_age: 90,
+            rolled_out: 14,
+            unchanged: 60,
+            unused: 30,
+        }
+    }
+
+    #[test]
+    fn test_reads_overrides_from_args() {
+        let args = vec!["--unused-days".to_string(), "7".to_string(), "--json".to_string()];
+        let config = config().with_overrides(&cli_options(&args));
+
+        assert_eq!(config.unused, 7);
+        assert_eq!(config.max_age, 90);
+    }
+
+    #[test]
+    fn test_ignores_fresh_flags() {
+        let f = Flag::new("f1", FlagValue::Bool(false), 1, true);
+        assert_eq!(check(&f, None, f.created(), &config()), None);
+    }
+
+    #[test]
+    fn test_suggests_removing_rolled_out_flags() {
+        let f = Flag::new("f1", FlagValue::Bool(true), 1, true);
+        let stale = check(&f, None, f.created() + 20 * DAY, &config()).unwrap();
+
+        assert_eq!(stale.reasons, vec![StaleReason::RolledOut { days: 20 }]);
+        assert_eq!(stale.action, Action::RemoveFromCode);
+    }
+
+    #[test]
+    fn test_only_reports_unused_permanent_flags() {
+        let mut f = Flag::new("f1", FlagValue::Bool(true), 1, true);
+        f.set_meta(&FlagMeta {
+            permanent: true,
+            ..FlagMeta::default()
+        });
+        let later = f.created() + 100 * DAY;
+        let tracking = |last| Tracking { since: f.created(), last_evaluated: last };
+
+        assert_eq!(check(&f, Some(tracking(Some(later))), later, &config()), None);
+
+        let stale = check(&f, Some(tracking(None)), later, &config()).unwrap();
+        assert_eq!(stale.reasons, vec![StaleReason::Unused { days: None }]);
+        assert_eq!(stale.action, Action::Delete);
+    }
+}
@@ -3,9 +3,9 @@ use rusoto_core::region::Region;
 use rusoto_core::request::{default_tls_client, DispatchSignedRequest, TlsError};
 use rusoto_credential::{AwsCredentials, BaseAutoRefreshingProvider, ChainProvider,
                         CredentialsError, DefaultCredentialsProviderSync, ProvideAwsCredentials};
-use rusoto_dynamodb::{AttributeValue, DeleteItemError, DeleteItemInput, DynamoDb, DynamoDbClient,
-                      GetItemError, GetItemInput, PutItemError, PutItemInput, QueryError,
-                      QueryInput};
+use rusoto_dynamodb::{AttributeValue, DeleteItemError, DeleteItemInput, DescribeTableError,
+                      DescribeTableInput, DynamoDb, DynamoDbClient, GetItemError, GetItemInput,
+                      PutItemError, PutItemInput, QueryError, QueryInput};
 
 use std::fmt::Debug;
 use std::collections::HashMap;
@@ -13,7 +13,7 @@ use std::time::Duration;
 
 use error::BannerError;
 use hash_cache::HashCache;
-use store::Store;
+use store::{Store, StoreStats};
 
 pub struct DynamoStore<T, P, D>
 where
@@ -29,6 +29,7 @@ where
 pub enum DynamoError {
     Credentials(CredentialsError),
     Delete(DeleteItemError),
+    Describe(DescribeTableError),
     FailedToParseResponse,
     Get(GetItemError),
     Put(PutItemError),
@@ -214,6 +215,24 @@ where
             })
         }))
     }
+
+    fn ping(&self) -> Result<(), BannerError> {
+        let mut describe = DescribeTableInput::default();
+        describe.table_name = self.table.clone();
+
+        self.client
+            .describe_table(&describe)
+            .map(|_| ())
+            .map_err(|err| DynamoError::Describe(err).into())
+    }
+
+    fn stats(&self) -> StoreStats {
+        StoreStats {
+            backend: "dynamo",
+            cached: self.cache.len(),
+            subs: HashMap::new(),
+        }
+    }
 }
 
 #[cfg(test)]
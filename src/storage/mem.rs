@@ -4,14 +4,16 @@ use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
 use std::time::{Duration, Instant};
 
+use change_log::{self, Change, ChangeLog};
 use error::BannerError;
 use hash_cache::HashCache;
-use store::Store;
+use store::{Store, StoreStats};
 
 #[derive(Debug, Clone)]
 pub struct MemStore<T> {
     data: HashCache<T>,
     updated_at: Arc<RwLock<Instant>>,
+    changes: ChangeLog<T>,
     subs: Arc<RwLock<HashMap<String, Vec<(String, Option<Task>)>>>>,
 }
 
@@ -20,6 +22,7 @@ impl<T> MemStore<T> {
         MemStore {
             data: HashCache::new(Duration::new(0, 0)),
             updated_at: Arc::new(RwLock::new(Instant::now())),
+            changes: ChangeLog::new(change_log::DEFAULT_LEN),
             subs: Arc::new(RwLock::new(HashMap::new())),
         }
     }
@@ -86,6 +89,11 @@ where
     fn delete(&self, path: &P, key: &str) -> Result<Option<T>, BannerError> {
         let res = self.data
             .remove([path.as_ref(), "/", key].concat().as_str());
+
+        if res.is_ok() {
+            let _ = self.changes.record(path, key, None);
+        }
+
         self.mark_updated(Instant::now());
         self.notify(path);
 
@@ -95,6 +103,11 @@ where
     fn upsert(&self, path: &P, key: &str, item: &T) -> Result<Option<T>, BannerError> {
         let res = self.data
             .insert([path.as_ref(), "/", key].concat().as_str(), item);
+
+        if res.is_ok() {
+            let _ = self.changes.record(path, key, Some(item));
+        }
+
         self.mark_updated(Instant::now());
         self.notify(path);
 
@@ -105,6 +118,14 @@ where
         self.updated_at.read().map(|val| *val).map_err(|_| BannerError::UpdatedAtPoisoned)
     }
 
+    fn last_change(&self, path: &P) -> Result<u64, BannerError> {
+        self.changes.last_id(path)
+    }
+
+    fn changes_since(&self, path: &P, id: u64) -> Result<Option<Vec<Change<T>>>, BannerError> {
+        self.changes.since(path, id)
+    }
+
     fn sub(&self, id: &str, path: &P, task: Option<Task>) -> bool {
         self.subs.write().map(|mut coll| {
             let subs = coll.entry(path.as_ref().into()).or_insert(vec![]);
@@ -119,6 +140,19 @@ where
             true
         }).unwrap_or(false)
     }
+
+    // Everything is held in memory so the store is always reachable
+    fn ping(&self) -> Result<(), BannerError> {
+        Ok(())
+    }
+
+    fn stats(&self) -> StoreStats {
+        StoreStats {
+            backend: "mem",
+            cached: self.data.len(),
+            subs: self.subs(),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -236,6 +270,19 @@ mod tests {
         assert!(t2 > t1);
     }
 
+    #[test]
+    fn test_records_changes() {
+        let data = dataset();
+        let _ = data.delete(&path(), "f1");
+
+        assert_eq!(data.last_change(&path()).unwrap(), 3);
+
+        let changes = data.changes_since(&path(), 2).unwrap().unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].key, "f1");
+        assert!(changes[0].item.is_none());
+    }
+
     #[test]
     fn test_adds_subs() {
         let data = dataset();
@@ -258,4 +305,17 @@ mod tests {
         
         assert_eq!(data.subs(), m);
     }
+
+    #[test]
+    fn test_reports_stats() {
+        let data = dataset();
+        data.sub("test-uid", &path(), None);
+
+        let stats = Store::<FlagPath, Flag>::stats(&data);
+
+        assert!(Store::<FlagPath, Flag>::ping(&data).is_ok());
+        assert_eq!(stats.backend, "mem");
+        assert_eq!(stats.cached, 2);
+        assert_eq!(stats.subs.get(PATH), Some(&1));
+    }
 }
@@ -0,0 +1,99 @@
+use futures::task::Task;
+
+use std::collections::HashMap;
+use std::time::Instant;
+
+use change_log::Change;
+use metrics;
+use store::{Store, StoreStats};
+
+// Wraps a store to record the latency and failures of every operation,
+// labelled with the name of the store and its backend
+pub struct MeteredStore<S> {
+    name: &'static str,
+    backend: &'static str,
+    inner: S,
+}
+
+impl<S> MeteredStore<S> {
+    pub fn new<P, I>(name: &'static str, inner: S) -> MeteredStore<S>
+    where
+        S: Store<P, I>,
+    {
+        let backend = inner.stats().backend;
+
+        MeteredStore {
+            name: name,
+            backend: backend,
+            inner: inner,
+        }
+    }
+
+    fn record<T, E, F>(&self, op: &'static str, f: F) -> Result<T, E>
+    where
+        F: FnOnce(&S) -> Result<T, E>,
+    {
+        let start = Instant::now();
+        let res = f(&self.inner);
+        let labels = [("store", self.name), ("backend", self.backend), ("op", op)];
+
+        metrics::observe(metrics::STORE_DURATION, &labels, metrics::seconds(start.elapsed()));
+
+        if res.is_err() {
+            metrics::inc(metrics::STORE_ERRORS, &labels);
+        }
+
+        res
+    }
+}
+
+impl<S, P, I> Store<P, I> for MeteredStore<S>
+where
+    S: Store<P, I>,
+{
+    type Error = S::Error;
+
+    fn get(&self, path: &P, key: &str) -> Result<Option<I>, S::Error> {
+        self.record("get", |store| store.get(path, key))
+    }
+
+    fn get_all(&self, path: &P) -> Result<HashMap<String, I>, S::Error> {
+        self.record("get_all", |store| store.get_all(path))
+    }
+
+    fn delete(&self, path: &P, key: &str) -> Result<Option<I>, S::Error> {
+        self.record("delete", |store| store.delete(path, key))
+    }
+
+    fn upsert(&self, path: &P, key: &str, item: &I) -> Result<Option<I>, S::Error> {
+        self.record("upsert", |store| store.upsert(path, key, item))
+    }
+
+    fn updated_at(&self) -> Result<Instant, S::Error> {
+        self.inner.updated_at()
+    }
+
+    fn last_change(&self, path: &P) -> Result<u64, S::Error> {
+        self.inner.last_change(path)
+    }
+
+    fn changes_since(&self, path: &P, id: u64) -> Result<Option<Vec<Change<I>>>, S::Error> {
+        self.record("changes_since", |store| store.changes_since(path, id))
+    }
+
+    fn sub(&self, id: &str, path: &P, task: Option<Task>) -> bool {
+        self.inner.sub(id, path, task)
+    }
+
+    fn unsub(&self, id: &str, path: &P) -> bool {
+        self.inner.unsub(id, path)
+    }
+
+    fn ping(&self) -> Result<(), S::Error> {
+        self.record("ping", |store| store.ping())
+    }
+
+    fn stats(&self) -> StoreStats {
+        self.inner.stats()
+    }
+}
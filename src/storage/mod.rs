@@ -4,6 +4,8 @@ pub mod dynamo;
 #[cfg(feature = "mem-backend")]
 pub mod mem;
 
+pub mod metered;
+
 #[cfg(feature = "mongo-backend")]
 pub mod mongo;
 
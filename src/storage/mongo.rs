@@ -12,7 +12,7 @@ use std::time::Duration;
 
 use error::BannerError;
 use hash_cache::HashCache;
-use store::Store;
+use store::{Store, StoreStats};
 
 #[derive(Debug)]
 pub struct MongoStore<T> {
@@ -232,6 +232,27 @@ where
             .map(|_| existing)
             .map_err(|err| MongoError::Driver(err).into())
     }
+
+    fn ping(&self) -> Result<(), BannerError> {
+        let client = self.pool.pop();
+        let coll = client.get_collection(self.db.as_str(), "banner_items");
+
+        match coll.command(doc! { "ping" => 1 }, None)
+            .map_err(MongoError::Driver)?
+            .next()
+        {
+            Some(res) => res.map(|_| ()).map_err(|err| MongoError::Driver(err).into()),
+            None => Err(MongoError::InvalidMongoConfig.into()),
+        }
+    }
+
+    fn stats(&self) -> StoreStats {
+        StoreStats {
+            backend: "mongo",
+            cached: self.cache.len() + self.all_cache.len(),
+            subs: HashMap::new(),
+        }
+    }
 }
 
 #[cfg(test)]
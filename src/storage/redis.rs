@@ -1,17 +1,29 @@
 use futures::task::Task;
-use redis::{cmd, Client, Commands, Connection, FromRedisValue, RedisResult, ToRedisArgs};
+use redis::{cmd, Client, Commands, Connection, FromRedisValue, RedisResult, Script, ToRedisArgs, Value};
 
 use std::collections::HashMap;
 use std::fmt::Debug;
 use std::sync::{Arc, RwLock};
 use std::time::{Duration, Instant};
 
+use change_log::{self, Change};
 use error::BannerError;
 use hash_cache::HashCache;
-use store::Store;
+use store::{Store, StoreStats};
 
 const FAIL: &'static [u8; 4] = &[102, 97, 105, 108];
 const ALL_CACHE: &'static str = ":all_flags$";
+const CHANGES: &'static str = "$changes";
+const SEQ: &'static str = "$seq";
+
+// Bumps the sequence of a path and adds the change under its id in one step,
+// trimming the log to its length
+const RECORD_CHANGE: &'static str = r"
+local id = redis.call('INCR', KEYS[1])
+redis.call('ZADD', KEYS[2], id, id .. '\n' .. ARGV[1])
+redis.call('ZREMRANGEBYRANK', KEYS[2], 0, -tonumber(ARGV[2]) - 1)
+return id
+";
 
 #[derive(Debug)]
 pub struct RedisStore<T> {
@@ -21,6 +33,7 @@ pub struct RedisStore<T> {
     all_cache: HashCache<HashMap<String, T>>,
     timeout: Duration,
     updated_at: Arc<RwLock<Instant>>,
+    changes_len: usize,
     subs: Arc<RwLock<HashMap<String, Vec<(String, Option<Task>)>>>>,
 }
 
@@ -72,6 +85,7 @@ impl<T: Clone + FromRedisValue + ToRedisArgs> RedisStore<T> {
             all_cache: HashCache::new(dur),
             timeout: dur,
             updated_at: Arc::new(RwLock::new(Instant::now())),
+            changes_len: change_log::DEFAULT_LEN,
             subs: Arc::new(RwLock::new(HashMap::new())),
         }
     }
@@ -98,6 +112,41 @@ impl<T: Clone + FromRedisValue + ToRedisArgs> RedisStore<T> {
         [self.key.as_str(), ":", path.as_ref(), "/", key].concat()
     }
 
+    fn changes_key<P: AsRef<str>>(&self, path: &P, suffix: &str) -> String {
+        [self.full_path(path).as_str(), suffix].concat()
+    }
+
+    // The change log lives in redis rather than in the process so that every
+    // replica hands out the same event ids
+    fn record_change<P: AsRef<str>>(
+        &self,
+        path: &P,
+        key: &str,
+        item: Option<&T>,
+        conn: &Connection,
+    ) -> RedisStoreResult<u64> {
+        let item_ser = match item {
+            Some(item) => {
+                let mut ser = item.to_redis_args();
+
+                if ser[0].as_slice() == FAIL {
+                    return Err(BannerError::FailedToSerializeItem);
+                }
+
+                Some(ser.swap_remove(0))
+            }
+            None => None,
+        };
+
+        Script::new(RECORD_CHANGE)
+            .key(self.changes_key(path, SEQ))
+            .key(self.changes_key(path, CHANGES))
+            .arg(encode_change(key, item_ser.as_ref().map(|ser| ser.as_slice())))
+            .arg(self.changes_len)
+            .invoke(conn)
+            .map_err(BannerError::RedisFailure)
+    }
+
     fn get_raw<P: AsRef<str>>(&self, path: &P, key: &str, conn: &Connection) -> Option<T> {
         conn.hget(self.full_path(path), key.to_string()).ok()
     }
@@ -231,6 +280,12 @@ where
                 .and_then(|_| lookup)
         });
 
+        if res.is_ok() {
+            if let Err(err) = self.record_change(path, key, None, &conn) {
+                error!("Failed to record the change to {}: {}", key, err);
+            }
+        }
+
         self.mark_updated(Instant::now());
         self.notify(path);
 
@@ -254,6 +309,12 @@ where
                 .and_then(|_| lookup)
         });
 
+        if res.is_ok() {
+            if let Err(err) = self.record_change(path, key, Some(item), &conn) {
+                error!("Failed to record the change to {}: {}", key, err);
+            }
+        }
+
         self.mark_updated(Instant::now());
         self.notify(path);
 
@@ -264,6 +325,44 @@ where
         self.updated_at.read().map(|val| *val).map_err(|_| BannerError::UpdatedAtPoisoned)
     }
 
+    fn last_change(&self, path: &P) -> Result<u64, BannerError> {
+        let last: Option<u64> = self.conn()?
+            .get(self.changes_key(path, SEQ))
+            .map_err(BannerError::RedisFailure)?;
+
+        Ok(last.unwrap_or(0))
+    }
+
+    // Ids past the last change (from before the log moved into redis, for
+    // instance) or older than what the log still holds need a full snapshot
+    fn changes_since(&self, path: &P, id: u64) -> Result<Option<Vec<Change<T>>>, BannerError> {
+        let conn = self.conn()?;
+        let last: Option<u64> = conn.get(self.changes_key(path, SEQ)).map_err(BannerError::RedisFailure)?;
+        let last = last.unwrap_or(0);
+
+        if id > last {
+            return Ok(None);
+        } else if id == last {
+            return Ok(Some(vec![]));
+        }
+
+        let members: Vec<Vec<u8>> = cmd("ZRANGEBYSCORE")
+            .arg(self.changes_key(path, CHANGES))
+            .arg(format!("({}", id))
+            .arg("+inf")
+            .query(&conn)
+            .map_err(BannerError::RedisFailure)?;
+        let changes = members
+            .iter()
+            .filter_map(|member| decode_change(member))
+            .collect::<Vec<Change<T>>>();
+
+        match changes.first() {
+            Some(first) if first.id == id + 1 => Ok(Some(changes)),
+            _ => Ok(None),
+        }
+    }
+
     fn sub(&self, id: &str, path: &P, task: Option<Task>) -> bool {
         self.subs.write().map(|mut coll| {
             let subs = coll.entry(path.as_ref().into()).or_insert(vec![]);
@@ -278,6 +377,57 @@ where
             true
         }).unwrap_or(false)
     }
+
+    fn ping(&self) -> Result<(), BannerError> {
+        let res: RedisResult<String> = cmd("PING").query(&self.conn()?);
+        res.map(|_| ()).map_err(BannerError::RedisFailure)
+    }
+
+    fn stats(&self) -> StoreStats {
+        StoreStats {
+            backend: "redis",
+            cached: self.cache.len() + self.all_cache.len(),
+            subs: self.subs(),
+        }
+    }
+}
+
+// Changes are written as the key and, for anything but a delete, the item.
+// The script puts the id in front.
+fn encode_change(key: &str, item: Option<&[u8]>) -> Vec<u8> {
+    let mut data = key.as_bytes().to_vec();
+
+    match item {
+        Some(item) => {
+            data.extend_from_slice(b"\n+");
+            data.extend_from_slice(item);
+        }
+        None => data.extend_from_slice(b"\n-"),
+    }
+
+    data
+}
+
+fn decode_change<T: FromRedisValue>(data: &[u8]) -> Option<Change<T>> {
+    let mut parts = data.splitn(3, |b| *b == b'\n');
+    let id = parts
+        .next()
+        .and_then(|id| ::std::str::from_utf8(id).ok())
+        .and_then(|id| id.parse::<u64>().ok())?;
+    let key = parts.next().and_then(|key| ::std::str::from_utf8(key).ok())?;
+    let rest = parts.next()?;
+
+    let item = match rest.split_first() {
+        Some((&b'+', item)) => Some(T::from_redis_value(&Value::Data(item.to_vec())).ok()?),
+        Some((&b'-', _)) => None,
+        _ => return None,
+    };
+
+    Some(Change {
+        id: id,
+        key: key.to_string(),
+        item: item,
+    })
 }
 
 #[cfg(test)]
@@ -435,6 +585,35 @@ mod tests {
         assert!(t2 > t1);
     }
 
+    #[test]
+    fn test_encodes_changes() {
+        let flag = f("f1", true);
+        let mut data = b"7\n".to_vec();
+        data.extend(encode_change("f1", Some(flag.to_redis_args()[0].as_slice())));
+
+        let change: Change<Flag> = decode_change(&data).unwrap();
+        assert_eq!(change, Change { id: 7, key: "f1".to_string(), item: Some(flag) });
+
+        let deleted: Change<Flag> = decode_change(b"8\nf1\n-").unwrap();
+        assert_eq!(deleted.item, None);
+        assert!(decode_change::<Flag>(b"f1\n-").is_none());
+    }
+
+    #[test]
+    fn test_resumes_from_changes_kept_in_redis() {
+        let data = dataset("changes", 0);
+        let last = data.last_change(&path()).unwrap();
+        let _ = data.upsert(&path(), "f1", &f("f1", true));
+        let _ = data.delete(&path(), "f2");
+
+        let changes = data.changes_since(&path(), last).unwrap().unwrap();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[1].key, "f2");
+        assert_eq!(changes[1].item, None);
+        assert_eq!(data.changes_since(&path(), last + 2).unwrap(), Some(vec![]));
+        assert_eq!(data.changes_since(&path(), last + 3).unwrap(), None);
+    }
+
     #[test]
     fn test_adds_subs() {
         let data = dataset("replace_no_cache", 0);
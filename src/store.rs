@@ -3,6 +3,16 @@ use futures::task::Task;
 use std::collections::HashMap;
 use std::time::Instant;
 
+use change_log::Change;
+
+// A snapshot of a store for the status endpoint
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StoreStats {
+    pub backend: &'static str,
+    pub cached: usize,
+    pub subs: HashMap<String, usize>,
+}
+
 pub trait Store<Path, Item> {
     type Error;
 
@@ -11,8 +21,12 @@ pub trait Store<Path, Item> {
     fn delete(&self, path: &Path, key: &str) -> Result<Option<Item>, Self::Error>;
     fn upsert(&self, path: &Path, key: &str, item: &Item) -> Result<Option<Item>, Self::Error>;
     fn updated_at(&self) -> Result<Instant, Self::Error>;
+    fn last_change(&self, path: &Path) -> Result<u64, Self::Error>;
+    fn changes_since(&self, path: &Path, id: u64) -> Result<Option<Vec<Change<Item>>>, Self::Error>;
     fn sub(&self, id: &str, path: &Path, task: Option<Task>) -> bool;
     fn unsub(&self, id: &str, path: &Path) -> bool;
+    fn ping(&self) -> Result<(), Self::Error>;
+    fn stats(&self) -> StoreStats;
 }
 
 pub trait ThreadedStore<P, I>: Store<P, I> + Send + Sync {}
@@ -0,0 +1,150 @@
+#[cfg(feature = "dynamo-backend")]
+use rusoto_dynamodb::AttributeValue;
+#[cfg(feature = "dynamo-backend")]
+use serde_json;
+use uuid::Uuid;
+
+#[cfg(feature = "dynamo-backend")]
+use std::collections::HashMap;
+
+#[cfg(feature = "dynamo-backend")]
+use error::BannerError;
+use grant::Role;
+#[cfg(feature = "dynamo-backend")]
+use storage::dynamo::{DynamoError, FromAttrMap};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TeamMember {
+    pub user: String,
+    pub role: Role,
+}
+
+// Teams own apps in place of a single user. A member's role applies to every
+// app of the team, further grants can still be given per app or environment.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Team {
+    pub id: String,
+    pub name: String,
+    pub members: Vec<TeamMember>,
+}
+
+impl Team {
+    pub fn new<S: Into<String>>(name: S, creator: &str) -> Team {
+        Team {
+            id: Uuid::new_v4().to_string(),
+            name: name.into(),
+            members: vec![
+                TeamMember {
+                    user: creator.to_string(),
+                    role: Role::Owner,
+                },
+            ],
+        }
+    }
+
+    pub fn role_of(&self, user: &str) -> Option<Role> {
+        self.members
+            .iter()
+            .find(|member| member.user == user)
+            .map(|member| member.role)
+    }
+
+    pub fn set_member(&mut self, user: &str, role: Role) {
+        match self.members.iter_mut().find(|member| member.user == user) {
+            Some(member) => member.role = role,
+            None => self.members.push(TeamMember {
+                user: user.to_string(),
+                role: role,
+            }),
+        }
+    }
+
+    pub fn remove_member(&mut self, user: &str) -> Option<TeamMember> {
+        let pos = self.members.iter().position(|member| member.user == user)?;
+        Some(self.members.remove(pos))
+    }
+
+    // A team must always be left with someone who can manage it
+    pub fn has_owner(&self) -> bool {
+        self.members.iter().any(|member| member.role == Role::Owner)
+    }
+}
+
+// Backend Impls
+
+redis_json!(Team);
+
+#[cfg(feature = "dynamo-backend")]
+impl Into<HashMap<String, AttributeValue>> for Team {
+    fn into(self) -> HashMap<String, AttributeValue> {
+        let mut id_attr = AttributeValue::default();
+        id_attr.s = Some(self.id);
+
+        let mut name_attr = AttributeValue::default();
+        name_attr.s = Some(self.name);
+
+        // Members are kept as a single json document as they are always read
+        // and written together with the team
+        let mut members_attr = AttributeValue::default();
+        members_attr.s = serde_json::to_string(&self.members).ok();
+
+        let mut map = HashMap::new();
+        map.insert("id".into(), id_attr);
+        map.insert("name".into(), name_attr);
+        map.insert("members".into(), members_attr);
+
+        map
+    }
+}
+
+#[cfg(feature = "dynamo-backend")]
+impl FromAttrMap<Team> for Team {
+    type Error = BannerError;
+
+    fn from_attr_map(mut map: HashMap<String, AttributeValue>) -> Result<Team, BannerError> {
+        let id = map.remove("id").and_then(|id_data| id_data.s);
+        let name = map.remove("name").and_then(|name_data| name_data.s);
+        let members = map.remove("members")
+            .and_then(|members_data| members_data.s)
+            .and_then(|members| serde_json::from_str::<Vec<TeamMember>>(&members).ok());
+
+        if let (Some(i), Some(n), Some(m)) = (id, name, members) {
+            Ok(Team {
+                id: i,
+                name: n,
+                members: m,
+            })
+        } else {
+            Err(DynamoError::FailedToParseResponse.into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_creator_owns_new_teams() {
+        let team = Team::new("team", "creator");
+
+        assert_eq!(team.role_of("creator"), Some(Role::Owner));
+        assert_eq!(team.role_of("other"), None);
+        assert!(team.has_owner());
+    }
+
+    #[test]
+    fn test_manages_members() {
+        let mut team = Team::new("team", "creator");
+        team.set_member("dev", Role::Viewer);
+        team.set_member("dev", Role::Editor);
+
+        assert_eq!(team.members.len(), 2);
+        assert_eq!(team.role_of("dev"), Some(Role::Editor));
+
+        team.remove_member("creator");
+
+        assert_eq!(team.role_of("creator"), None);
+        assert!(!team.has_owner());
+    }
+}
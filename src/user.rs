@@ -1,20 +1,31 @@
-#[cfg(feature = "redis-backend")]
-use redis::{ErrorKind, FromRedisValue, RedisResult, ToRedisArgs, Value as RedisValue};
+use base64::{encode_config, URL_SAFE_NO_PAD};
+#[cfg(feature = "mongo-backend")]
+use bson;
 use ring::{digest, pbkdf2};
+use ring::rand::{SecureRandom, SystemRandom};
 #[cfg(feature = "dynamo-backend")]
 use rusoto_dynamodb::AttributeValue;
-#[cfg(feature = "redis-backend")]
-use serde_json;
 
 #[cfg(feature = "dynamo-backend")]
 use std::collections::HashMap;
+use std::env;
 
+#[cfg(feature = "dynamo-backend")]
+use error::BannerError;
 #[cfg(feature = "dynamo-backend")]
 use storage::dynamo::{DynamoError, FromAttrMap};
+use util::current_millis;
 
 static DIGEST_ALG: &'static digest::Algorithm = &digest::SHA256;
 const CREDENTIAL_LEN: usize = digest::SHA256_OUTPUT_LEN;
-const ITERATIONS: u32 = 5;
+const SALT_LEN: usize = 16;
+const SECRET_LEN: usize = 24;
+const DEFAULT_ITERATIONS: u32 = 100_000;
+const ITERATIONS_VAR: &'static str = "PASSWORD_HASH_ITERATIONS";
+
+// Users created before per-user salts were introduced were hashed with this
+// number of iterations and a salt derived from SALT and their key
+const LEGACY_ITERATIONS: u32 = 5;
 const SALT: [u8; 16] = [
     // This value was generated from a secure PRNG.
     0xd6, 0x26, 0x98, 0xda, 0xf4, 0xdc, 0x50, 0x52,
@@ -23,40 +34,117 @@ const SALT: [u8; 16] = [
 
 pub type Credential = [u8; CREDENTIAL_LEN];
 
+fn legacy_iterations() -> u32 {
+    LEGACY_ITERATIONS
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
     pub uuid: String,
     pub key: String,
     pub hash: Credential,
+    #[serde(default)]
+    salt: Vec<u8>,
+    #[serde(default = "legacy_iterations")]
+    iterations: u32,
     is_admin: bool,
+    #[serde(default)]
+    disabled: bool,
+    // Sessions issued before this time, in milliseconds, are no longer
+    // accepted
+    #[serde(default)]
+    #[cfg_attr(feature = "mongo-backend", serde(with = "bson::compat::u2f"))]
+    sessions_since: u64,
 }
 
 impl User {
     pub fn new(uuid: String, key: String, secret: String, is_admin: bool) -> User {
-        let hash = User::generate_hash(key.as_str(), secret.as_str());
+        let mut user = User {
+            uuid: uuid,
+            key: key,
+            hash: [0u8; CREDENTIAL_LEN],
+            salt: vec![],
+            iterations: User::iterations(),
+            is_admin: is_admin,
+            disabled: false,
+            sessions_since: 0,
+        };
+
+        user.set_secret(secret.as_str());
+        user
+    }
 
+    // Builds a user without credentials from the identity a session carries
+    pub fn from_session(uuid: String, key: String, is_admin: bool) -> User {
         User {
             uuid: uuid,
             key: key,
-            hash: hash,
+            hash: [0u8; CREDENTIAL_LEN],
+            salt: vec![],
+            iterations: LEGACY_ITERATIONS,
             is_admin: is_admin,
+            disabled: false,
+            sessions_since: 0,
         }
     }
 
+    // Generates a random secret for accounts created or reset by an admin
+    pub fn generate_secret() -> String {
+        let mut secret = vec![0u8; SECRET_LEN];
+        SystemRandom::new()
+            .fill(&mut secret)
+            .expect("Failed to generate a random secret");
+
+        encode_config(&secret, URL_SAFE_NO_PAD)
+    }
+
     pub fn is_admin(&self) -> bool {
         self.is_admin
     }
 
+    pub fn set_admin(&mut self, is_admin: bool) {
+        self.is_admin = is_admin;
+    }
+
+    // Disabled users keep their record but can no longer authenticate
+    pub fn is_disabled(&self) -> bool {
+        self.disabled
+    }
+
+    pub fn set_disabled(&mut self, disabled: bool) {
+        self.disabled = disabled;
+    }
+
+    // Ends every session issued to the user so far, returning from when on
+    // sessions are accepted again
+    pub fn end_sessions(&mut self) -> u64 {
+        self.sessions_since = current_millis();
+        self.sessions_since
+    }
+
+    pub fn accepts_session(&self, issued: u64) -> bool {
+        issued >= self.sessions_since
+    }
+
+    // The iteration count can be raised over time, existing users are
+    // rehashed with the new count on their next successful login
+    pub fn iterations() -> u32 {
+        env::var(ITERATIONS_VAR)
+            .ok()
+            .and_then(|val| val.parse::<u32>().ok())
+            .filter(|iterations| *iterations > 0)
+            .unwrap_or(DEFAULT_ITERATIONS)
+    }
+
     // Example implementation from ring library: https://briansmith.org/rustdoc/ring/pbkdf2/
 
-    pub fn generate_hash(key: &str, secret: &str) -> Credential {
-        let salt = User::salt(key);
+    pub fn generate_hash(salt: &[u8], iterations: u32, secret: &str) -> Credential {
         let mut to_store: Credential = [0u8; CREDENTIAL_LEN];
 
         pbkdf2::derive(
             DIGEST_ALG,
-            ITERATIONS,
-            &salt,
+            iterations,
+            salt,
             secret.as_bytes(),
             &mut to_store,
         );
@@ -64,18 +152,40 @@ impl User {
         to_store
     }
 
+    pub fn set_secret(&mut self, secret: &str) {
+        let mut salt = vec![0u8; SALT_LEN];
+        SystemRandom::new()
+            .fill(&mut salt)
+            .expect("Failed to generate a random salt");
+
+        self.iterations = User::iterations();
+        self.hash = User::generate_hash(&salt, self.iterations, secret);
+        self.salt = salt;
+    }
+
     pub fn verify_secret(&self, secret: &str) -> bool {
-        let salt = User::salt(&self.key);
-        pbkdf2::verify(DIGEST_ALG, ITERATIONS, &salt, secret.as_bytes(), &self.hash).is_ok()
+        let salt = if self.is_legacy() {
+            User::legacy_salt(&self.key)
+        } else {
+            self.salt.clone()
+        };
+
+        pbkdf2::verify(DIGEST_ALG, self.iterations, &salt, secret.as_bytes(), &self.hash).is_ok()
+    }
+
+    pub fn needs_rehash(&self) -> bool {
+        self.is_legacy() || self.iterations < User::iterations()
     }
 
-    // The salt should have a user-specific component so that an attacker
-    // cannot crack one password for multiple users in the database. It
-    // should have a database-unique component so that an attacker cannot
-    // crack the same user's password across databases in the unfortunate
-    // but common case that the user has used the same password for
-    // multiple systems.
-    fn salt(key: &str) -> Vec<u8> {
+    fn is_legacy(&self) -> bool {
+        self.salt.is_empty()
+    }
+
+    // The legacy salt has a user-specific component so that an attacker
+    // cannot crack one password for multiple users in the database, and a
+    // database-unique component. It is only used to verify hashes created
+    // before random per-user salts were stored.
+    fn legacy_salt(key: &str) -> Vec<u8> {
         let mut salt = Vec::with_capacity(SALT.len() + key.as_bytes().len());
         salt.extend(SALT.as_ref());
         salt.extend(key.as_bytes());
@@ -83,49 +193,7 @@ impl User {
     }
 }
 
-#[cfg(feature = "redis-backend")]
-impl FromRedisValue for User {
-    fn from_redis_value(v: &RedisValue) -> RedisResult<User> {
-        match *v {
-            RedisValue::Data(ref data) => {
-                let data = String::from_utf8(data.clone());
-
-                data.or_else(|_| Err((ErrorKind::TypeError, "Expected utf8 string").into()))
-                    .and_then(|ser| {
-                        serde_json::from_str(ser.as_str()).or_else(|_| {
-                            let err = (ErrorKind::TypeError, "Unable to deserialize json to User");
-                            Err(err.into())
-                        })
-                    })
-            }
-            _ => {
-                let err = (
-                    ErrorKind::TypeError,
-                    "Recieved non-data type for deserializing",
-                );
-                Err(err.into())
-            }
-        }
-    }
-}
-
-#[cfg(feature = "redis-backend")]
-impl<'a> ToRedisArgs for User {
-    fn write_redis_args(&self, out: &mut Vec<Vec<u8>>) {
-        let ser = serde_json::to_string(&self);
-
-        out.push(
-            match ser {
-                Ok(json) => json.as_bytes().into(),
-
-                // Because this trait can not normally fail, but json serialization
-                // can fail, the failure cause is encoded as a special value that
-                // is checked by the store
-                Err(_) => "fail".to_string().as_bytes().into(),
-            },
-        )
-    }
-}
+redis_json!(User);
 
 #[cfg(feature = "dynamo-backend")]
 impl Into<HashMap<String, AttributeValue>> for User {
@@ -137,16 +205,32 @@ impl Into<HashMap<String, AttributeValue>> for User {
         key_attr.s = Some(self.key);
 
         let mut hash_attr = AttributeValue::default();
-        hash_attr.s = Some(self.hash);
+        hash_attr.b = Some(self.hash.to_vec());
+
+        let mut salt_attr = AttributeValue::default();
+        salt_attr.b = Some(self.salt);
+
+        let mut iterations_attr = AttributeValue::default();
+        iterations_attr.n = Some(self.iterations.to_string());
 
         let mut is_admin_attr = AttributeValue::default();
         is_admin_attr.bool = Some(self.is_admin);
 
+        let mut disabled_attr = AttributeValue::default();
+        disabled_attr.bool = Some(self.disabled);
+
+        let mut sessions_since_attr = AttributeValue::default();
+        sessions_since_attr.n = Some(self.sessions_since.to_string());
+
         let mut map = HashMap::new();
         map.insert("uuid".into(), uuid_attr);
         map.insert("key".into(), key_attr);
         map.insert("hash".into(), hash_attr);
+        map.insert("salt".into(), salt_attr);
+        map.insert("iterations".into(), iterations_attr);
         map.insert("is_admin".into(), is_admin_attr);
+        map.insert("disabled".into(), disabled_attr);
+        map.insert("sessions_since".into(), sessions_since_attr);
 
         map
     }
@@ -159,14 +243,123 @@ impl FromAttrMap<User> for User {
     fn from_attr_map(mut map: HashMap<String, AttributeValue>) -> Result<User, BannerError> {
         let uuid = map.remove("uuid").and_then(|uuid_data| uuid_data.s);
         let key = map.remove("key").and_then(|key_data| key_data.s);
-        let hash = map.remove("hash").and_then(|hash_data| hash_data.s);
+        let hash = map.remove("hash")
+            .and_then(|hash_data| hash_data.b)
+            .and_then(|hash_data| {
+                if hash_data.len() == CREDENTIAL_LEN {
+                    let mut hash: Credential = [0u8; CREDENTIAL_LEN];
+                    hash.copy_from_slice(&hash_data);
+                    Some(hash)
+                } else {
+                    None
+                }
+            });
+        let salt = map.remove("salt")
+            .and_then(|salt_data| salt_data.b)
+            .unwrap_or(vec![]);
+        let iterations = map.get("iterations")
+            .and_then(|iterations_data| match iterations_data.n {
+                Some(ref iterations) => iterations.parse::<u32>().ok(),
+                None => None,
+            })
+            .unwrap_or(LEGACY_ITERATIONS);
         let is_admin = map.remove("is_admin")
             .and_then(|is_admin_data| is_admin_data.bool);
+        let disabled = map.remove("disabled")
+            .and_then(|disabled_data| disabled_data.bool)
+            .unwrap_or(false);
+        let sessions_since = map.get("sessions_since")
+            .and_then(|sessions_since_data| match sessions_since_data.n {
+                Some(ref sessions_since) => sessions_since.parse::<u64>().ok(),
+                None => None,
+            })
+            .unwrap_or(0);
 
-        if let (Some(u), Some(k), Some(s), Some(a)) = (uuid, key, hash, is_admin) {
-            Ok(User::new(u, k, s, a))
+        if let (Some(u), Some(k), Some(h), Some(a)) = (uuid, key, hash, is_admin) {
+            Ok(User {
+                uuid: u,
+                key: k,
+                hash: h,
+                salt: salt,
+                iterations: iterations,
+                is_admin: a,
+                disabled: disabled,
+                sessions_since: sessions_since,
+            })
         } else {
             Err(DynamoError::FailedToParseResponse.into())
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn legacy_user(key: &str, secret: &str) -> User {
+        User {
+            uuid: "user-id".to_string(),
+            key: key.to_string(),
+            hash: User::generate_hash(&User::legacy_salt(key), LEGACY_ITERATIONS, secret),
+            salt: vec![],
+            iterations: LEGACY_ITERATIONS,
+            is_admin: false,
+            disabled: false,
+            sessions_since: 0,
+        }
+    }
+
+    #[test]
+    fn test_verifies_secret() {
+        let u = User::new("user-id".into(), "key".into(), "secret".into(), false);
+
+        assert!(u.verify_secret("secret"));
+        assert!(!u.verify_secret("other"));
+        assert!(!u.needs_rehash());
+    }
+
+    #[test]
+    fn test_salts_are_unique_per_user() {
+        let u1 = User::new("user-1".into(), "key".into(), "secret".into(), false);
+        let u2 = User::new("user-2".into(), "key".into(), "secret".into(), false);
+
+        assert!(u1.salt != u2.salt);
+        assert!(u1.hash != u2.hash);
+    }
+
+    #[test]
+    fn test_verifies_and_rehashes_legacy_hashes() {
+        let mut u = legacy_user("key", "secret");
+
+        assert!(u.verify_secret("secret"));
+        assert!(u.needs_rehash());
+
+        u.set_secret("secret");
+
+        assert!(u.verify_secret("secret"));
+        assert!(!u.needs_rehash());
+        assert_eq!(u.salt.len(), SALT_LEN);
+    }
+
+    #[test]
+    fn test_reads_legacy_records() {
+        let legacy = legacy_user("key", "secret");
+        let mut json: ::serde_json::Value = ::serde_json::to_value(&legacy).unwrap();
+        json.as_object_mut().unwrap().remove("salt");
+        json.as_object_mut().unwrap().remove("iterations");
+
+        let u: User = ::serde_json::from_value(json).unwrap();
+
+        assert!(u.verify_secret("secret"));
+        assert!(u.needs_rehash());
+    }
+
+    #[test]
+    fn test_generates_distinct_secrets() {
+        let s1 = User::generate_secret();
+        let s2 = User::generate_secret();
+
+        assert!(s1 != s2);
+        assert_eq!(s1.len(), 32);
+    }
+}
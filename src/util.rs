@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+use std::env;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Seconds since the epoch, which is how every stored time is kept
+pub fn current_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::from_secs(0))
+        .as_secs()
+}
+
+// Milliseconds since the epoch, for times that have to be told apart within
+// the same second
+pub fn current_millis() -> u64 {
+    let since = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::from_secs(0));
+
+    since.as_secs() * 1000 + u64::from(since.subsec_nanos() / 1_000_000)
+}
+
+// Reads a setting from the environment, falling back to the default when
+// it is unset or does not parse
+pub fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    env::var(name)
+        .ok()
+        .and_then(|val| val.parse::<T>().ok())
+        .unwrap_or(default)
+}
+
+// Reads options given as `--max-age-days 90` on the command line, keyed by
+// their name with underscores. Switches without a value are left out.
+pub fn cli_options(args: &[String]) -> HashMap<String, String> {
+    args.iter()
+        .zip(args.iter().skip(1))
+        .filter(|&(name, value)| name.starts_with("--") && !value.starts_with("--"))
+        .map(|(name, value)| (name[2..].replace("-", "_"), value.clone()))
+        .collect()
+}
+
+// Stores a type in redis as its json serialization. Serializing for redis can
+// not fail, so a failure is written as a special value that the store checks.
+macro_rules! redis_json {
+    ($t:ident) => {
+        #[cfg(feature = "redis-backend")]
+        impl ::redis::FromRedisValue for $t {
+            fn from_redis_value(v: &::redis::Value) -> ::redis::RedisResult<$t> {
+                match *v {
+                    ::redis::Value::Data(ref data) => ::std::str::from_utf8(data)
+                        .map_err(|_| ::redis::RedisError::from((::redis::ErrorKind::TypeError, "Expected utf8 string")))
+                        .and_then(|ser| {
+                            ::serde_json::from_str(ser).map_err(|_| {
+                                let reason = concat!("Unable to deserialize json to ", stringify!($t));
+                                ::redis::RedisError::from((::redis::ErrorKind::TypeError, reason))
+                            })
+                        }),
+                    _ => Err(::redis::RedisError::from((
+                        ::redis::ErrorKind::TypeError,
+                        "Recieved non-data type for deserializing",
+                    ))),
+                }
+            }
+        }
+
+        #[cfg(feature = "redis-backend")]
+        impl ::redis::ToRedisArgs for $t {
+            fn write_redis_args(&self, out: &mut Vec<Vec<u8>>) {
+                out.push(match ::serde_json::to_string(self) {
+                    Ok(json) => json.into_bytes(),
+                    Err(_) => b"fail".to_vec(),
+                })
+            }
+        }
+    };
+}